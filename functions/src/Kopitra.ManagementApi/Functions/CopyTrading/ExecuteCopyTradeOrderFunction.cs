@@ -180,6 +180,18 @@ public sealed class ExecuteCopyTradeOrderFunction
         {
             payload["positionId"] = request.PositionId;
         }
+        if (!string.IsNullOrWhiteSpace(request.OrderId))
+        {
+            payload["orderId"] = request.OrderId;
+        }
+        if (!string.IsNullOrWhiteSpace(request.OriginalCommandId))
+        {
+            payload["originalCommandId"] = request.OriginalCommandId;
+        }
+        if (!string.IsNullOrWhiteSpace(request.OriginalClientOrderId))
+        {
+            payload["originalClientOrderId"] = request.OriginalClientOrderId;
+        }
         if (!string.IsNullOrWhiteSpace(request.ClientOrderId))
         {
             payload["clientOrderId"] = request.ClientOrderId;
@@ -217,6 +229,9 @@ public sealed class ExecuteCopyTradeOrderFunction
         double? TakeProfit,
         string? TimeInForce,
         string? PositionId,
+        string? OrderId,
+        string? OriginalCommandId,
+        string? OriginalClientOrderId,
         string? ClientOrderId,
         string? InitiatedBy,
         IDictionary<string, object>? Metadata);
@@ -109,6 +109,9 @@ public sealed class EnqueueExpertAdvisorTradeOrderFunction
         double? TakeProfit,
         string? TimeInForce,
         string? PositionId,
+        string? OrderId,
+        string? OriginalCommandId,
+        string? OriginalClientOrderId,
         string? ClientOrderId,
         IDictionary<string, object>? Metadata);
 
@@ -153,6 +156,18 @@ public sealed class EnqueueExpertAdvisorTradeOrderFunction
         {
             payload["positionId"] = request.PositionId;
         }
+        if (!string.IsNullOrWhiteSpace(request.OrderId))
+        {
+            payload["orderId"] = request.OrderId;
+        }
+        if (!string.IsNullOrWhiteSpace(request.OriginalCommandId))
+        {
+            payload["originalCommandId"] = request.OriginalCommandId;
+        }
+        if (!string.IsNullOrWhiteSpace(request.OriginalClientOrderId))
+        {
+            payload["originalClientOrderId"] = request.OriginalClientOrderId;
+        }
         if (!string.IsNullOrWhiteSpace(request.ClientOrderId))
         {
             payload["clientOrderId"] = request.ClientOrderId;
@@ -0,0 +1,114 @@
+//! Measures outbox polling throughput while many follower EAs hit the gateway
+//! at once. With per-account locking, spreading the same number of requests
+//! over more accounts should scale with the available worker threads instead
+//! of serializing on a single lock.
+
+use axum::{
+    body::Body,
+    http::{self, header, Request, StatusCode},
+    Router,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use gateway::{router, AppState};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use tokio::runtime::Runtime;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+const REQUESTS_PER_ITERATION: usize = 512;
+const ACCOUNT_COUNTS: [usize; 4] = [1, 16, 128, 512];
+
+struct Follower {
+    account: String,
+    token: String,
+}
+
+async fn open_session(app: &Router, account: &str) -> Follower {
+    let request = Request::builder()
+        .method(http::Method::POST)
+        .uri("/trade-agent/v1/sessions")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("X-TradeAgent-Account", account)
+        .header("Idempotency-Key", Uuid::new_v4().to_string())
+        .body(Body::from(
+            json!({ "authenticationKey": format!("{account}-secret") }).to_string(),
+        ))
+        .expect("failed to build create session request");
+
+    let response = app.clone().oneshot(request).await.expect("router error");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("body collection failed")
+        .to_bytes();
+    let created: Value = serde_json::from_slice(&bytes).expect("invalid session response");
+
+    Follower {
+        account: account.to_string(),
+        token: created["sessionToken"]
+            .as_str()
+            .expect("missing session token")
+            .to_string(),
+    }
+}
+
+async fn poll_outbox(app: Router, account: String, token: String) {
+    let request = Request::builder()
+        .method(http::Method::GET)
+        .uri("/trade-agent/v1/sessions/current/outbox")
+        .header("X-TradeAgent-Account", account)
+        .header(header::AUTHORIZATION, format!("Bearer {token}"))
+        .body(Body::empty())
+        .expect("failed to build outbox request");
+
+    let response = app.oneshot(request).await.expect("router error");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+fn outbox_polling(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("failed to build tokio runtime");
+    let mut group = c.benchmark_group("outbox_polling");
+    group.throughput(Throughput::Elements(REQUESTS_PER_ITERATION as u64));
+
+    for accounts in ACCOUNT_COUNTS {
+        let app = router(AppState::default());
+        let followers: Vec<Follower> = runtime.block_on(async {
+            let mut followers = Vec::with_capacity(accounts);
+            for index in 0..accounts {
+                followers.push(open_session(&app, &format!("acct-bench-{index}")).await);
+            }
+            followers
+        });
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(accounts),
+            &followers,
+            |b, followers| {
+                b.to_async(&runtime).iter(|| async {
+                    let handles: Vec<_> = (0..REQUESTS_PER_ITERATION)
+                        .map(|index| {
+                            let follower = &followers[index % followers.len()];
+                            tokio::spawn(poll_outbox(
+                                app.clone(),
+                                follower.account.clone(),
+                                follower.token.clone(),
+                            ))
+                        })
+                        .collect();
+
+                    for handle in handles {
+                        handle.await.expect("poll task panicked");
+                    }
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, outbox_polling);
+criterion_main!(benches);
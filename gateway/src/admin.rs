@@ -13,7 +13,8 @@ use uuid::Uuid;
 
 use crate::{
     AdminApprovalCommand, AdminCommand, AdminCommandError, AdminCommandOutcome,
-    AdminRejectionCommand, ApiError, AppState, OutboxEventRequest, TradeOrderRequest,
+    AdminRejectionCommand, ApiError, AppState, OutboxEventRequest, ResyncRequest,
+    TradeOrderRequest,
 };
 
 const NAMESPACE_ENV: &str = "EA_SERVICE_BUS_NAMESPACE";
@@ -137,6 +138,7 @@ enum ServiceBusBackend {
         client: HttpClient,
         queue_name: String,
         dequeue_url: Url,
+        enqueue_url: Url,
     },
 }
 
@@ -157,12 +159,16 @@ impl ServiceBusWorker {
             let dequeue_url = base
                 .join(&format!("queues/{}/dequeue", config.queue))
                 .map_err(ServiceBusWorkerInitError::InvalidEmulatorUrl)?;
+            let enqueue_url = base
+                .join(&format!("queues/{}/messages", config.queue))
+                .map_err(ServiceBusWorkerInitError::InvalidEmulatorUrl)?;
 
             return Ok(Self {
                 backend: ServiceBusBackend::Emulator {
                     client,
                     queue_name: config.queue,
                     dequeue_url,
+                    enqueue_url,
                 },
                 poll_interval: config.poll_interval,
             });
@@ -222,6 +228,21 @@ impl ServiceBusWorker {
                                                     );
                                                 }
                                             }
+                                            Err(error) if error.is_retryable() => {
+                                                info!(
+                                                    queue = %queue_name,
+                                                    %error,
+                                                    "abandoning admin message for a later retry",
+                                                );
+                                                if let Err(error) = lock.unlock_message().await {
+                                                    warn!(
+                                                        queue = %queue_name,
+                                                        %error,
+                                                        "failed to abandon admin message"
+                                                    );
+                                                }
+                                                sleep(poll_interval).await;
+                                            }
                                             Err(error) => match &error {
                                                 MessageHandlingError::Admin { account, .. } => {
                                                     warn!(
@@ -272,6 +293,7 @@ impl ServiceBusWorker {
                 client,
                 queue_name,
                 dequeue_url,
+                enqueue_url,
             } => {
                 let poll_interval = self.poll_interval;
                 tokio::spawn(async move {
@@ -296,12 +318,47 @@ impl ServiceBusWorker {
                                     continue;
                                 }
 
-                                match response.json::<AdminEnqueueRequest>().await {
+                                let body = match response.json::<Value>().await {
+                                    Ok(body) => body,
+                                    Err(error) => {
+                                        warn!(
+                                            queue = %queue_name,
+                                            %error,
+                                            "failed to read admin message from emulator"
+                                        );
+                                        sleep(poll_interval).await;
+                                        continue;
+                                    }
+                                };
+
+                                match AdminEnqueueRequest::deserialize(&body) {
                                     Ok(envelope) => {
                                         if let Err(error) =
                                             process_envelope(&state, envelope, &queue_name).await
                                         {
                                             match error {
+                                                error if error.is_retryable() => {
+                                                    info!(
+                                                        queue = %queue_name,
+                                                        %error,
+                                                        "returning admin message to emulator for a later retry",
+                                                    );
+                                                    let requeued = client
+                                                        .post(enqueue_url.clone())
+                                                        .json(&serde_json::json!({ "body": body }))
+                                                        .send()
+                                                        .await
+                                                        .and_then(|response| {
+                                                            response.error_for_status()
+                                                        });
+                                                    if let Err(error) = requeued {
+                                                        warn!(
+                                                            queue = %queue_name,
+                                                            %error,
+                                                            "failed to return admin message to emulator"
+                                                        );
+                                                    }
+                                                }
                                                 MessageHandlingError::Admin { account, source } => {
                                                     warn!(
                                                         queue = %queue_name,
@@ -368,6 +425,18 @@ pub(crate) enum MessageHandlingError {
     },
 }
 
+impl MessageHandlingError {
+    /// Whether the message should be abandoned and retried later instead of
+    /// being dropped, e.g. because the target session outbox is full.
+    fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::Api { source, .. }
+                if source.status() == axum::http::StatusCode::TOO_MANY_REQUESTS
+        )
+    }
+}
+
 async fn handle_command(
     state: &AppState,
     command: AdminCommand,
@@ -405,6 +474,7 @@ pub(crate) enum AdminEnqueueRequest {
     AuthReject(AuthRejectMessage),
     QueueOutboxEvent(OutboxEventMessage),
     TradeOrder(TradeOrderMessage),
+    Resync(ResyncMessage),
 }
 
 #[derive(Debug, Deserialize)]
@@ -416,7 +486,7 @@ pub(crate) struct AuthApprovalMessage {
     auth_key_fingerprint: String,
     #[serde(default)]
     approved_by: Option<String>,
-    #[serde(default)]
+    #[serde(default, with = "time::serde::rfc3339::option")]
     expires_at: Option<OffsetDateTime>,
 }
 
@@ -443,6 +513,8 @@ pub(crate) struct OutboxEventMessage {
     payload: Value,
     #[serde(default = "default_requires_ack_true")]
     requires_ack: bool,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    expires_at: Option<OffsetDateTime>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -454,6 +526,15 @@ pub(crate) struct TradeOrderMessage {
     command: TradeOrderRequest,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ResyncMessage {
+    account_id: String,
+    session_id: Uuid,
+    #[serde(flatten)]
+    request: ResyncRequest,
+}
+
 const fn default_requires_ack_true() -> bool {
     true
 }
@@ -507,6 +588,7 @@ async fn process_envelope(
         AdminEnqueueRequest::TradeOrder(message) => {
             process_trade_order(state, message, queue_name).await
         }
+        AdminEnqueueRequest::Resync(message) => process_resync(state, message, queue_name).await,
     }
 }
 
@@ -521,12 +603,14 @@ async fn process_outbox_event(
         event_type,
         payload,
         requires_ack,
+        expires_at,
     } = message;
 
     let request = OutboxEventRequest {
         event_type: event_type.clone(),
         payload,
         requires_ack,
+        expires_at,
     };
 
     let response = state
@@ -585,3 +669,35 @@ async fn process_trade_order(
 
     Ok(())
 }
+
+async fn process_resync(
+    state: &AppState,
+    message: ResyncMessage,
+    queue_name: &str,
+) -> Result<(), MessageHandlingError> {
+    let ResyncMessage {
+        account_id,
+        session_id,
+        request,
+    } = message;
+
+    let resync = state
+        .resync_outbox(&account_id, session_id, request)
+        .await
+        .map_err(|source| MessageHandlingError::Api {
+            account: account_id.clone(),
+            session: session_id,
+            source,
+        })?;
+
+    info!(
+        queue = %queue_name,
+        account = %account_id,
+        session = %session_id,
+        replayed = resync.replayed.len(),
+        skipped_expired = resync.skipped_expired,
+        "replayed acknowledged outbox events from Service Bus",
+    );
+
+    Ok(())
+}
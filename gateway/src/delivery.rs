@@ -0,0 +1,230 @@
+use std::{env, time::Duration};
+
+use thiserror::Error;
+
+use crate::TradeCommandType;
+
+const VISIBILITY_TIMEOUT_ENV: &str = "EA_OUTBOX_VISIBILITY_TIMEOUT_SECS";
+const MAX_ATTEMPTS_ENV: &str = "EA_OUTBOX_MAX_DELIVERY_ATTEMPTS";
+const MAX_DEPTH_ENV: &str = "EA_OUTBOX_MAX_DEPTH";
+const MAX_BYTES_ENV: &str = "EA_OUTBOX_MAX_BYTES";
+const PREEMPTION_POLICY_ENV: &str = "EA_OUTBOX_PREEMPTION_POLICY";
+const OPEN_COMMAND_TTL_ENV: &str = "EA_OPEN_COMMAND_TTL_SECS";
+const CLOSE_COMMAND_TTL_ENV: &str = "EA_CLOSE_COMMAND_TTL_SECS";
+const MODIFY_COMMAND_TTL_ENV: &str = "EA_MODIFY_COMMAND_TTL_SECS";
+const CANCEL_COMMAND_TTL_ENV: &str = "EA_CANCEL_COMMAND_TTL_SECS";
+const ACK_HISTORY_RETENTION_ENV: &str = "EA_OUTBOX_ACK_HISTORY_RETENTION_SECS";
+const ACK_HISTORY_MAX_EVENTS_ENV: &str = "EA_OUTBOX_ACK_HISTORY_MAX_EVENTS";
+const COMMAND_LEDGER_RETENTION_ENV: &str = "EA_COMMAND_LEDGER_RETENTION_SECS";
+const COMMAND_LEDGER_MAX_UNSETTLED_AGE_ENV: &str = "EA_COMMAND_LEDGER_MAX_UNSETTLED_AGE_SECS";
+const MIN_RETRY_AFTER_ENV: &str = "EA_OUTBOX_MIN_RETRY_AFTER_MS";
+const MAX_RETRY_AFTER_ENV: &str = "EA_OUTBOX_MAX_RETRY_AFTER_MS";
+
+/// Quiet period after which the poll back-off hint doubles.
+const RETRY_AFTER_IDLE_WINDOW: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Error)]
+pub enum OutboxDeliveryConfigError {
+    #[error("environment variable {name} contains invalid UTF-8 characters")]
+    InvalidUnicode {
+        name: &'static str,
+        #[source]
+        source: env::VarError,
+    },
+    #[error("failed to parse {name}: {source}")]
+    InvalidNumber {
+        name: &'static str,
+        #[source]
+        source: std::num::ParseIntError,
+    },
+    #[error("{name} must be `migrate` or `cancel`, got `{value}`")]
+    InvalidPreemptionPolicy { name: &'static str, value: String },
+}
+
+/// What happens to unacknowledged outbox events when a new session preempts
+/// the one they were queued for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutboxPreemptionPolicy {
+    /// Re-sequence the events into the new session's outbox.
+    #[default]
+    Migrate,
+    /// Drop the events and notify the new session that they were cancelled.
+    Cancel,
+}
+
+/// Controls when unacknowledged outbox events are handed out again, how much a
+/// session outbox may hold, how long trade commands stay eligible for delivery,
+/// how long acknowledged events and tracked commands are kept, and how long
+/// EAs are told to wait between polls.
+#[derive(Debug, Clone)]
+pub struct OutboxDeliveryConfig {
+    /// Time an event stays hidden from polls after being delivered.
+    pub visibility_timeout: Duration,
+    /// Deliveries after which an unacknowledged event is dead-lettered.
+    pub max_attempts: u32,
+    /// Maximum number of events queued in a single session outbox.
+    pub max_depth: usize,
+    /// Maximum combined payload size, in bytes, of a single session outbox.
+    pub max_bytes: usize,
+    /// Handling of unacknowledged events when a session is preempted.
+    pub preemption_policy: OutboxPreemptionPolicy,
+    /// Default expiry for `open` trade commands that do not carry `expiresAt`.
+    pub open_command_ttl: Option<Duration>,
+    /// Default expiry for `close` trade commands that do not carry `expiresAt`.
+    pub close_command_ttl: Option<Duration>,
+    /// Default expiry for `modify` trade commands that do not carry `expiresAt`.
+    pub modify_command_ttl: Option<Duration>,
+    /// Default expiry for `cancel` trade commands that do not carry `expiresAt`.
+    pub cancel_command_ttl: Option<Duration>,
+    /// How long acknowledged events stay available for an admin resync.
+    pub ack_history_retention: Duration,
+    /// Most acknowledged events kept per account for an admin resync.
+    pub ack_history_max_events: usize,
+    /// How long settled trade commands stay in the command ledger.
+    pub command_ledger_retention: Duration,
+    /// Age after which a trade command that never settled leaves the ledger.
+    pub command_ledger_max_unsettled_age: Duration,
+    /// Poll back-off hint while commands are flowing to the session.
+    pub min_retry_after: Duration,
+    /// Poll back-off hint for pending and long-idle sessions.
+    pub max_retry_after: Duration,
+}
+
+impl Default for OutboxDeliveryConfig {
+    fn default() -> Self {
+        Self {
+            visibility_timeout: Duration::from_secs(30),
+            max_attempts: 5,
+            max_depth: 1_000,
+            max_bytes: 1024 * 1024,
+            preemption_policy: OutboxPreemptionPolicy::default(),
+            open_command_ttl: Some(Duration::from_secs(60)),
+            close_command_ttl: None,
+            modify_command_ttl: Some(Duration::from_secs(60)),
+            cancel_command_ttl: None,
+            ack_history_retention: Duration::from_secs(24 * 60 * 60),
+            ack_history_max_events: 10_000,
+            command_ledger_retention: Duration::from_secs(7 * 24 * 60 * 60),
+            command_ledger_max_unsettled_age: Duration::from_secs(7 * 24 * 60 * 60),
+            min_retry_after: Duration::from_millis(500),
+            max_retry_after: Duration::from_secs(30),
+        }
+    }
+}
+
+impl OutboxDeliveryConfig {
+    pub fn from_env() -> Result<Self, OutboxDeliveryConfigError> {
+        let defaults = Self::default();
+        let min_retry_after = read_number(MIN_RETRY_AFTER_ENV)?
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.min_retry_after);
+        let max_retry_after = read_number(MAX_RETRY_AFTER_ENV)?
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.max_retry_after)
+            .max(min_retry_after);
+
+        Ok(Self {
+            visibility_timeout: read_number(VISIBILITY_TIMEOUT_ENV)?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.visibility_timeout),
+            max_attempts: read_number(MAX_ATTEMPTS_ENV)?
+                .map(|attempts| attempts.clamp(1, u64::from(u32::MAX)) as u32)
+                .unwrap_or(defaults.max_attempts),
+            max_depth: read_limit(MAX_DEPTH_ENV, defaults.max_depth)?,
+            max_bytes: read_limit(MAX_BYTES_ENV, defaults.max_bytes)?,
+            preemption_policy: read_preemption_policy(defaults.preemption_policy)?,
+            open_command_ttl: read_ttl(OPEN_COMMAND_TTL_ENV, defaults.open_command_ttl)?,
+            close_command_ttl: read_ttl(CLOSE_COMMAND_TTL_ENV, defaults.close_command_ttl)?,
+            modify_command_ttl: read_ttl(MODIFY_COMMAND_TTL_ENV, defaults.modify_command_ttl)?,
+            cancel_command_ttl: read_ttl(CANCEL_COMMAND_TTL_ENV, defaults.cancel_command_ttl)?,
+            ack_history_retention: read_number(ACK_HISTORY_RETENTION_ENV)?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.ack_history_retention),
+            ack_history_max_events: read_limit(
+                ACK_HISTORY_MAX_EVENTS_ENV,
+                defaults.ack_history_max_events,
+            )?,
+            command_ledger_retention: read_number(COMMAND_LEDGER_RETENTION_ENV)?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.command_ledger_retention),
+            command_ledger_max_unsettled_age: read_number(COMMAND_LEDGER_MAX_UNSETTLED_AGE_ENV)?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.command_ledger_max_unsettled_age),
+            min_retry_after,
+            max_retry_after,
+        })
+    }
+
+    /// Poll back-off hint for a session whose outbox last saw traffic `idle`
+    /// ago, or never if `None`: the minimum while commands are flowing,
+    /// doubling for every further quiet window up to the maximum.
+    pub(crate) fn retry_after(&self, idle: Option<Duration>) -> Duration {
+        let Some(idle) = idle else {
+            return self.max_retry_after;
+        };
+        let windows = (idle.as_secs() / RETRY_AFTER_IDLE_WINDOW.as_secs()).min(31) as u32;
+        self.min_retry_after
+            .saturating_mul(1 << windows)
+            .min(self.max_retry_after)
+    }
+
+    pub(crate) fn command_ttl(&self, command_type: TradeCommandType) -> Option<Duration> {
+        match command_type {
+            TradeCommandType::Open => self.open_command_ttl,
+            TradeCommandType::Close => self.close_command_ttl,
+            TradeCommandType::Modify => self.modify_command_ttl,
+            TradeCommandType::Cancel => self.cancel_command_ttl,
+        }
+    }
+}
+
+fn read_preemption_policy(
+    default: OutboxPreemptionPolicy,
+) -> Result<OutboxPreemptionPolicy, OutboxDeliveryConfigError> {
+    let name = PREEMPTION_POLICY_ENV;
+    match env::var(name) {
+        Ok(value) => match value.trim().to_ascii_lowercase().as_str() {
+            "migrate" => Ok(OutboxPreemptionPolicy::Migrate),
+            "cancel" => Ok(OutboxPreemptionPolicy::Cancel),
+            _ => Err(OutboxDeliveryConfigError::InvalidPreemptionPolicy { name, value }),
+        },
+        Err(env::VarError::NotPresent) => Ok(default),
+        Err(error) => Err(OutboxDeliveryConfigError::InvalidUnicode {
+            name,
+            source: error,
+        }),
+    }
+}
+
+fn read_limit(name: &'static str, default: usize) -> Result<usize, OutboxDeliveryConfigError> {
+    Ok(read_number(name)?
+        .map(|limit| usize::try_from(limit.max(1)).unwrap_or(usize::MAX))
+        .unwrap_or(default))
+}
+
+/// Reads a TTL in seconds, where `0` disables the default expiry.
+fn read_ttl(
+    name: &'static str,
+    default: Option<Duration>,
+) -> Result<Option<Duration>, OutboxDeliveryConfigError> {
+    Ok(match read_number(name)? {
+        Some(0) => None,
+        Some(secs) => Some(Duration::from_secs(secs)),
+        None => default,
+    })
+}
+
+fn read_number(name: &'static str) -> Result<Option<u64>, OutboxDeliveryConfigError> {
+    match env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|source| OutboxDeliveryConfigError::InvalidNumber { name, source }),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(error) => Err(OutboxDeliveryConfigError::InvalidUnicode {
+            name,
+            source: error,
+        }),
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Duration,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::OutboundEvent;
+
+/// An outbox event the EA acknowledged, kept so that a resync can replay it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AcknowledgedEvent {
+    #[serde(flatten)]
+    event: OutboundEvent,
+    #[serde(with = "crate::timestamp")]
+    acknowledged_at: OffsetDateTime,
+}
+
+/// Acknowledged outbox events of an account, oldest acknowledgement first,
+/// with the sequence of each indexed by event id.
+///
+/// Session bookkeeping (`InitAck`, `ShutdownNotice`) is never kept, since a
+/// resync must not replay it. Persisted as a plain list; the index is rebuilt
+/// on load.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AckHistory {
+    events: VecDeque<AcknowledgedEvent>,
+    sequences: HashMap<Uuid, u64>,
+}
+
+impl Serialize for AckHistory {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.events.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AckHistory {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let events = VecDeque::<AcknowledgedEvent>::deserialize(deserializer)?;
+        let sequences = events
+            .iter()
+            .map(|entry| (entry.event.id, entry.event.sequence))
+            .collect();
+        Ok(Self { events, sequences })
+    }
+}
+
+impl AckHistory {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Remembers `events` as acknowledged at `now`, dropping the oldest
+    /// entries beyond `max_events`.
+    pub(crate) fn record(
+        &mut self,
+        events: impl IntoIterator<Item = OutboundEvent>,
+        max_events: usize,
+        now: OffsetDateTime,
+    ) {
+        for event in events {
+            if matches!(event.event_type.as_str(), "InitAck" | "ShutdownNotice") {
+                continue;
+            }
+            self.sequences.insert(event.id, event.sequence);
+            self.events.push_back(AcknowledgedEvent {
+                event,
+                acknowledged_at: now,
+            });
+        }
+        while self.events.len() > max_events {
+            self.pop_oldest();
+        }
+    }
+
+    /// Drops events acknowledged more than `retention` ago, returning how many
+    /// were dropped.
+    pub(crate) fn prune(&mut self, retention: Duration, now: OffsetDateTime) -> usize {
+        let mut pruned = 0;
+        while self
+            .events
+            .front()
+            .is_some_and(|entry| now - entry.acknowledged_at >= retention)
+        {
+            self.pop_oldest();
+            pruned += 1;
+        }
+        pruned
+    }
+
+    /// Sequence of a retained acknowledged event, if `event_id` was acknowledged.
+    pub(crate) fn sequence_of(&self, event_id: Uuid) -> Option<u64> {
+        self.sequences.get(&event_id).copied()
+    }
+
+    /// Acknowledged events numbered `from_sequence` or later, in sequence
+    /// order. An event that was already replayed is only returned once.
+    pub(crate) fn since(&self, from_sequence: u64) -> Vec<OutboundEvent> {
+        let mut events: Vec<OutboundEvent> = self
+            .events
+            .iter()
+            .filter(|entry| entry.event.sequence >= from_sequence)
+            .map(|entry| entry.event.clone())
+            .collect();
+        events.sort_by_key(|event| event.sequence);
+
+        let mut seen = HashSet::new();
+        events.retain(|event| seen.insert(event.original_event_id.unwrap_or(event.id)));
+        events
+    }
+
+    fn pop_oldest(&mut self) {
+        if let Some(entry) = self.events.pop_front() {
+            self.sequences.remove(&entry.event.id);
+        }
+    }
+}
@@ -0,0 +1,443 @@
+use std::{collections::HashMap, mem, sync::Arc, time::Duration};
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, Request},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tokio::{
+    sync::{Mutex, Notify},
+    task::JoinHandle,
+    time::{interval, MissedTickBehavior},
+};
+use tracing::debug;
+
+use crate::{current_time, ApiError, AppState, StoredResponse};
+
+/// Retention window promised to EA clients for `Idempotency-Key` replays.
+pub(crate) const IDEMPOTENCY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long a concurrent duplicate waits for the original request to finish
+/// before it is told to retry later.
+const IN_FLIGHT_WAIT: Duration = Duration::from_secs(5);
+
+/// Reservations older than this are assumed abandoned (for example because the
+/// operation behind them hung) and may be taken over by a retry.
+const IN_FLIGHT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const EVICTION_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Responses recorded per idempotency storage key, each tagged with the
+/// fingerprint of the request body that produced it. Keys whose first request
+/// is still executing hold an in-flight reservation instead of a response.
+pub(crate) struct IdempotencyCache {
+    entries: HashMap<String, IdempotencyEntry>,
+    ttl: Duration,
+    in_flight_wait: Duration,
+    in_flight_timeout: Duration,
+}
+
+struct IdempotencyEntry {
+    fingerprint: String,
+    state: EntryState,
+    stored_at: OffsetDateTime,
+}
+
+enum EntryState {
+    InFlight(Arc<Notify>),
+    Completed(StoredResponse),
+}
+
+impl IdempotencyEntry {
+    fn is_expired(&self, now: OffsetDateTime, ttl: Duration, in_flight_timeout: Duration) -> bool {
+        let lifetime = match self.state {
+            EntryState::InFlight(_) => in_flight_timeout,
+            EntryState::Completed(_) => ttl,
+        };
+        now - self.stored_at >= lifetime
+    }
+}
+
+pub(crate) enum IdempotencyLookup {
+    /// The caller now owns the key and must `complete` or `release` it.
+    Reserved,
+    Replay(StoredResponse),
+    /// Another request with the same key and body is executing; the notifier
+    /// fires once it completes or releases the key.
+    InFlight(Arc<Notify>),
+    FingerprintMismatch,
+}
+
+impl Default for IdempotencyCache {
+    fn default() -> Self {
+        Self::new(IDEMPOTENCY_TTL)
+    }
+}
+
+impl IdempotencyCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            ttl,
+            in_flight_wait: IN_FLIGHT_WAIT,
+            in_flight_timeout: IN_FLIGHT_TIMEOUT,
+        }
+    }
+
+    pub(crate) fn in_flight_wait(&self) -> Duration {
+        self.in_flight_wait
+    }
+
+    /// Returns the recorded outcome for `key`, or reserves it for the caller
+    /// when no live record exists.
+    pub(crate) fn reserve(
+        &mut self,
+        key: &str,
+        fingerprint: &str,
+        now: OffsetDateTime,
+    ) -> IdempotencyLookup {
+        if let Some(entry) = self.entries.get(key) {
+            if !entry.is_expired(now, self.ttl, self.in_flight_timeout) {
+                if entry.fingerprint != fingerprint {
+                    return IdempotencyLookup::FingerprintMismatch;
+                }
+
+                return match &entry.state {
+                    EntryState::InFlight(notify) => IdempotencyLookup::InFlight(notify.clone()),
+                    EntryState::Completed(response) => IdempotencyLookup::Replay(response.clone()),
+                };
+            }
+        }
+
+        if let Some(IdempotencyEntry {
+            state: EntryState::InFlight(notify),
+            ..
+        }) = self.entries.insert(
+            key.to_string(),
+            IdempotencyEntry {
+                fingerprint: fingerprint.to_string(),
+                state: EntryState::InFlight(Arc::new(Notify::new())),
+                stored_at: now,
+            },
+        ) {
+            notify.notify_waiters();
+        }
+
+        IdempotencyLookup::Reserved
+    }
+
+    /// Records the response for a reserved key and wakes any waiting duplicates.
+    pub(crate) fn complete(
+        &mut self,
+        key: String,
+        fingerprint: String,
+        response: StoredResponse,
+        now: OffsetDateTime,
+    ) {
+        let previous = self.entries.insert(
+            key,
+            IdempotencyEntry {
+                fingerprint,
+                state: EntryState::Completed(response),
+                stored_at: now,
+            },
+        );
+
+        if let Some(IdempotencyEntry {
+            state: EntryState::InFlight(notify),
+            ..
+        }) = previous
+        {
+            notify.notify_waiters();
+        }
+    }
+
+    /// Drops the reservation for a request that failed so that a retry can run.
+    pub(crate) fn release(&mut self, key: &str) {
+        if let Some(entry) = self.entries.get(key) {
+            if let EntryState::InFlight(notify) = &entry.state {
+                notify.notify_waiters();
+                self.entries.remove(key);
+            }
+        }
+    }
+
+    pub(crate) fn evict_expired(&mut self, now: OffsetDateTime) -> usize {
+        let (ttl, in_flight_timeout) = (self.ttl, self.in_flight_timeout);
+        let before = self.entries.len();
+        self.entries
+            .retain(|_, entry| !entry.is_expired(now, ttl, in_flight_timeout));
+        before - self.entries.len()
+    }
+}
+
+/// A key reserved through [`IdempotencyCache::reserve`], owned by the request
+/// running under it.
+///
+/// Dropping the reservation without completing or releasing it (because the
+/// request panicked or its task was aborted) releases the key, so that a retry
+/// runs straight away instead of waiting out the in-flight timeout.
+pub(crate) struct Reservation {
+    cache: Arc<Mutex<IdempotencyCache>>,
+    key: String,
+    fingerprint: String,
+    settled: bool,
+}
+
+impl Reservation {
+    pub(crate) fn new(
+        cache: Arc<Mutex<IdempotencyCache>>,
+        key: String,
+        fingerprint: String,
+    ) -> Self {
+        Self {
+            cache,
+            key,
+            fingerprint,
+            settled: false,
+        }
+    }
+
+    /// Records the response of the request and wakes any waiting duplicates.
+    pub(crate) async fn complete(mut self, response: StoredResponse) {
+        let mut cache = self.cache.lock().await;
+        cache.complete(
+            mem::take(&mut self.key),
+            mem::take(&mut self.fingerprint),
+            response,
+            current_time(),
+        );
+        self.settled = true;
+    }
+
+    /// Gives the key up after the request failed so that a retry can run.
+    pub(crate) async fn release(mut self) {
+        self.cache.lock().await.release(&self.key);
+        self.settled = true;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if self.settled {
+            return;
+        }
+
+        let key = mem::take(&mut self.key);
+        match self.cache.try_lock() {
+            Ok(mut cache) => cache.release(&key),
+            Err(_) => {
+                let cache = self.cache.clone();
+                tokio::spawn(async move { cache.lock().await.release(&key) });
+            }
+        }
+    }
+}
+
+/// Background task that drops idempotency records once their TTL has passed.
+pub struct IdempotencyEvictor {
+    interval: Duration,
+}
+
+impl Default for IdempotencyEvictor {
+    fn default() -> Self {
+        Self::new(EVICTION_INTERVAL)
+    }
+}
+
+impl IdempotencyEvictor {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval }
+    }
+
+    pub fn spawn(self, state: AppState) -> JoinHandle<()> {
+        let period = self.interval;
+        tokio::spawn(async move {
+            let mut ticker = interval(period);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+                let evicted = state.evict_idempotency_records(current_time()).await;
+                if evicted > 0 {
+                    debug!(evicted, "evicted expired idempotency records");
+                }
+            }
+        })
+    }
+}
+
+/// Hashes a canonical JSON rendering of a request body so that retries are
+/// compared on content rather than on formatting.
+pub(crate) fn body_fingerprint(body: &Value) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(body.to_string().as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// JSON body extractor that also records the body fingerprint used for
+/// idempotent replays.
+pub(crate) struct FingerprintedJson<T> {
+    pub(crate) payload: T,
+    pub(crate) fingerprint: String,
+}
+
+#[async_trait]
+impl<S, T> FromRequest<S> for FingerprintedJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(body) = Json::<Value>::from_request(request, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let fingerprint = body_fingerprint(&body);
+        let payload = T::deserialize(&body).map_err(|error| {
+            ApiError::unprocessable("invalid_payload", error.to_string()).into_response()
+        })?;
+
+        Ok(Self {
+            payload,
+            fingerprint,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    #[test]
+    fn replays_matching_fingerprint_and_flags_reused_keys() {
+        let mut cache = IdempotencyCache::default();
+        let now = current_time();
+        let original = body_fingerprint(&json!({"authenticationKey": "a", "authMethod": "x"}));
+        let reordered = body_fingerprint(&json!({"authMethod": "x", "authenticationKey": "a"}));
+        let different = body_fingerprint(&json!({"authenticationKey": "b"}));
+
+        assert!(matches!(
+            cache.reserve("key", &original, now),
+            IdempotencyLookup::Reserved
+        ));
+        assert!(matches!(
+            cache.reserve("key", &reordered, now),
+            IdempotencyLookup::InFlight(_)
+        ));
+
+        cache.complete(
+            "key".to_string(),
+            original,
+            StoredResponse::empty(StatusCode::NO_CONTENT),
+            now,
+        );
+
+        assert!(matches!(
+            cache.reserve("key", &reordered, now),
+            IdempotencyLookup::Replay(_)
+        ));
+        assert!(matches!(
+            cache.reserve("key", &different, now),
+            IdempotencyLookup::FingerprintMismatch
+        ));
+        assert!(matches!(
+            cache.reserve("other", &different, now),
+            IdempotencyLookup::Reserved
+        ));
+    }
+
+    #[test]
+    fn entries_expire_after_ttl() {
+        let mut cache = IdempotencyCache::new(Duration::from_secs(60));
+        let now = current_time();
+        let fingerprint = body_fingerprint(&Value::Null);
+
+        cache.complete(
+            "stale".to_string(),
+            fingerprint.clone(),
+            StoredResponse::empty(StatusCode::NO_CONTENT),
+            now,
+        );
+        cache.complete(
+            "fresh".to_string(),
+            fingerprint.clone(),
+            StoredResponse::empty(StatusCode::NO_CONTENT),
+            now + time::Duration::seconds(30),
+        );
+
+        let later = now + time::Duration::seconds(61);
+        assert_eq!(cache.evict_expired(later), 1);
+        assert!(matches!(
+            cache.reserve("stale", &fingerprint, later),
+            IdempotencyLookup::Reserved
+        ));
+        assert!(matches!(
+            cache.reserve("fresh", &fingerprint, later),
+            IdempotencyLookup::Replay(_)
+        ));
+    }
+
+    #[test]
+    fn released_and_abandoned_reservations_can_be_retaken() {
+        let mut cache = IdempotencyCache::default();
+        let now = current_time();
+        let fingerprint = body_fingerprint(&Value::Null);
+
+        assert!(matches!(
+            cache.reserve("key", &fingerprint, now),
+            IdempotencyLookup::Reserved
+        ));
+        cache.release("key");
+        assert!(matches!(
+            cache.reserve("key", &fingerprint, now),
+            IdempotencyLookup::Reserved
+        ));
+
+        let abandoned = now + time::Duration::seconds(31);
+        assert!(matches!(
+            cache.reserve("key", &fingerprint, abandoned),
+            IdempotencyLookup::Reserved
+        ));
+    }
+
+    #[tokio::test]
+    async fn dropped_reservations_release_their_key() {
+        let cache = Arc::new(Mutex::new(IdempotencyCache::default()));
+        let fingerprint = body_fingerprint(&Value::Null);
+        let reserve = |cache: &mut IdempotencyCache| {
+            matches!(
+                cache.reserve("key", &fingerprint, current_time()),
+                IdempotencyLookup::Reserved
+            )
+        };
+
+        assert!(reserve(&mut *cache.lock().await));
+        drop(Reservation::new(
+            cache.clone(),
+            "key".to_string(),
+            fingerprint.clone(),
+        ));
+        assert!(reserve(&mut *cache.lock().await));
+
+        Reservation::new(cache.clone(), "key".to_string(), fingerprint.clone())
+            .complete(StoredResponse::empty(StatusCode::NO_CONTENT))
+            .await;
+        assert!(matches!(
+            cache
+                .lock()
+                .await
+                .reserve("key", &fingerprint, current_time()),
+            IdempotencyLookup::Replay(_)
+        ));
+    }
+}
@@ -0,0 +1,363 @@
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value;
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Debug, Error)]
+pub enum InboxPayloadError {
+    #[error("{event_type} payload is malformed: {source}")]
+    Malformed {
+        event_type: &'static str,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("{event_type} payload is invalid: {reason}")]
+    Invalid {
+        event_type: &'static str,
+        reason: &'static str,
+    },
+}
+
+/// Payload of an inbox event, typed for the event catalogue the EA speaks.
+/// Event types outside the catalogue are accepted as opaque JSON.
+#[derive(Debug, Clone)]
+pub enum InboxPayload {
+    InitRequest(InitRequest),
+    StatusHeartbeat(StatusHeartbeat),
+    StatusSummary(StatusSummary),
+    SyncSnapshot(SyncSnapshot),
+    OrderIntent(OrderIntent),
+    ExecutionReport(ExecutionReport),
+    ErrorAlert(ErrorAlert),
+    OutboxAck(OutboxAck),
+    Other,
+}
+
+impl InboxPayload {
+    /// Parses and validates `payload` against the schema for `event_type`,
+    /// matched case-insensitively.
+    pub fn parse(event_type: &str, payload: &Value) -> Result<Self, InboxPayloadError> {
+        const CATALOGUE: [&str; 8] = [
+            "InitRequest",
+            "StatusHeartbeat",
+            "StatusSummary",
+            "SyncSnapshot",
+            "OrderIntent",
+            "ExecutionReport",
+            "ErrorAlert",
+            "OutboxAck",
+        ];
+
+        let Some(known) = CATALOGUE
+            .into_iter()
+            .find(|known| known.eq_ignore_ascii_case(event_type.trim()))
+        else {
+            return Ok(Self::Other);
+        };
+
+        let parsed = match known {
+            "InitRequest" => Self::InitRequest(decode(known, payload)?),
+            "StatusHeartbeat" => Self::StatusHeartbeat(decode(known, payload)?),
+            "StatusSummary" => Self::StatusSummary(decode(known, payload)?),
+            "SyncSnapshot" => Self::SyncSnapshot(decode(known, payload)?),
+            "OrderIntent" => Self::OrderIntent(decode(known, payload)?),
+            "ExecutionReport" => Self::ExecutionReport(decode(known, payload)?),
+            "ErrorAlert" => Self::ErrorAlert(decode(known, payload)?),
+            _ => Self::OutboxAck(decode(known, payload)?),
+        };
+
+        parsed
+            .validate()
+            .map_err(|reason| InboxPayloadError::Invalid {
+                event_type: known,
+                reason,
+            })?;
+        Ok(parsed)
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        match self {
+            Self::StatusSummary(summary) if summary.status.trim().is_empty() => {
+                Err("status must not be empty")
+            }
+            Self::SyncSnapshot(snapshot) => {
+                if snapshot
+                    .open_trades
+                    .iter()
+                    .chain(&snapshot.pending_orders)
+                    .any(|trade| trade.symbol.trim().is_empty())
+                {
+                    return Err("every trade must name a symbol");
+                }
+                if snapshot
+                    .open_trades
+                    .iter()
+                    .chain(&snapshot.pending_orders)
+                    .any(|trade| !is_volume(trade.volume))
+                {
+                    return Err("trade volumes must be positive");
+                }
+                if snapshot
+                    .open_trades
+                    .iter()
+                    .chain(&snapshot.pending_orders)
+                    .any(|trade| trade.volume_step.is_some_and(|step| !is_volume(step)))
+                {
+                    return Err("volume steps must be positive");
+                }
+                Ok(())
+            }
+            Self::OrderIntent(intent) => {
+                if intent.symbol.trim().is_empty() {
+                    return Err("symbol must not be empty");
+                }
+                if intent.volume.is_some_and(|volume| !is_volume(volume)) {
+                    return Err("volume must be positive");
+                }
+                Ok(())
+            }
+            Self::ExecutionReport(report)
+                if report
+                    .volume
+                    .into_iter()
+                    .chain(report.remaining_volume)
+                    .any(|volume| volume < 0.0) =>
+            {
+                Err("volumes must not be negative")
+            }
+            Self::ErrorAlert(alert) if alert.code.trim().is_empty() => {
+                Err("code must not be empty")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn decode<T: DeserializeOwned>(
+    event_type: &'static str,
+    payload: &Value,
+) -> Result<T, InboxPayloadError> {
+    T::deserialize(payload).map_err(|source| InboxPayloadError::Malformed { event_type, source })
+}
+
+fn is_volume(volume: f64) -> bool {
+    volume.is_finite() && volume > 0.0
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitRequest {
+    #[serde(default)]
+    pub account_login: Option<String>,
+    #[serde(default)]
+    pub broker: Option<String>,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub leverage: Option<u32>,
+    #[serde(default)]
+    pub subscribed_symbols: Vec<String>,
+    #[serde(default)]
+    pub supports_order_submission: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusHeartbeat {
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub chart_symbol: Option<String>,
+    #[serde(default)]
+    pub equity: Option<f64>,
+    #[serde(default)]
+    pub margin_free: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusSummary {
+    pub status: String,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub details: Option<Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncSnapshot {
+    pub balance: f64,
+    pub equity: f64,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub margin: Option<f64>,
+    #[serde(default)]
+    pub margin_free: Option<f64>,
+    #[serde(default)]
+    pub open_trades: Vec<SnapshotTrade>,
+    #[serde(default)]
+    pub pending_orders: Vec<SnapshotTrade>,
+}
+
+/// A position or pending order as listed in a `SyncSnapshot`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotTrade {
+    pub ticket: u64,
+    pub symbol: String,
+    pub volume: f64,
+    /// Smallest volume increment the broker accepts for `symbol`.
+    #[serde(default)]
+    pub volume_step: Option<f64>,
+    /// MetaTrader position or order type code.
+    #[serde(default, rename = "type")]
+    pub trade_type: Option<i32>,
+    #[serde(default)]
+    pub price: Option<f64>,
+    #[serde(default)]
+    pub stop_loss: Option<f64>,
+    #[serde(default)]
+    pub take_profit: Option<f64>,
+    #[serde(default)]
+    pub profit: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderIntent {
+    pub symbol: String,
+    #[serde(default)]
+    pub side: Option<String>,
+    #[serde(default)]
+    pub volume: Option<f64>,
+    #[serde(default)]
+    pub rationale: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionReport {
+    #[serde(default)]
+    pub command_id: Option<Uuid>,
+    #[serde(default)]
+    pub client_order_id: Option<String>,
+    #[serde(default)]
+    pub deal: Option<u64>,
+    /// Ticket of the position the execution opened, changed or closed.
+    #[serde(default)]
+    pub position: Option<u64>,
+    /// Volume left in `position` after the execution; `0` once it is closed.
+    #[serde(default)]
+    pub remaining_volume: Option<f64>,
+    #[serde(default)]
+    pub order: Option<u64>,
+    #[serde(default)]
+    pub symbol: Option<String>,
+    /// MetaTrader trade transaction type code.
+    #[serde(default, rename = "type")]
+    pub trade_type: Option<i32>,
+    #[serde(default)]
+    pub volume: Option<f64>,
+    #[serde(default)]
+    pub price: Option<f64>,
+    #[serde(default)]
+    pub profit: Option<f64>,
+    #[serde(default)]
+    pub retcode: Option<u32>,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorAlert {
+    pub code: String,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub severity: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxAck {
+    pub event_id: Uuid,
+    /// Trade command carried by the acknowledged event, if any.
+    #[serde(default)]
+    pub command_id: Option<Uuid>,
+    #[serde(default)]
+    pub sequence: Option<u64>,
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn known_types_are_validated_and_unknown_types_pass_through() {
+        let ack = InboxPayload::parse("outboxack", &json!({"eventId": Uuid::nil(), "sequence": 4}))
+            .expect("a well-formed ack should parse");
+        assert!(matches!(
+            ack,
+            InboxPayload::OutboxAck(OutboxAck {
+                sequence: Some(4),
+                ..
+            })
+        ));
+
+        assert!(matches!(
+            InboxPayload::parse("OutboxAck", &json!({"eventId": "not-a-uuid"})),
+            Err(InboxPayloadError::Malformed {
+                event_type: "OutboxAck",
+                ..
+            })
+        ));
+        assert!(matches!(
+            InboxPayload::parse(
+                "SyncSnapshot",
+                &json!({
+                    "balance": 1000.0,
+                    "equity": 990.0,
+                    "openTrades": [{"ticket": 7, "symbol": "", "volume": 0.1}],
+                }),
+            ),
+            Err(InboxPayloadError::Invalid {
+                event_type: "SyncSnapshot",
+                ..
+            })
+        ));
+        assert!(matches!(
+            InboxPayload::parse("ea.custom", &Value::Null),
+            Ok(InboxPayload::Other)
+        ));
+    }
+
+    /// An inbox batch exactly as `KopitraAgent.mq5` posts it for a deal that
+    /// partially closed a position it opened for a command.
+    const EA_EXECUTION_REPORT: &str = include_str!("../tests/fixtures/ea_execution_report.json");
+
+    #[test]
+    fn execution_report_from_the_ea_parses() {
+        let batch: Value =
+            serde_json::from_str(EA_EXECUTION_REPORT).expect("the EA should post valid JSON");
+        let event = &batch["events"][0];
+        let event_type = event["eventType"].as_str().expect("event type");
+        let report = match InboxPayload::parse(event_type, &event["payload"]) {
+            Ok(InboxPayload::ExecutionReport(report)) => report,
+            other => panic!("expected an execution report, got {other:?}"),
+        };
+        assert_eq!(report.deal, Some(5012345));
+        assert_eq!(report.position, Some(7034567));
+        assert_eq!(report.remaining_volume, Some(0.05));
+        assert_eq!(report.symbol.as_deref(), Some("EURUSD"));
+        assert_eq!(
+            report.command_id,
+            Some(Uuid::parse_str("5f0c6d2e-8b1a-4c3e-9d47-2a6b1e3f9c80").unwrap())
+        );
+    }
+}
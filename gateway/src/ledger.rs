@@ -0,0 +1,358 @@
+use std::{cmp::Reverse, collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{ExecutionReport, OutboundEvent, TradeCommandType, TradeOrderType};
+
+/// MetaTrader return codes for a request that was placed or filled.
+const ACCEPTED_RETCODES: [u32; 3] = [10008, 10009, 10010];
+
+/// MetaTrader transaction type of a deal being added to the history.
+const DEAL_ADD_TRANSACTION: i32 = 6;
+
+/// Where a trade command stands between being queued and its outcome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CommandStatus {
+    Queued,
+    Delivered,
+    Acknowledged,
+    Executed,
+    Rejected,
+    Expired,
+}
+
+impl CommandStatus {
+    fn is_final(self) -> bool {
+        matches!(self, Self::Executed | Self::Rejected | Self::Expired)
+    }
+}
+
+/// Lifecycle of a single trade command, keyed by its `commandId`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CommandRecord {
+    command_id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_order_id: Option<String>,
+    session_id: Uuid,
+    event_id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    command_type: Option<TradeCommandType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    order_type: Option<TradeOrderType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    instrument: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    position_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    volume: Option<f64>,
+    status: CommandStatus,
+    #[serde(with = "time::serde::rfc3339")]
+    queued_at: OffsetDateTime,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    delivered_at: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    acknowledged_at: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    completed_at: Option<OffsetDateTime>,
+    /// Details of the latest `ExecutionReport` for the command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    execution: Option<CommandExecution>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CommandExecution {
+    deal: Option<u64>,
+    order: Option<u64>,
+    position: Option<u64>,
+    volume: Option<f64>,
+    price: Option<f64>,
+    retcode: Option<u32>,
+    comment: Option<String>,
+}
+
+impl From<&ExecutionReport> for CommandExecution {
+    fn from(report: &ExecutionReport) -> Self {
+        Self {
+            deal: report.deal,
+            order: report.order,
+            position: report.position,
+            volume: report.volume,
+            price: report.price,
+            retcode: report.retcode,
+            comment: report.comment.clone(),
+        }
+    }
+}
+
+/// Trade commands queued for an account, tracked from the `OrderCommand`
+/// outbox event through to the EA's `ExecutionReport`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct CommandLedger {
+    commands: HashMap<Uuid, CommandRecord>,
+}
+
+/// Outcome an `ExecutionReport` settles its command with, if it has one.
+fn verdict(report: &ExecutionReport) -> Option<CommandStatus> {
+    match report.retcode {
+        Some(retcode) if ACCEPTED_RETCODES.contains(&retcode) => Some(CommandStatus::Executed),
+        Some(retcode) if retcode != 0 => Some(CommandStatus::Rejected),
+        _ => (report.trade_type == Some(DEAL_ADD_TRANSACTION)
+            && report.deal.is_some_and(|deal| deal > 0))
+        .then_some(CommandStatus::Executed),
+    }
+}
+
+/// Command id carried by an `OrderCommand` or `OrderCancel` outbox event.
+fn order_command_id(event: &OutboundEvent) -> Option<Uuid> {
+    if !matches!(event.event_type.as_str(), "OrderCommand" | "OrderCancel") {
+        return None;
+    }
+    event
+        .payload
+        .get("commandId")
+        .and_then(|value| value.as_str())
+        .and_then(|value| Uuid::parse_str(value).ok())
+}
+
+impl CommandRecord {
+    pub(crate) fn command_id(&self) -> Uuid {
+        self.command_id
+    }
+
+    pub(crate) fn command_type(&self) -> Option<TradeCommandType> {
+        self.command_type
+    }
+
+    pub(crate) fn order_type(&self) -> Option<TradeOrderType> {
+        self.order_type
+    }
+
+    /// Volume the command asked for; `None` on a `close` means the whole position.
+    pub(crate) fn volume(&self) -> Option<f64> {
+        self.volume
+    }
+
+    pub(crate) fn status(&self) -> CommandStatus {
+        self.status
+    }
+
+    /// Ticket of the position an executed `close` command reduced.
+    pub(crate) fn closed_position(&self) -> Option<u64> {
+        if self.command_type != Some(TradeCommandType::Close)
+            || self.status != CommandStatus::Executed
+        {
+            return None;
+        }
+        self.position_id.as_deref()?.parse().ok()
+    }
+
+    /// Broker ticket of the order the command placed, once reported.
+    pub(crate) fn order(&self) -> Option<u64> {
+        self.execution
+            .as_ref()
+            .and_then(|execution| execution.order)
+    }
+}
+
+impl CommandLedger {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub(crate) fn get(&self, command_id: Uuid) -> Option<&CommandRecord> {
+        self.commands.get(&command_id)
+    }
+
+    /// The command with `command_id`, or else the latest one queued with
+    /// `client_order_id`.
+    pub(crate) fn lookup(
+        &self,
+        command_id: Option<Uuid>,
+        client_order_id: Option<&str>,
+    ) -> Option<&CommandRecord> {
+        match command_id {
+            Some(command_id) => self.get(command_id),
+            None => self.find(Some(client_order_id?)).into_iter().next(),
+        }
+    }
+
+    /// Commands queued with `client_order_id` (or every command when `None`),
+    /// most recently queued first.
+    pub(crate) fn find(&self, client_order_id: Option<&str>) -> Vec<&CommandRecord> {
+        let mut records: Vec<&CommandRecord> = self
+            .commands
+            .values()
+            .filter(|record| {
+                client_order_id.is_none() || record.client_order_id.as_deref() == client_order_id
+            })
+            .collect();
+        records.sort_by_key(|record| Reverse(record.queued_at));
+        records
+    }
+
+    /// `close` commands for `position_id` that have not settled yet.
+    pub(crate) fn unsettled_closes<'a>(
+        &'a self,
+        position_id: &'a str,
+    ) -> impl Iterator<Item = &'a CommandRecord> {
+        self.commands.values().filter(move |record| {
+            record.command_type == Some(TradeCommandType::Close)
+                && record.position_id.as_deref() == Some(position_id)
+                && !record.status.is_final()
+        })
+    }
+
+    /// Starts tracking the command carried by `event`, if it is an
+    /// `OrderCommand` or `OrderCancel`.
+    pub(crate) fn record_queued(&mut self, session_id: Uuid, event: &OutboundEvent) {
+        let Some(command_id) = order_command_id(event) else {
+            return;
+        };
+        let text = |field: &str| {
+            event
+                .payload
+                .get(field)
+                .and_then(|value| value.as_str())
+                .map(str::to_string)
+        };
+
+        self.commands.insert(
+            command_id,
+            CommandRecord {
+                command_id,
+                client_order_id: text("clientOrderId"),
+                session_id,
+                event_id: event.id,
+                command_type: event
+                    .payload
+                    .get("commandType")
+                    .and_then(|value| serde_json::from_value(value.clone()).ok()),
+                order_type: event
+                    .payload
+                    .get("orderType")
+                    .and_then(|value| serde_json::from_value(value.clone()).ok()),
+                instrument: text("instrument"),
+                position_id: text("positionId"),
+                volume: event.payload.get("volume").and_then(|value| value.as_f64()),
+                status: CommandStatus::Queued,
+                queued_at: event.enqueued_at,
+                delivered_at: None,
+                acknowledged_at: None,
+                completed_at: None,
+                execution: None,
+            },
+        );
+    }
+
+    /// Marks the commands carried by `events` delivered, returning how many
+    /// had not been delivered before.
+    pub(crate) fn mark_delivered(
+        &mut self,
+        events: &[OutboundEvent],
+        now: OffsetDateTime,
+    ) -> usize {
+        let mut delivered = 0;
+        for command_id in events.iter().filter_map(order_command_id) {
+            if let Some(record) = self.commands.get_mut(&command_id) {
+                if record.delivered_at.is_none() {
+                    record.delivered_at = Some(now);
+                    delivered += 1;
+                }
+                if record.status == CommandStatus::Queued {
+                    record.status = CommandStatus::Delivered;
+                }
+            }
+        }
+        delivered
+    }
+
+    pub(crate) fn mark_acknowledged(&mut self, events: &[OutboundEvent], now: OffsetDateTime) {
+        for command_id in events.iter().filter_map(order_command_id) {
+            self.acknowledge(command_id, now);
+        }
+    }
+
+    /// Records an acknowledgement that names the command directly.
+    pub(crate) fn acknowledge(&mut self, command_id: Uuid, now: OffsetDateTime) {
+        if let Some(record) = self.commands.get_mut(&command_id) {
+            record.acknowledged_at.get_or_insert(now);
+            if !record.status.is_final() {
+                record.status = CommandStatus::Acknowledged;
+            }
+        }
+    }
+
+    pub(crate) fn mark_expired(&mut self, event: &OutboundEvent, now: OffsetDateTime) {
+        let Some(record) = order_command_id(event).and_then(|id| self.commands.get_mut(&id)) else {
+            return;
+        };
+        if !record.status.is_final() {
+            record.status = CommandStatus::Expired;
+            record.completed_at = Some(now);
+        }
+    }
+
+    /// Applies an `ExecutionReport` to the command it refers to, by
+    /// `commandId` or else by the latest unsettled command with its
+    /// `clientOrderId`.
+    ///
+    /// MetaTrader reports a trade as several transactions, and only some of
+    /// them carry a verdict: a return code from the trade request settles the
+    /// command as executed or rejected, and an added deal settles it as
+    /// executed. The others, with no or a zero return code, only fill in the
+    /// execution details.
+    ///
+    /// Returns the command only when this report settled it; a command that
+    /// already settled keeps its outcome.
+    pub(crate) fn record_execution(
+        &mut self,
+        report: &ExecutionReport,
+        now: OffsetDateTime,
+    ) -> Option<&CommandRecord> {
+        let command_id = report.command_id.or_else(|| {
+            let client_order_id = report.client_order_id.as_deref()?;
+            self.find(Some(client_order_id))
+                .into_iter()
+                .find(|record| !record.status.is_final())
+                .map(|record| record.command_id)
+        })?;
+        let record = self
+            .commands
+            .get_mut(&command_id)
+            .filter(|record| !record.status.is_final())?;
+
+        let Some(status) = verdict(report) else {
+            record
+                .execution
+                .get_or_insert_with(|| CommandExecution::from(report));
+            return None;
+        };
+        record.status = status;
+        record.completed_at = Some(now);
+        record.execution = Some(CommandExecution::from(report));
+        Some(record)
+    }
+
+    /// Drops commands settled more than `retention` ago and those queued more
+    /// than `max_unsettled_age` ago that never settled, returning how many
+    /// were dropped.
+    pub(crate) fn prune(
+        &mut self,
+        retention: Duration,
+        max_unsettled_age: Duration,
+        now: OffsetDateTime,
+    ) -> usize {
+        let before = self.commands.len();
+        self.commands.retain(|_, record| match record.completed_at {
+            Some(completed_at) => now - completed_at < retention,
+            None => now - record.queued_at < max_unsettled_age,
+        });
+        before - self.commands.len()
+    }
+}
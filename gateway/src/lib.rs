@@ -1,4 +1,9 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
 
 use axum::{
     body::Body,
@@ -13,15 +18,49 @@ use serde_json::{json, Map, Value};
 use sha2::{Digest, Sha256};
 use thiserror::Error;
 use time::OffsetDateTime;
-use tokio::sync::Mutex;
+use tokio::{
+    sync::{Mutex, Notify, RwLock},
+    time::{timeout_at, Instant},
+};
 use tracing::{debug, info, warn};
 use uuid::Uuid;
 
+use history::AckHistory;
+use idempotency::{
+    body_fingerprint, FingerprintedJson, IdempotencyCache, IdempotencyLookup, Reservation,
+};
+use ledger::{CommandLedger, CommandRecord, CommandStatus};
+use operations::OperationalEvent;
+use portfolio::{Portfolio, VOLUME_EPSILON};
+use store::{SaveReceipt, SectionUpdate, StoreWriter, Tracked};
+
 mod admin;
+mod delivery;
+mod history;
+mod idempotency;
+mod inbox;
+mod ledger;
+mod operations;
+mod portfolio;
+mod reaper;
+mod store;
+mod stream;
+mod timestamp;
 
 pub use admin::{
     ServiceBusConfig, ServiceBusConfigError, ServiceBusWorker, ServiceBusWorkerInitError,
 };
+pub use delivery::{OutboxDeliveryConfig, OutboxDeliveryConfigError, OutboxPreemptionPolicy};
+pub use idempotency::IdempotencyEvictor;
+pub use inbox::{
+    ErrorAlert, ExecutionReport, InboxPayload, InboxPayloadError, InitRequest, OrderIntent,
+    OutboxAck, SnapshotTrade, StatusHeartbeat, StatusSummary, SyncSnapshot,
+};
+pub use reaper::{SessionReaper, SessionReaperConfig, SessionReaperConfigError};
+pub use store::{
+    InMemorySessionStore, SectionChanges, SessionStore, SessionStoreConfig,
+    SessionStoreConfigError, SessionStoreError, SqliteSessionStore, StoredSections,
+};
 
 /// Builds the application router for the EA counterparty service.
 pub fn router(state: AppState) -> Router {
@@ -37,23 +76,88 @@ pub fn router(state: AppState) -> Router {
             "/trade-agent/v1/sessions/current/outbox",
             get(fetch_outbox_events),
         )
+        .route(
+            "/trade-agent/v1/sessions/current/outbox/ack",
+            post(acknowledge_outbox_batch),
+        )
         .route(
             "/trade-agent/v1/sessions/current/outbox/:event_id/ack",
             post(acknowledge_outbox_event),
         )
+        .route(
+            "/trade-agent/v1/sessions/current/outbox/stream",
+            get(stream::outbox_event_stream),
+        )
+        .route(
+            "/trade-agent/v1/sessions/current/stream",
+            get(stream::session_stream),
+        )
+        .with_state(state)
+}
+
+/// Builds the router for the operator endpoints under `/trade-agent/v1/admin`.
+///
+/// These routes carry no EA session authentication and must only be served
+/// on the internal management listener, never alongside [`router`].
+pub fn admin_router(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/trade-agent/v1/admin/accounts/:account_id/sessions/:session_id/dead-letters",
+            get(list_dead_letters),
+        )
+        .route(
+            "/trade-agent/v1/admin/accounts/:account_id/sessions/:session_id/dead-letters/:event_id/requeue",
+            post(requeue_dead_letter),
+        )
+        .route(
+            "/trade-agent/v1/admin/accounts/:account_id/sessions/:session_id/outbox",
+            get(fetch_outbox_depth),
+        )
+        .route(
+            "/trade-agent/v1/admin/accounts/:account_id/sessions/:session_id/resync",
+            post(resync_outbox),
+        )
+        .route(
+            "/trade-agent/v1/admin/accounts/:account_id/portfolio",
+            get(fetch_portfolio),
+        )
+        .route(
+            "/trade-agent/v1/admin/accounts/:account_id/commands",
+            get(list_commands),
+        )
+        .route(
+            "/trade-agent/v1/admin/accounts/:account_id/commands/:command_id",
+            get(fetch_command),
+        )
         .with_state(state)
 }
 
-/// Shared application state guarded by a mutex for simplicity.
-#[derive(Clone, Default)]
+type AccountHandle = Arc<Mutex<AccountSessions>>;
+
+/// Shared application state.
+///
+/// Every account's sessions sit behind their own lock so that requests for
+/// different accounts never wait on one another; the account map itself is
+/// only write-locked when an account appears or disappears. The idempotency
+/// cache has a separate lock and is never held while an account is locked.
+/// Persistence goes through a [`StoreWriter`], which keeps database writes
+/// off the request path until its queue fills up.
+/// Released accounts leave their sequence counters behind, guarded by a lock
+/// only taken while the account map is write-locked.
+#[derive(Clone)]
 pub struct AppState {
-    inner: Arc<Mutex<SharedState>>,
+    accounts: Arc<RwLock<HashMap<String, AccountHandle>>>,
+    released_sequences: Arc<Mutex<HashMap<String, AccountSequences>>>,
+    idempotency: Arc<Mutex<IdempotencyCache>>,
+    writer: Arc<StoreWriter>,
+    delivery: OutboxDeliveryConfig,
 }
 
-#[derive(Default)]
-struct SharedState {
-    sessions: HashMap<String, AccountSessions>,
-    idempotency: HashMap<String, StoredResponse>,
+impl Default for AppState {
+    fn default() -> Self {
+        Self::with_store(Arc::new(InMemorySessionStore::default()))
+            .expect("an empty in-memory session store always loads")
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -92,18 +196,175 @@ pub enum AdminCommandError {
     SessionTerminated,
     #[error("authentication key must not be empty")]
     AuthenticationKeyEmpty,
+    #[error("the approval has already expired")]
+    ApprovalExpired,
 }
 
-#[derive(Default)]
-struct AccountSessions {
+/// Outbox and inbox sequence counters of an account, so that numbering keeps
+/// increasing across reconnects and preemption. They are only touched under
+/// the account lock, and outlive the account entry once it is released.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AccountSequences {
+    next_outbox: u64,
+    next_inbox: u64,
+}
+
+impl Default for AccountSequences {
+    fn default() -> Self {
+        Self {
+            next_outbox: 1,
+            next_inbox: 1,
+        }
+    }
+}
+
+impl AccountSequences {
+    fn next_outbox(&mut self) -> u64 {
+        let sequence = self.next_outbox;
+        self.next_outbox += 1;
+        sequence
+    }
+
+    fn next_inbox(&mut self) -> u64 {
+        let sequence = self.next_inbox;
+        self.next_inbox += 1;
+        sequence
+    }
+
+    /// Highest outbox sequence handed out so far, or `0` if none was.
+    fn outbox_high_water_mark(&self) -> u64 {
+        self.next_outbox - 1
+    }
+
+    fn advance_past_outbox(&mut self, sequence: u64) {
+        self.next_outbox = self.next_outbox.max(sequence + 1);
+    }
+}
+
+/// Section holding everything of a serialized [`AccountSessions`] that is not
+/// persisted on its own.
+const ACCOUNT_SECTION: &str = "account";
+
+/// Prefix of the sections holding one session each, followed by its token.
+const SESSION_SECTION_PREFIX: &str = "session:";
+
+/// Section holding the account's [`AccountSequences`], which is kept when the
+/// rest of the account is released.
+pub(crate) const SEQUENCES_SECTION: &str = "sequences";
+
+/// Reassembles the sections written by [`AccountSessions::sections`].
+fn join_sections(sections: &StoredSections) -> Result<Value, serde_json::Error> {
+    let mut account = Map::new();
+    let mut sessions = Map::new();
+    for (name, snapshot) in sections {
+        let value: Value = serde_json::from_str(snapshot)?;
+        if let Some(token) = name.strip_prefix(SESSION_SECTION_PREFIX) {
+            sessions.insert(token.to_string(), value);
+        } else if name == ACCOUNT_SECTION {
+            if let Value::Object(fields) = value {
+                account.extend(fields);
+            }
+        } else {
+            account.insert(name.clone(), value);
+        }
+    }
+    account.insert("sessionsByToken".to_string(), Value::Object(sessions));
+    Ok(Value::Object(account))
+}
+
+/// Adds `value` to `update` as the section `name` if it changed, or else
+/// names it among the unchanged sections.
+fn tracked_section<T: Serialize>(
+    update: &mut SectionUpdate,
+    name: &str,
+    value: &Tracked<T>,
+) -> Result<(), serde_json::Error> {
+    if value.take_changed() {
+        update
+            .sections
+            .insert(name.to_string(), serde_json::to_string(value)?);
+    } else {
+        update.unchanged.push(name.to_string());
+    }
+    Ok(())
+}
+
+/// All sessions and pre-approvals tracked for a single EA account.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AccountSessions {
     sessions_by_token: HashMap<Uuid, SessionRecord>,
     active_index: HashMap<String, Uuid>,
     session_index: HashMap<Uuid, Uuid>,
     preapproved: HashMap<String, PreapprovalRecord>,
+    sequences: AccountSequences,
+    acknowledged: Tracked<AckHistory>,
+    highest_acknowledged_sequence: u64,
+    portfolio: Tracked<Portfolio>,
+    commands: Tracked<CommandLedger>,
+}
+
+/// Fields of an [`AccountSessions`] stored in its [`ACCOUNT_SECTION`]; every
+/// other field has a section of its own.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountSection<'a> {
+    active_index: &'a HashMap<String, Uuid>,
+    session_index: &'a HashMap<Uuid, Uuid>,
+    preapproved: &'a HashMap<String, PreapprovalRecord>,
+    highest_acknowledged_sequence: u64,
 }
 
 impl AccountSessions {
+    /// Empty account state that continues numbering from `sequences`.
+    fn resuming(sequences: AccountSequences) -> Self {
+        Self {
+            sequences,
+            ..Self::default()
+        }
+    }
+
+    fn outbox_high_water_mark(&self) -> u64 {
+        self.sequences.outbox_high_water_mark()
+    }
+
+    /// Serializes the account into the sections it is stored as, leaving out
+    /// the history, portfolio and ledger unless they changed since the last
+    /// call; those are the sections that grow large.
+    fn sections(&self) -> Result<SectionUpdate, serde_json::Error> {
+        let mut update = SectionUpdate::default();
+        for (token, session) in &self.sessions_by_token {
+            update.sections.insert(
+                format!("{SESSION_SECTION_PREFIX}{token}"),
+                serde_json::to_string(session)?,
+            );
+        }
+        update.sections.insert(
+            ACCOUNT_SECTION.to_string(),
+            serde_json::to_string(&AccountSection {
+                active_index: &self.active_index,
+                session_index: &self.session_index,
+                preapproved: &self.preapproved,
+                highest_acknowledged_sequence: self.highest_acknowledged_sequence,
+            })?,
+        );
+        update.sections.insert(
+            SEQUENCES_SECTION.to_string(),
+            serde_json::to_string(&self.sequences)?,
+        );
+        tracked_section(&mut update, "acknowledged", &self.acknowledged)?;
+        tracked_section(&mut update, "portfolio", &self.portfolio)?;
+        tracked_section(&mut update, "commands", &self.commands)?;
+        Ok(update)
+    }
+
     fn insert(&mut self, session: SessionRecord) {
+        // Sessions built outside the account may already hold numbered events.
+        if let Some(highest) = session.highest_outbox_sequence() {
+            self.sequences.advance_past_outbox(highest);
+        }
+
         let token = session.session_token;
         let session_id = session.session_id;
         let auth_hash = session.auth_key_hash.clone();
@@ -113,11 +374,16 @@ impl AccountSessions {
         self.sessions_by_token.insert(token, session);
     }
 
-    fn preempt_existing(&mut self, auth_hash: &str) -> Option<Uuid> {
+    /// Terminates the active session for `auth_hash`, handing back the
+    /// unacknowledged events that its EA never confirmed.
+    fn preempt_existing(&mut self, auth_hash: &str) -> Option<PreemptedSession> {
         let token = self.active_index.remove(auth_hash)?;
         let session = self.sessions_by_token.get_mut(&token)?;
-        session.mark_terminated(TerminationReason::Preempted);
-        Some(session.session_id)
+        session.mark_terminated(&mut self.sequences, TerminationReason::Preempted);
+        Some(PreemptedSession {
+            session_id: session.session_id,
+            unacknowledged: session.take_unacknowledged(),
+        })
     }
 
     fn get_mut_by_token(&mut self, token: &Uuid) -> Option<&mut SessionRecord> {
@@ -129,6 +395,27 @@ impl AccountSessions {
         self.sessions_by_token.get_mut(token)
     }
 
+    /// The session with `session_id` along with the counters it numbers its
+    /// events from.
+    fn session_with_sequences(
+        &mut self,
+        session_id: &Uuid,
+    ) -> Option<(&mut SessionRecord, &mut AccountSequences)> {
+        let token = self.session_index.get(session_id)?;
+        let session = self.sessions_by_token.get_mut(token)?;
+        Some((session, &mut self.sequences))
+    }
+
+    /// The session behind `token` along with the counters it numbers its
+    /// events from.
+    fn session_with_sequences_by_token(
+        &mut self,
+        token: &Uuid,
+    ) -> Option<(&mut SessionRecord, &mut AccountSequences)> {
+        let session = self.sessions_by_token.get_mut(token)?;
+        Some((session, &mut self.sequences))
+    }
+
     fn remove_by_token(&mut self, token: &Uuid) -> Option<SessionRecord> {
         let session = self.sessions_by_token.remove(token)?;
         self.session_index.remove(&session.session_id);
@@ -148,7 +435,121 @@ impl AccountSessions {
     }
 
     fn is_empty(&self) -> bool {
-        self.sessions_by_token.is_empty() && self.preapproved.is_empty()
+        self.sessions_by_token.is_empty()
+            && self.preapproved.is_empty()
+            && self.acknowledged.is_empty()
+            && self.portfolio.is_empty()
+            && self.commands.is_empty()
+    }
+
+    /// Remembers events the EA acknowledged so that a resync can replay them,
+    /// within the history limits of `config`.
+    fn record_acknowledged(
+        &mut self,
+        events: Vec<OutboundEvent>,
+        config: &OutboxDeliveryConfig,
+        now: OffsetDateTime,
+    ) {
+        if let Some(highest) = events.iter().map(|event| event.sequence).max() {
+            self.highest_acknowledged_sequence = self.highest_acknowledged_sequence.max(highest);
+        }
+        self.commands.mark_acknowledged(&events, now);
+        self.acknowledged
+            .record(events, config.ack_history_max_events, now);
+        self.acknowledged.prune(config.ack_history_retention, now);
+    }
+
+    /// Terminates every session whose approval window has lapsed, returning their ids.
+    fn expire_lapsed_approvals(&mut self, now: OffsetDateTime) -> Vec<Uuid> {
+        let mut expired = Vec::new();
+        let tokens: Vec<Uuid> = self.sessions_by_token.keys().copied().collect();
+
+        for token in tokens {
+            let Some(session) = self.sessions_by_token.get_mut(&token) else {
+                continue;
+            };
+
+            if session.expire_lapsed_approval(&mut self.sequences, now) {
+                let auth_hash = session.auth_key_hash.clone();
+                expired.push(session.session_id);
+                self.remove_from_active_index(&auth_hash, token);
+            }
+        }
+
+        expired
+    }
+
+    /// Drops expired outbox events across every session, returning them with
+    /// the id of the session they were queued for.
+    fn expire_outbox_events(&mut self, now: OffsetDateTime) -> Vec<(Uuid, OutboundEvent)> {
+        let expired: Vec<(Uuid, OutboundEvent)> = self
+            .sessions_by_token
+            .values_mut()
+            .flat_map(|session| {
+                let session_id = session.session_id;
+                session
+                    .expire_outbox(&mut self.sequences, now)
+                    .into_iter()
+                    .map(move |event| (session_id, event))
+            })
+            .collect();
+        for (_, event) in &expired {
+            self.commands.mark_expired(event, now);
+        }
+        expired
+    }
+
+    fn reap(
+        &mut self,
+        config: &SessionReaperConfig,
+        delivery: &OutboxDeliveryConfig,
+        now: OffsetDateTime,
+    ) -> AccountReapOutcome {
+        let mut outcome = AccountReapOutcome {
+            approvals_expired: self.expire_lapsed_approvals(now),
+            commands_expired: self.expire_outbox_events(now),
+            acknowledged_pruned: self.acknowledged.update(
+                |history| history.prune(delivery.ack_history_retention, now),
+                |pruned| *pruned > 0,
+            ),
+            commands_pruned: self.commands.update(
+                |ledger| {
+                    ledger.prune(
+                        delivery.command_ledger_retention,
+                        delivery.command_ledger_max_unsettled_age,
+                        now,
+                    )
+                },
+                |pruned| *pruned > 0,
+            ),
+            ..AccountReapOutcome::default()
+        };
+        let tokens: Vec<Uuid> = self.sessions_by_token.keys().copied().collect();
+
+        for token in tokens {
+            let Some(session) = self.sessions_by_token.get_mut(&token) else {
+                continue;
+            };
+
+            if session.heartbeat_expired(now, config.heartbeat_timeout) {
+                session.mark_terminated(
+                    &mut self.sequences,
+                    TerminationReason::HeartbeatTimeout {
+                        last_heartbeat_at: session.last_heartbeat_at,
+                        reconnect_after: config.reconnect_window,
+                    },
+                );
+                let auth_hash = session.auth_key_hash.clone();
+                outcome.timed_out.push(session.session_id);
+                self.remove_from_active_index(&auth_hash, token);
+            } else if session.is_reapable(now, config.heartbeat_timeout) {
+                if let Some(removed) = self.remove_by_token(&token) {
+                    outcome.removed.push(removed.session_id);
+                }
+            }
+        }
+
+        outcome
     }
 
     fn register_preapproval(&mut self, fingerprint: String, record: PreapprovalRecord) {
@@ -165,9 +566,37 @@ impl AccountSessions {
     }
 }
 
-#[derive(Debug, Clone)]
+struct PreemptedSession {
+    session_id: Uuid,
+    unacknowledged: Vec<OutboundEvent>,
+}
+
+#[derive(Default)]
+struct AccountReapOutcome {
+    timed_out: Vec<Uuid>,
+    approvals_expired: Vec<Uuid>,
+    commands_expired: Vec<(Uuid, OutboundEvent)>,
+    acknowledged_pruned: usize,
+    commands_pruned: usize,
+    removed: Vec<Uuid>,
+}
+
+/// Totals from a single pass of the session reaper.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct ReapSummary {
+    timed_out: usize,
+    approvals_expired: usize,
+    commands_expired: usize,
+    acknowledged_pruned: usize,
+    commands_pruned: usize,
+    removed: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct PreapprovalRecord {
     approved_by: Option<String>,
+    #[serde(default, with = "timestamp::option")]
     expires_at: Option<OffsetDateTime>,
 }
 
@@ -181,1921 +610,5593 @@ impl PreapprovalRecord {
 }
 
 impl AppState {
-    async fn stored_response(&self, key: &str) -> Option<StoredResponse> {
-        self.inner.lock().await.idempotency.get(key).cloned()
+    /// Builds application state backed by the supplied store, restoring any
+    /// sessions it has persisted.
+    pub fn with_store(store: Arc<dyn SessionStore>) -> Result<Self, SessionStoreError> {
+        let loaded = store.load()?;
+        let mut accounts = HashMap::new();
+        let mut released_sequences = HashMap::new();
+        for (account, sections) in &loaded {
+            let sessions: AccountSessions = serde_json::from_value(join_sections(sections)?)?;
+            if sessions.is_empty() {
+                released_sequences.insert(account.clone(), sessions.sequences);
+            } else {
+                accounts.insert(account.clone(), Arc::new(Mutex::new(sessions)));
+            }
+        }
+        if !accounts.is_empty() {
+            info!(accounts = accounts.len(), "restored persisted sessions");
+        }
+
+        Ok(Self {
+            accounts: Arc::new(RwLock::new(accounts)),
+            released_sequences: Arc::new(Mutex::new(released_sequences)),
+            idempotency: Arc::default(),
+            writer: Arc::new(StoreWriter::spawn(store, &loaded)?),
+            delivery: OutboxDeliveryConfig::default(),
+        })
     }
 
-    pub(crate) async fn enqueue_outbox_event(
-        &self,
-        account: &str,
-        session_id: Uuid,
-        request: OutboxEventRequest,
-    ) -> Result<OutboxEnqueueResponse, ApiError> {
-        if request.event_type.trim().is_empty() {
-            return Err(ApiError::bad_request(
-                "event_type_empty",
-                "eventType must not be empty",
-            ));
-        }
+    /// Overrides the redelivery and dead-letter policy for outbox events.
+    pub fn with_outbox_delivery(mut self, config: OutboxDeliveryConfig) -> Self {
+        self.delivery = config;
+        self
+    }
 
-        let mut inner = self.inner.lock().await;
-        let account_sessions = inner.sessions.get_mut(account).ok_or_else(|| {
-            ApiError::unauthorized(
-                "session_missing",
-                "no active session for the supplied account",
-            )
-        })?;
+    /// Returns the lock guarding `account`, if the account has any state.
+    async fn account(&self, account: &str) -> Option<AccountHandle> {
+        self.accounts.read().await.get(account).cloned()
+    }
 
-        let session = account_sessions
-            .get_mut_by_session_id(&session_id)
-            .ok_or_else(|| {
-                ApiError::conflict(
-                    "session_mismatch",
-                    "the supplied session id does not match the active session",
-                )
-            })?;
+    /// Returns the lock guarding `account`, creating empty state on first use.
+    /// A recreated account continues the numbering it was released with.
+    async fn account_or_default(&self, account: &str) -> AccountHandle {
+        if let Some(handle) = self.account(account).await {
+            return handle;
+        }
 
-        if session.status == SessionStatus::Terminated {
-            return Err(ApiError::conflict(
-                "session_terminated",
-                "the session has been terminated and cannot accept outbox events",
-            ));
+        let mut accounts = self.accounts.write().await;
+        if let Some(handle) = accounts.get(account) {
+            return handle.clone();
         }
 
-        let event = session.enqueue_outbox(request);
-        let pending_session = session.status.is_pending();
+        let sequences = self
+            .released_sequences
+            .lock()
+            .await
+            .remove(account)
+            .unwrap_or_default();
+        let handle = Arc::new(Mutex::new(AccountSessions::resuming(sequences)));
+        accounts.insert(account.to_string(), handle.clone());
+        handle
+    }
 
-        debug!(
-            account = %account,
-            session = %session_id,
-            event = %event.id,
-            pending_session,
-            "queued outbox event",
-        );
+    /// Forgets `account` once it holds no sessions, pre-approvals,
+    /// acknowledged-event history, reported portfolio or command ledger
+    /// entries (see [`AccountSessions::is_empty`]), keeping only its sequence
+    /// counters so that numbering never restarts.
+    ///
+    /// The account is only dropped while nobody else holds its handle, so a
+    /// concurrent request can never mutate a detached entry. Callers must
+    /// release their own guard and handle first.
+    async fn release_account_if_empty(&self, account: &str) {
+        let mut accounts = self.accounts.write().await;
+        let Some(handle) = accounts.get(account) else {
+            return;
+        };
+        if Arc::strong_count(handle) != 1 {
+            return;
+        }
+        let sequences = match handle.try_lock() {
+            Ok(sessions) if sessions.is_empty() => sessions.sequences.clone(),
+            _ => return,
+        };
 
-        Ok(OutboxEnqueueResponse {
-            session_id,
-            event_id: event.id,
-            sequence: event.sequence,
-            pending_session,
-        })
+        accounts.remove(account);
+        match serde_json::to_string(&sequences) {
+            Ok(snapshot) => {
+                let update = SectionUpdate {
+                    sections: StoredSections::from([(SEQUENCES_SECTION.to_string(), snapshot)]),
+                    unchanged: Vec::new(),
+                };
+                self.writer.save(account, update).await;
+            }
+            Err(error) => {
+                warn!(account = %account, %error, "failed to serialize account sequences")
+            }
+        }
+        self.released_sequences
+            .lock()
+            .await
+            .insert(account.to_string(), sequences);
     }
 
-    pub(crate) async fn enqueue_trade_command(
+    /// Waits until every session write queued so far has reached the store.
+    pub async fn flush_sessions(&self) {
+        self.writer.flush().await;
+    }
+
+    /// Hands the account's sections to the store writer, which only writes
+    /// those that changed since the last save. Callers that must not answer
+    /// before the write is durable wait on the receipt once they have
+    /// released the account lock.
+    async fn persist_sessions(
         &self,
         account: &str,
-        session_id: Uuid,
-        request: TradeOrderRequest,
-    ) -> Result<TradeCommandQueued, ApiError> {
-        let TradeOrderRequest {
-            command_type,
-            instrument,
-            order_type,
-            side,
-            volume,
-            price,
-            stop_loss,
-            take_profit,
-            time_in_force,
-            position_id,
-            client_order_id,
-            metadata,
-        } = request;
-
-        let instrument = instrument.trim();
-        if instrument.is_empty() {
-            return Err(ApiError::bad_request(
-                "instrument_empty",
-                "instrument must not be empty",
-            ));
+        account_sessions: &AccountSessions,
+    ) -> SaveReceipt {
+        match account_sessions.sections() {
+            Ok(update) => self.writer.save(account, update).await,
+            Err(error) => {
+                warn!(account = %account, %error, "failed to serialize account sessions");
+                SaveReceipt::default()
+            }
         }
-        let instrument = instrument.to_string();
-
-        let command_id = Uuid::new_v4();
-        let issued_at = current_time();
+    }
 
-        let mut command_payload = json!({
-            "commandId": command_id,
-            "commandType": command_type,
-            "instrument": instrument,
-            "issuedAt": issued_at,
-        });
+    /// Terminates sessions with stale heartbeats and drops drained terminated sessions.
+    pub(crate) async fn reap_sessions(
+        &self,
+        config: &SessionReaperConfig,
+        now: OffsetDateTime,
+    ) -> ReapSummary {
+        let mut summary = ReapSummary::default();
+        let accounts: Vec<(String, AccountHandle)> = self
+            .accounts
+            .read()
+            .await
+            .iter()
+            .map(|(account, handle)| (account.clone(), handle.clone()))
+            .collect();
+
+        for (account, handle) in accounts {
+            let (outcome, drained) = {
+                let mut account_sessions = handle.lock().await;
+                let outcome = account_sessions.reap(config, &self.delivery, now);
+                if outcome.timed_out.is_empty()
+                    && outcome.approvals_expired.is_empty()
+                    && outcome.commands_expired.is_empty()
+                    && outcome.acknowledged_pruned == 0
+                    && outcome.commands_pruned == 0
+                    && outcome.removed.is_empty()
+                {
+                    continue;
+                }
 
-        let (response_order_type, response_side, response_position_id, response_volume) =
-            match command_type {
-                TradeCommandType::Open => {
-                    let side = side.ok_or_else(|| {
-                        ApiError::bad_request(
-                            "side_required",
-                            "side is required when opening a position",
-                        )
-                    })?;
+                self.persist_sessions(&account, &account_sessions).await;
+                (outcome, account_sessions.is_empty())
+            };
+            drop(handle);
 
-                    let order_type = order_type.ok_or_else(|| {
-                        ApiError::bad_request(
-                            "order_type_required",
-                            "orderType is required when opening a position",
-                        )
-                    })?;
+            for session_id in &outcome.timed_out {
+                warn!(
+                    account = %account,
+                    session = %session_id,
+                    "terminating session after heartbeat timeout",
+                );
+            }
 
-                    let volume = volume.ok_or_else(|| {
-                        ApiError::bad_request(
-                            "volume_required",
-                            "volume is required when opening a position",
-                        )
-                    })?;
+            for session_id in &outcome.approvals_expired {
+                warn!(
+                    account = %account,
+                    session = %session_id,
+                    "terminating session after approval expired",
+                );
+            }
 
-                    if !volume.is_finite() || volume <= 0.0 {
-                        return Err(ApiError::bad_request(
-                            "volume_invalid",
-                            "volume must be greater than zero",
-                        ));
-                    }
+            self.report_expired_events(&account, &outcome.commands_expired);
 
-                    command_payload["side"] = json!(side);
-                    command_payload["orderType"] = json!(order_type);
-                    command_payload["volume"] = json!(volume);
+            for session_id in &outcome.removed {
+                debug!(account = %account, session = %session_id, "removed drained session");
+            }
 
-                    match order_type {
-                        TradeOrderType::Limit
-                        | TradeOrderType::Stop
-                        | TradeOrderType::StopLimit => {
-                            let price = price.ok_or_else(|| {
-                                ApiError::bad_request(
-                                    "price_required",
-                                    "price is required for limit and stop orders",
-                                )
-                            })?;
+            if drained {
+                self.release_account_if_empty(&account).await;
+            }
 
-                            if !price.is_finite() || price <= 0.0 {
-                                return Err(ApiError::bad_request(
-                                    "price_invalid",
-                                    "price must be greater than zero",
-                                ));
-                            }
+            summary.timed_out += outcome.timed_out.len();
+            summary.approvals_expired += outcome.approvals_expired.len();
+            summary.commands_expired += outcome.commands_expired.len();
+            summary.acknowledged_pruned += outcome.acknowledged_pruned;
+            summary.commands_pruned += outcome.commands_pruned;
+            summary.removed += outcome.removed.len();
+        }
 
-                            command_payload["price"] = json!(price);
-                        }
-                        TradeOrderType::Market => {
-                            if let Some(price) = price {
-                                if !price.is_finite() || price <= 0.0 {
-                                    return Err(ApiError::bad_request(
-                                        "price_invalid",
-                                        "price must be greater than zero",
-                                    ));
-                                }
-                                command_payload["price"] = json!(price);
-                            }
-                        }
-                    }
+        summary
+    }
 
-                    Ok((Some(order_type), Some(side), None, Some(volume)))
+    /// Applies the preemption policy to the events a preempted session left
+    /// unacknowledged, either moving them to `session` or cancelling them.
+    /// Migrated events are held to the same outbox limits as new ones.
+    fn carry_over_outbox(
+        &self,
+        account: &str,
+        preempted: PreemptedSession,
+        session: &mut SessionRecord,
+        sequences: &mut AccountSequences,
+    ) {
+        let PreemptedSession {
+            session_id: previous_session_id,
+            unacknowledged,
+        } = preempted;
+
+        for event in unacknowledged {
+            let fits = session.outbox_has_room(payload_size(&event.payload), &self.delivery);
+            match self.delivery.preemption_policy {
+                OutboxPreemptionPolicy::Migrate if fits => {
+                    let adopted = session.adopt_outbox_event(sequences, event);
+                    info!(
+                        account = %account,
+                        previous_session = %previous_session_id,
+                        session = %session.session_id,
+                        original_event = ?adopted.original_event_id,
+                        event = %adopted.id,
+                        sequence = adopted.sequence,
+                        "migrated unacknowledged outbox event to preempting session",
+                    );
                 }
-                TradeCommandType::Close => {
-                    let position_id = position_id
-                        .as_deref()
-                        .map(str::trim)
-                        .filter(|value| !value.is_empty())
-                        .ok_or_else(|| {
-                            ApiError::bad_request(
-                                "position_id_required",
-                                "positionId is required when closing a position",
-                            )
-                        })?;
+                policy => {
+                    // Events that would overflow the new outbox are cancelled
+                    // rather than migrated.
+                    let reason = match policy {
+                        OutboxPreemptionPolicy::Migrate => "outbox_full",
+                        OutboxPreemptionPolicy::Cancel => "session_preempted",
+                    };
+                    let mut payload = json!({
+                        "eventId": event.original_event_id.unwrap_or(event.id),
+                        "eventType": event.event_type,
+                        "commandId": event.payload.get("commandId"),
+                        "reason": reason,
+                        "previousSessionId": previous_session_id,
+                    });
+                    session.enqueue_outbox(
+                        sequences,
+                        OutboxEventRequest {
+                            event_type: "CommandCancelled".to_string(),
+                            payload: payload.clone(),
+                            requires_ack: false,
+                            expires_at: None,
+                        },
+                    );
 
-                    command_payload["positionId"] = Value::String(position_id.to_string());
+                    warn!(
+                        account = %account,
+                        previous_session = %previous_session_id,
+                        event = %event.id,
+                        event_type = %event.event_type,
+                        reason,
+                        "cancelled unacknowledged outbox event of preempted session",
+                    );
+                    payload["sessionId"] = json!(session.session_id);
+                    OperationalEvent::new("command.cancelled", account, event.sequence, payload)
+                        .emit();
+                }
+            }
+        }
+    }
 
-                    let order_type = order_type.unwrap_or(TradeOrderType::Market);
-                    command_payload["orderType"] = json!(order_type);
+    /// Logs and publishes an operational event for outbox events that expired
+    /// before the EA picked them up.
+    fn report_expired_events(&self, account: &str, expired: &[(Uuid, OutboundEvent)]) {
+        for (session_id, event) in expired {
+            warn!(
+                account = %account,
+                session = %session_id,
+                event = %event.id,
+                event_type = %event.event_type,
+                "outbox event expired before delivery",
+            );
 
-                    let mut response_side = None;
-                    if let Some(side) = side {
-                        command_payload["side"] = json!(side);
-                        response_side = Some(side);
-                    }
+            let mut payload = expired_event_payload(event);
+            payload["sessionId"] = json!(session_id);
+            OperationalEvent::new("command.expired", account, event.sequence, payload).emit();
+        }
+    }
 
-                    let mut response_volume = None;
-                    if let Some(volume) = volume {
-                        if !volume.is_finite() || volume <= 0.0 {
-                            return Err(ApiError::bad_request(
-                                "volume_invalid",
-                                "volume must be greater than zero",
-                            ));
-                        }
-                        command_payload["volume"] = json!(volume);
-                        response_volume = Some(volume);
-                    }
+    /// Logs and publishes an operational event for outbox events that used up
+    /// their delivery attempts without being acknowledged.
+    pub(crate) fn report_dead_letters(
+        &self,
+        account: &str,
+        session_id: Uuid,
+        dead_lettered: &[DeadLetter],
+    ) {
+        for DeadLetter { event, .. } in dead_lettered {
+            warn!(
+                account = %account,
+                session = %session_id,
+                event = %event.id,
+                attempts = event.delivery_attempts,
+                "outbox event dead-lettered after exhausting delivery attempts",
+            );
 
-                    if let Some(price) = price {
-                        if !price.is_finite() || price <= 0.0 {
-                            return Err(ApiError::bad_request(
-                                "price_invalid",
-                                "price must be greater than zero",
-                            ));
-                        }
-                        command_payload["price"] = json!(price);
-                    }
+            let payload = json!({
+                "sessionId": session_id,
+                "eventId": event.id,
+                "eventType": event.event_type,
+                "sequence": event.sequence,
+                "deliveryAttempts": event.delivery_attempts,
+            });
+            OperationalEvent::new("outbox.dead_lettered", account, event.sequence, payload).emit();
+        }
+    }
 
-                    Ok((
-                        Some(order_type),
-                        response_side,
-                        Some(position_id.to_string()),
-                        response_volume,
+    /// Runs `operation` at most once per idempotency key.
+    ///
+    /// Completed requests are replayed, keys reused with a different request
+    /// body are rejected, and concurrent duplicates wait for the original to
+    /// finish before replaying its response (or receive `409` if it does not
+    /// finish in time). Failed operations release the key so that a retry can
+    /// run them again.
+    ///
+    /// The operation runs on its own task, so a client that disconnects midway
+    /// cannot cancel it between its side effects and the recording of its
+    /// response; a retry replays that response instead of running it twice.
+    async fn run_idempotent<F>(
+        &self,
+        key: String,
+        fingerprint: String,
+        operation: F,
+    ) -> Result<Response, ApiError>
+    where
+        F: Future<Output = Result<StoredResponse, ApiError>> + Send + 'static,
+    {
+        let mut deadline = None;
+
+        loop {
+            let mut idempotency = self.idempotency.lock().await;
+            let notify = match idempotency.reserve(&key, &fingerprint, current_time()) {
+                IdempotencyLookup::Reserved => break,
+                IdempotencyLookup::Replay(stored) => return Ok(stored.into_response()),
+                IdempotencyLookup::FingerprintMismatch => {
+                    return Err(ApiError::unprocessable(
+                        "idempotency_key_reused",
+                        "the Idempotency-Key was already used with a different request body",
                     ))
                 }
-            }?;
-
-        if let Some(stop_loss) = stop_loss {
-            if !stop_loss.is_finite() || stop_loss <= 0.0 {
-                return Err(ApiError::bad_request(
-                    "stop_loss_invalid",
-                    "stopLoss must be greater than zero",
+                IdempotencyLookup::InFlight(notify) => notify,
+            };
+            let deadline =
+                *deadline.get_or_insert_with(|| Instant::now() + idempotency.in_flight_wait());
+
+            // Register for the wake-up before releasing the lock so that a
+            // completion racing with us cannot be missed.
+            let notified = notify.notified();
+            drop(idempotency);
+
+            if timeout_at(deadline, notified).await.is_err() {
+                return Err(ApiError::conflict(
+                    "idempotency_in_progress",
+                    "a request with the same Idempotency-Key is still being processed",
                 ));
             }
-            command_payload["stopLoss"] = json!(stop_loss);
         }
 
-        if let Some(take_profit) = take_profit {
-            if !take_profit.is_finite() || take_profit <= 0.0 {
-                return Err(ApiError::bad_request(
-                    "take_profit_invalid",
-                    "takeProfit must be greater than zero",
-                ));
+        let reservation = Reservation::new(self.idempotency.clone(), key, fingerprint);
+        let task = tokio::spawn(async move {
+            let outcome = operation.await;
+            match &outcome {
+                Ok(stored) => reservation.complete(stored.clone()).await,
+                Err(_) => reservation.release().await,
             }
-            command_payload["takeProfit"] = json!(take_profit);
-        }
+            outcome
+        });
 
-        if let Some(time_in_force) = time_in_force {
-            command_payload["timeInForce"] = json!(time_in_force);
+        match task.await {
+            Ok(outcome) => outcome.map(StoredResponse::into_response),
+            Err(error) => {
+                warn!(%error, "idempotent request failed before recording its outcome");
+                Err(ApiError::internal("the request failed before it completed"))
+            }
         }
+    }
 
-        if let Some(metadata) = metadata {
-            command_payload["metadata"] = metadata;
+    pub(crate) async fn evict_idempotency_records(&self, now: OffsetDateTime) -> usize {
+        self.idempotency.lock().await.evict_expired(now)
+    }
+
+    pub(crate) async fn enqueue_outbox_event(
+        &self,
+        account: &str,
+        session_id: Uuid,
+        request: OutboxEventRequest,
+    ) -> Result<OutboxEnqueueResponse, ApiError> {
+        self.enqueue_outbox_event_with(account, session_id, request, |_, _| Ok(()))
+            .await
+    }
+
+    /// Queues an outbox event after `prepare` has checked it against the
+    /// account and completed its payload, both under the same account lock.
+    async fn enqueue_outbox_event_with(
+        &self,
+        account: &str,
+        session_id: Uuid,
+        mut request: OutboxEventRequest,
+        prepare: impl FnOnce(&AccountSessions, &mut Value) -> Result<(), ApiError>,
+    ) -> Result<OutboxEnqueueResponse, ApiError> {
+        if request.event_type.trim().is_empty() {
+            return Err(ApiError::bad_request(
+                "event_type_empty",
+                "eventType must not be empty",
+            ));
         }
 
-        if let Some(client_order_id) = client_order_id {
-            let trimmed = client_order_id.trim();
-            if trimmed.is_empty() {
-                return Err(ApiError::bad_request(
-                    "client_order_id_empty",
-                    "clientOrderId must not be empty",
-                ));
-            }
-            command_payload["clientOrderId"] = Value::String(trimmed.to_string());
+        if request
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= current_time())
+        {
+            return Err(ApiError::bad_request(
+                "expires_at_elapsed",
+                "expiresAt must be in the future",
+            ));
         }
 
-        let enqueue_response = self
-            .enqueue_outbox_event(
-                account,
-                session_id,
-                OutboxEventRequest {
-                    event_type: "OrderCommand".to_string(),
-                    payload: command_payload,
-                    requires_ack: true,
-                },
+        let handle = self.account(account).await.ok_or_else(|| {
+            ApiError::unauthorized(
+                "session_missing",
+                "no active session for the supplied account",
             )
-            .await?;
+        })?;
+        let mut account_sessions = handle.lock().await;
 
-        info!(
+        if !account_sessions
+            .expire_lapsed_approvals(current_time())
+            .is_empty()
+        {
+            self.persist_sessions(account, &account_sessions).await;
+        }
+
+        prepare(&account_sessions, &mut request.payload)?;
+
+        // A payload that could never fit is not worth retrying, unlike one that
+        // only has to wait for the EA to drain its outbox.
+        let size = payload_size(&request.payload);
+        if size > self.delivery.max_bytes {
+            return Err(ApiError::payload_too_large(
+                "payload_too_large",
+                format!(
+                    "the payload is {size} bytes, above the {} byte outbox limit",
+                    self.delivery.max_bytes
+                ),
+            ));
+        }
+
+        let (session, sequences) = account_sessions
+            .session_with_sequences(&session_id)
+            .ok_or_else(|| {
+                ApiError::conflict(
+                    "session_mismatch",
+                    "the supplied session id does not match the active session",
+                )
+            })?;
+
+        if session.status == SessionStatus::Terminated {
+            return Err(ApiError::conflict(
+                "session_terminated",
+                "the session has been terminated and cannot accept outbox events",
+            ));
+        }
+
+        if !session.outbox_has_room(size, &self.delivery) {
+            warn!(
+                account = %account,
+                session = %session_id,
+                depth = session.outbox.len(),
+                bytes = session.outbox_bytes(),
+                "rejecting outbox event; session outbox is full",
+            );
+            return Err(ApiError::too_many_requests(
+                "outbox_full",
+                "the session outbox is full; retry once the EA has drained it",
+            ));
+        }
+
+        let event = session.enqueue_outbox(sequences, request);
+        let pending_session = session.status.is_pending();
+        account_sessions.commands.record_queued(session_id, &event);
+        let persisted = self.persist_sessions(account, &account_sessions).await;
+        drop(account_sessions);
+        persisted.written().await;
+
+        debug!(
             account = %account,
             session = %session_id,
-            event = %enqueue_response.event_id,
-            command = %command_id,
-            instrument = %instrument,
-            command_type = ?command_type,
-            pending = enqueue_response.pending_session,
-            "queued trade command",
+            event = %event.id,
+            pending_session,
+            "queued outbox event",
         );
 
-        Ok(TradeCommandQueued {
+        Ok(OutboxEnqueueResponse {
             session_id,
-            event_id: enqueue_response.event_id,
-            sequence: enqueue_response.sequence,
-            command_id,
-            pending_session: enqueue_response.pending_session,
-            command_type,
-            instrument,
-            order_type: response_order_type,
-            side: response_side,
-            position_id: response_position_id,
-            volume: response_volume,
+            event_id: event.id,
+            sequence: event.sequence,
+            pending_session,
         })
     }
 
-    pub async fn preapprove_session_key(
+    /// Reports how much is queued in a session outbox against the configured limits.
+    pub(crate) async fn outbox_depth(
         &self,
         account: &str,
-        auth_method: AuthMethod,
-        authentication_key: &str,
-        approved_by: Option<String>,
-        expires_at: Option<OffsetDateTime>,
-    ) -> Result<(), AdminCommandError> {
-        if authentication_key.trim().is_empty() {
-            return Err(AdminCommandError::AuthenticationKeyEmpty);
-        }
-
-        let fingerprint = hash_secret(auth_method, authentication_key, account);
-        let mut inner = self.inner.lock().await;
-        let account_sessions = inner.sessions.entry(account.to_string()).or_default();
+        session_id: Uuid,
+    ) -> Result<OutboxDepthResponse, ApiError> {
+        let handle = self.account(account).await.ok_or_else(session_not_found)?;
+        let mut account_sessions = handle.lock().await;
+        let session = account_sessions
+            .get_mut_by_session_id(&session_id)
+            .ok_or_else(session_not_found)?;
 
-        match approved_by.as_deref() {
-            Some(operator) if !operator.is_empty() => {
-                info!(
-                    account = %account,
-                    operator,
-                    auth_method = ?auth_method,
-                    "registered pre-approved authentication key",
-                );
-            }
-            _ => info!(
-                account = %account,
-                auth_method = ?auth_method,
-                "registered pre-approved authentication key",
-            ),
-        }
+        Ok(OutboxDepthResponse {
+            session_id,
+            status: session.status,
+            depth: session.outbox.len(),
+            bytes: session.outbox_bytes(),
+            max_depth: self.delivery.max_depth,
+            max_bytes: self.delivery.max_bytes,
+            dead_letters: session.dead_letters.len(),
+            oldest_enqueued_at: session.outbox.first().map(|event| event.enqueued_at),
+        })
+    }
 
-        account_sessions.register_preapproval(
-            fingerprint,
-            PreapprovalRecord {
-                approved_by,
-                expires_at,
-            },
-        );
+    /// Lists the outbox events of a session that were dead-lettered after
+    /// exhausting their delivery attempts.
+    pub(crate) async fn dead_letters(
+        &self,
+        account: &str,
+        session_id: Uuid,
+    ) -> Result<DeadLetterListResponse, ApiError> {
+        let handle = self.account(account).await.ok_or_else(session_not_found)?;
+        let mut account_sessions = handle.lock().await;
+        let session = account_sessions
+            .get_mut_by_session_id(&session_id)
+            .ok_or_else(session_not_found)?;
 
-        Ok(())
+        Ok(DeadLetterListResponse {
+            session_id,
+            dead_letters: session.dead_letters.clone(),
+        })
     }
 
-    pub async fn promote_session_with_secret(
+    /// Puts a dead-lettered event back into the session outbox with a fresh
+    /// delivery budget.
+    pub(crate) async fn requeue_dead_letter(
         &self,
         account: &str,
         session_id: Uuid,
-        authentication_key: &str,
-    ) -> Result<SessionPromotionResponse, AdminCommandError> {
-        if authentication_key.trim().is_empty() {
-            return Err(AdminCommandError::AuthenticationKeyEmpty);
+        event_id: Uuid,
+    ) -> Result<OutboxEnqueueResponse, ApiError> {
+        let handle = self.account(account).await.ok_or_else(session_not_found)?;
+        let mut account_sessions = handle.lock().await;
+        let (session, sequences) = account_sessions
+            .session_with_sequences(&session_id)
+            .ok_or_else(session_not_found)?;
+
+        if session.status == SessionStatus::Terminated {
+            return Err(ApiError::conflict(
+                "session_terminated",
+                "the session has been terminated and cannot accept outbox events",
+            ));
         }
 
-        let fingerprint = {
-            let mut inner = self.inner.lock().await;
-            let account_sessions = inner
-                .sessions
-                .get_mut(account)
-                .ok_or(AdminCommandError::SessionMissing)?;
-            let session = account_sessions
-                .get_mut_by_session_id(&session_id)
-                .ok_or(AdminCommandError::SessionMismatch)?;
-            hash_secret(session.auth_method, authentication_key, account)
+        let event = session
+            .requeue_dead_letter(sequences, event_id)
+            .ok_or_else(|| {
+                ApiError::not_found(
+                    "dead_letter_missing",
+                    "no dead-lettered event with the supplied identifier",
+                )
+            })?;
+        let pending_session = session.status.is_pending();
+        self.persist_sessions(account, &account_sessions).await;
+
+        info!(
+            account = %account,
+            session = %session_id,
+            event = %event.id,
+            sequence = event.sequence,
+            "requeued dead-lettered outbox event",
+        );
+
+        Ok(OutboxEnqueueResponse {
+            session_id,
+            event_id: event.id,
+            sequence: event.sequence,
+            pending_session,
+        })
+    }
+
+    /// Returns the balances, positions and pending orders last reported by
+    /// the account's EA.
+    pub(crate) async fn portfolio(&self, account: &str) -> Result<PortfolioResponse, ApiError> {
+        let not_found = || {
+            ApiError::not_found(
+                "portfolio_missing",
+                "no portfolio has been reported for the supplied account",
+            )
         };
+        let handle = self.account(account).await.ok_or_else(not_found)?;
+        let account_sessions = handle.lock().await;
+        if account_sessions.portfolio.is_empty() {
+            return Err(not_found());
+        }
 
-        self.promote_session_internal(account, session_id, fingerprint, None)
-            .await
+        Ok(PortfolioResponse {
+            account_id: account.to_string(),
+            portfolio: account_sessions.portfolio.clone(),
+        })
     }
 
-    pub async fn promote_session_with_fingerprint(
+    /// Looks up the lifecycle of a trade command by its `commandId`.
+    pub(crate) async fn command(
         &self,
         account: &str,
-        session_id: Uuid,
-        fingerprint: &str,
-        operator: Option<&str>,
-    ) -> Result<SessionPromotionResponse, AdminCommandError> {
-        self.promote_session_internal(account, session_id, fingerprint.to_string(), operator)
-            .await
+        command_id: Uuid,
+    ) -> Result<CommandRecord, ApiError> {
+        let not_found = || {
+            ApiError::not_found(
+                "command_missing",
+                "no trade command with the supplied identifier",
+            )
+        };
+        let handle = self.account(account).await.ok_or_else(not_found)?;
+        let account_sessions = handle.lock().await;
+        account_sessions
+            .commands
+            .get(command_id)
+            .cloned()
+            .ok_or_else(not_found)
     }
 
-    pub async fn reject_session(
+    /// Lists tracked trade commands, optionally only those queued with `clientOrderId`.
+    pub(crate) async fn commands(
+        &self,
+        account: &str,
+        query: CommandQuery,
+    ) -> Result<CommandListResponse, ApiError> {
+        let commands = match self.account(account).await {
+            Some(handle) => handle
+                .lock()
+                .await
+                .commands
+                .find(query.client_order_id.as_deref())
+                .into_iter()
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Ok(CommandListResponse {
+            account_id: account.to_string(),
+            commands,
+        })
+    }
+
+    /// Queues the acknowledged events numbered `fromSequence` or later into
+    /// the session outbox again, marked as replays. Events whose `expiresAt`
+    /// has passed are skipped rather than replayed.
+    pub(crate) async fn resync_outbox(
         &self,
         account: &str,
         session_id: Uuid,
-        fingerprint: &str,
-        reason: Option<String>,
-        rejected_by: Option<String>,
-    ) -> Result<SessionRejectionResponse, AdminCommandError> {
-        let mut inner = self.inner.lock().await;
-        let account_sessions = inner
-            .sessions
-            .get_mut(account)
-            .ok_or(AdminCommandError::SessionMissing)?;
-        let (outcome, auth_hash, session_token) = {
-            let session = account_sessions
-                .get_mut_by_session_id(&session_id)
-                .ok_or(AdminCommandError::SessionMismatch)?;
+        request: ResyncRequest,
+    ) -> Result<ResyncResponse, ApiError> {
+        let handle = self.account(account).await.ok_or_else(session_not_found)?;
+        let mut account_sessions = handle.lock().await;
+        let now = current_time();
 
-            if !session.verify_secret(fingerprint) {
-                return Err(AdminCommandError::AuthenticationFailed);
-            }
+        account_sessions
+            .acknowledged
+            .prune(self.delivery.ack_history_retention, now);
+        let history = account_sessions.acknowledged.since(request.from_sequence);
+        let (session, sequences) = account_sessions
+            .session_with_sequences(&session_id)
+            .ok_or_else(session_not_found)?;
 
-            let auth_hash = session.auth_key_hash.clone();
-            let session_token = session.session_token;
-            let outcome = session.reject(reason.clone(), rejected_by.clone());
-            (outcome, auth_hash, session_token)
-        };
+        if session.status == SessionStatus::Terminated {
+            return Err(ApiError::conflict(
+                "session_terminated",
+                "the session has been terminated and cannot accept outbox events",
+            ));
+        }
 
-        if outcome.already_terminated {
-            debug!(
+        let (replayable, expired): (Vec<_>, Vec<_>) = history
+            .into_iter()
+            .partition(|event| !event.is_expired(now));
+        let depth = session.outbox.len() + replayable.len();
+        let bytes = session.outbox_bytes()
+            + replayable
+                .iter()
+                .map(|event| payload_size(&event.payload))
+                .sum::<usize>();
+        if depth > self.delivery.max_depth || bytes > self.delivery.max_bytes {
+            warn!(
                 account = %account,
                 session = %session_id,
-                "reject command ignored; session already terminated"
+                depth,
+                bytes,
+                "rejecting resync; replayed events would overflow the session outbox",
             );
-        } else {
-            account_sessions.remove_from_active_index(&auth_hash, session_token);
-            match rejected_by.as_deref() {
-                Some(operator) if !operator.is_empty() => {
-                    warn!(
-                        account = %account,
-                        session = %session_id,
-                        operator,
-                        reason = ?reason,
-                        "session rejected"
-                    );
-                }
-                _ => {
-                    warn!(
-                        account = %account,
-                        session = %session_id,
-                        reason = ?reason,
-                        "session rejected"
-                    );
-                }
-            }
+            return Err(ApiError::too_many_requests(
+                "outbox_full",
+                "the session outbox cannot hold the replayed events; retry once the EA has drained it",
+            ));
         }
 
-        Ok(outcome.response)
+        let replayed: Vec<ReplayedEvent> = replayable
+            .into_iter()
+            .map(|event| {
+                let original_sequence = event.sequence;
+                let replay = session.replay_outbox_event(sequences, event);
+                ReplayedEvent {
+                    event_id: replay.id,
+                    original_event_id: replay.original_event_id.unwrap_or(replay.id),
+                    sequence: replay.sequence,
+                    original_sequence,
+                }
+            })
+            .collect();
+        let pending_session = session.status.is_pending();
+        self.persist_sessions(account, &account_sessions).await;
+
+        info!(
+            account = %account,
+            session = %session_id,
+            from_sequence = request.from_sequence,
+            replayed = replayed.len(),
+            skipped_expired = expired.len(),
+            reason = ?request.reason,
+            "replayed acknowledged outbox events",
+        );
+        OperationalEvent::new(
+            "outbox.resync",
+            account,
+            request.from_sequence,
+            json!({
+                "sessionId": session_id,
+                "replayed": replayed.len(),
+                "skippedExpired": expired.len(),
+                "reason": request.reason,
+            }),
+        )
+        .emit();
+
+        Ok(ResyncResponse {
+            session_id,
+            from_sequence: request.from_sequence,
+            replayed,
+            skipped_expired: expired.len(),
+            pending_session,
+        })
     }
 
-    pub async fn apply_admin_command(
+    /// Records inbound events for the session identified by `token`.
+    async fn capture_inbox(
         &self,
-        command: AdminCommand,
-    ) -> Result<AdminCommandOutcome, AdminCommandError> {
-        match command {
-            AdminCommand::Approve(command) => {
-                let AdminApprovalCommand {
-                    account_id,
-                    session_id,
-                    auth_key_fingerprint,
-                    approved_by,
-                    expires_at: _,
-                } = command;
+        account: &str,
+        token: Uuid,
+        events: Vec<InboxEvent>,
+    ) -> Result<InboxResponse, ApiError> {
+        let Some(handle) = self.account(account).await else {
+            return Err(ApiError::unauthorized(
+                "session_missing",
+                "no active session for the supplied account",
+            ));
+        };
+        let mut account_sessions = handle.lock().await;
+
+        let (accepted, duplicates, rejected, pending) = {
+            if !account_sessions
+                .expire_lapsed_approvals(current_time())
+                .is_empty()
+            {
+                self.persist_sessions(account, &account_sessions).await;
+            }
 
-                let response = self
-                    .promote_session_with_fingerprint(
-                        &account_id,
-                        session_id,
-                        &auth_key_fingerprint,
-                        approved_by.as_deref(),
-                    )
-                    .await?;
+            let Some((session, sequences)) =
+                account_sessions.session_with_sequences_by_token(&token)
+            else {
+                return Err(ApiError::unauthorized(
+                    "invalid_session_token",
+                    "the provided session token is not valid for this account",
+                ));
+            };
 
-                Ok(AdminCommandOutcome::SessionAuthenticated(response))
+            if session.status == SessionStatus::Terminated {
+                return Err(ApiError::forbidden(
+                    "session_terminated",
+                    "the session has been terminated and no longer accepts events",
+                ));
             }
-            AdminCommand::Reject(command) => {
-                let AdminRejectionCommand {
-                    account_id,
-                    session_id,
-                    auth_key_fingerprint,
-                    rejected_by,
-                    reason,
-                } = command;
-
-                let response = self
-                    .reject_session(
-                        &account_id,
-                        session_id,
-                        &auth_key_fingerprint,
-                        reason,
-                        rejected_by,
-                    )
-                    .await?;
 
-                Ok(AdminCommandOutcome::SessionRejected(response))
+            let captured = session.capture_inbox(sequences, events);
+            let accepted = captured.records.len();
+            let pending = session.status.is_pending();
+            debug!(
+                account = %account,
+                captured = accepted,
+                duplicates = captured.duplicates,
+                "captured inbox events",
+            );
+            for rejection in &captured.rejected {
+                warn!(
+                    account = %account,
+                    session = %session.session_id,
+                    index = rejection.index,
+                    event_type = %rejection.event_type,
+                    error = %rejection.message,
+                    "rejected malformed inbox event",
+                );
             }
-        }
+            let now = current_time();
+            account_sessions.record_acknowledged(captured.acknowledged, &self.delivery, now);
+            for update in &captured.account_updates {
+                match update {
+                    InboxPayload::SyncSnapshot(snapshot) => {
+                        account_sessions.portfolio.apply_snapshot(snapshot, now)
+                    }
+                    InboxPayload::ExecutionReport(report) => {
+                        let applied = account_sessions.portfolio.apply_execution(report, now);
+                        let closed = account_sessions
+                            .commands
+                            .record_execution(report, now)
+                            .and_then(|command| command.closed_position());
+                        if let (false, Some(ticket), Some(volume)) =
+                            (applied, closed, report.volume)
+                        {
+                            account_sessions
+                                .portfolio
+                                .reduce_position(ticket, volume, now);
+                        }
+                    }
+                    InboxPayload::OutboxAck(OutboxAck {
+                        command_id: Some(command_id),
+                        ..
+                    }) => account_sessions.commands.acknowledge(*command_id, now),
+                    _ => {}
+                }
+            }
+            (accepted, captured.duplicates, captured.rejected, pending)
+        };
+        self.persist_sessions(account, &account_sessions).await;
+
+        Ok(InboxResponse {
+            accepted,
+            duplicates,
+            rejected,
+            pending_session: pending,
+        })
     }
 
-    async fn promote_session_internal(
+    /// Removes an acknowledged event from the outbox of the session identified by `token`.
+    async fn acknowledge_outbox(
         &self,
         account: &str,
-        session_id: Uuid,
-        fingerprint: String,
-        operator: Option<&str>,
-    ) -> Result<SessionPromotionResponse, AdminCommandError> {
-        let mut inner = self.inner.lock().await;
-        let account_sessions = inner
-            .sessions
-            .get_mut(account)
-            .ok_or(AdminCommandError::SessionMissing)?;
-        let session = account_sessions
-            .get_mut_by_session_id(&session_id)
-            .ok_or(AdminCommandError::SessionMismatch)?;
+        token: Uuid,
+        event_id: Uuid,
+    ) -> Result<AckResponse, ApiError> {
+        let Some(handle) = self.account(account).await else {
+            return Err(ApiError::unauthorized(
+                "session_missing",
+                "no active session for the supplied account",
+            ));
+        };
+        let mut account_sessions = handle.lock().await;
 
-        let was_pending = session.status.is_pending();
-        let response = session.promote(&fingerprint)?;
+        let remaining = {
+            let Some(session) = account_sessions.get_mut_by_token(&token) else {
+                return Err(ApiError::unauthorized(
+                    "invalid_session_token",
+                    "the provided session token is not valid for this account",
+                ));
+            };
 
-        if was_pending && response.status == SessionStatus::Authenticated {
-            match operator {
-                Some(operator) if !operator.is_empty() => {
-                    info!(
-                        account = %account,
-                        session = %session_id,
-                        operator,
-                        "session authenticated"
-                    );
-                }
-                _ => {
-                    info!(account = %account, session = %session_id, "session authenticated");
-                }
-            }
-        }
+            let Some(event) = session.acknowledge_outbox(event_id) else {
+                return Err(ApiError::not_found(
+                    "event_not_found",
+                    "no outbox event with the supplied identifier",
+                ));
+            };
 
-        Ok(response)
-    }
-}
-
-#[cfg(test)]
-impl AppState {
-    async fn insert_session_for_test(&self, account: &str, session: SessionRecord) {
-        let mut inner = self.inner.lock().await;
-        inner
-            .sessions
-            .entry(account.to_string())
-            .or_default()
-            .insert(session);
-    }
+            let remaining = session.outbox.len();
+            account_sessions.record_acknowledged(vec![event], &self.delivery, current_time());
+            remaining
+        };
+        self.persist_sessions(account, &account_sessions).await;
 
-    async fn outbox_events_for_test(&self, account: &str, session_id: Uuid) -> Vec<OutboundEvent> {
-        let mut inner = self.inner.lock().await;
-        inner
-            .sessions
-            .get_mut(account)
-            .and_then(|sessions| sessions.get_mut_by_session_id(&session_id))
-            .map(|session| session.outbox.clone())
-            .unwrap_or_default()
+        Ok(AckResponse {
+            acknowledged_event_id: event_id,
+            remaining_outbox_depth: remaining,
+        })
     }
-}
-
-/// Health status payload emitted by the service.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct HealthStatus {
-    /// Name of the component being checked.
-    pub component: &'static str,
-    /// Human-readable description of the current state.
-    pub message: &'static str,
-    /// Indicates whether the component is healthy.
-    pub healthy: bool,
-}
 
-impl HealthStatus {
-    /// Creates a successful health check response for the provided component.
-    pub const fn ok(component: &'static str) -> Self {
-        Self {
-            component,
-            message: "ok",
-            healthy: true,
+    /// Acknowledges the listed events and, with `ackThrough`, every event
+    /// numbered at or below it, reporting the outcome for each event.
+    async fn acknowledge_outbox_batch(
+        &self,
+        account: &str,
+        token: Uuid,
+        request: OutboxBatchAckRequest,
+    ) -> Result<OutboxBatchAckResponse, ApiError> {
+        if request.event_ids.is_empty() && request.ack_through.is_none() {
+            return Err(ApiError::bad_request(
+                "ack_batch_empty",
+                "supply eventIds, ackThrough, or both",
+            ));
         }
-    }
-}
 
-/// Returns the health status of the gateway service.
-pub fn health_check() -> HealthStatus {
-    HealthStatus::ok("gateway")
-}
+        let Some(handle) = self.account(account).await else {
+            return Err(ApiError::unauthorized(
+                "session_missing",
+                "no active session for the supplied account",
+            ));
+        };
+        let mut account_sessions = handle.lock().await;
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct ErrorBody {
-    code: &'static str,
-    message: String,
-}
+        let (mut results, acknowledged, remaining) = {
+            let Some(session) = account_sessions.get_mut_by_token(&token) else {
+                return Err(ApiError::unauthorized(
+                    "invalid_session_token",
+                    "the provided session token is not valid for this account",
+                ));
+            };
 
-#[derive(Debug, Error)]
-#[error("{message}")]
-pub(crate) struct ApiError {
-    status: StatusCode,
-    code: &'static str,
-    message: String,
-}
+            let mut results = Vec::with_capacity(request.event_ids.len());
+            let mut acknowledged = Vec::new();
+            for event_id in request.event_ids {
+                match session.acknowledge_outbox(event_id) {
+                    Some(event) => {
+                        results.push(OutboxAckResult::acknowledged(&event));
+                        acknowledged.push(event);
+                    }
+                    None => results.push(OutboxAckResult {
+                        event_id,
+                        sequence: None,
+                        status: OutboxAckStatus::Unknown,
+                    }),
+                }
+            }
 
-impl ApiError {
-    fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
-        Self {
-            status: StatusCode::BAD_REQUEST,
-            code,
-            message: message.into(),
-        }
-    }
+            if let Some(sequence) = request.ack_through {
+                for event in session.acknowledge_through(sequence) {
+                    results.push(OutboxAckResult::acknowledged(&event));
+                    acknowledged.push(event);
+                }
+            }
 
-    fn unauthorized(code: &'static str, message: impl Into<String>) -> Self {
-        Self {
-            status: StatusCode::UNAUTHORIZED,
-            code,
-            message: message.into(),
-        }
-    }
+            (results, acknowledged, session.outbox.len())
+        };
 
-    fn forbidden(code: &'static str, message: impl Into<String>) -> Self {
-        Self {
-            status: StatusCode::FORBIDDEN,
-            code,
-            message: message.into(),
+        let acknowledged_count = acknowledged.len();
+        if acknowledged_count > 0 {
+            account_sessions.record_acknowledged(acknowledged, &self.delivery, current_time());
+            self.persist_sessions(account, &account_sessions).await;
         }
-    }
 
-    fn conflict(code: &'static str, message: impl Into<String>) -> Self {
-        Self {
-            status: StatusCode::CONFLICT,
-            code,
-            message: message.into(),
+        for result in &mut results {
+            if result.status != OutboxAckStatus::Unknown {
+                continue;
+            }
+            if let Some(sequence) = account_sessions.acknowledged.sequence_of(result.event_id) {
+                result.status = OutboxAckStatus::AlreadyAcknowledged;
+                result.sequence = Some(sequence);
+            }
         }
-    }
 
-    fn not_found(code: &'static str, message: impl Into<String>) -> Self {
-        Self {
-            status: StatusCode::NOT_FOUND,
-            code,
-            message: message.into(),
-        }
-    }
+        debug!(
+            account = %account,
+            acknowledged = acknowledged_count,
+            remaining,
+            "acknowledged outbox batch",
+        );
 
-    fn internal(message: impl Into<String>) -> Self {
-        Self {
-            status: StatusCode::INTERNAL_SERVER_ERROR,
-            code: "internal_error",
-            message: message.into(),
-        }
+        Ok(OutboxBatchAckResponse {
+            acknowledged: acknowledged_count,
+            results,
+            remaining_outbox_depth: remaining,
+        })
     }
 
-    pub(crate) fn code(&self) -> &'static str {
-        self.code
-    }
+    pub(crate) async fn enqueue_trade_command(
+        &self,
+        account: &str,
+        session_id: Uuid,
+        request: TradeOrderRequest,
+    ) -> Result<TradeCommandQueued, ApiError> {
+        let TradeOrderRequest {
+            command_type,
+            instrument,
+            order_type,
+            side,
+            volume,
+            price,
+            stop_loss,
+            take_profit,
+            time_in_force,
+            position_id,
+            order_id,
+            original_command_id,
+            original_client_order_id,
+            client_order_id,
+            metadata,
+            expires_at,
+        } = request;
 
-    pub(crate) fn message(&self) -> &str {
-        &self.message
-    }
+        let instrument = instrument.trim();
+        if instrument.is_empty() {
+            return Err(ApiError::bad_request(
+                "instrument_empty",
+                "instrument must not be empty",
+            ));
+        }
+        let instrument = instrument.to_string();
 
-    pub(crate) fn status(&self) -> StatusCode {
-        self.status
-    }
-}
+        let command_id = Uuid::new_v4();
+        let issued_at = current_time();
+        let expires_at = expires_at.or_else(|| {
+            self.delivery
+                .command_ttl(command_type)
+                .map(|ttl| issued_at + ttl)
+        });
 
-impl IntoResponse for ApiError {
-    fn into_response(self) -> Response {
-        let body = Json(ErrorBody {
-            code: self.code,
-            message: self.message,
+        let mut command_payload = json!({
+            "commandId": command_id,
+            "commandType": command_type,
+            "instrument": instrument,
+            "issuedAt": timestamp::to_value(issued_at),
         });
-        (self.status, body).into_response()
-    }
-}
 
-#[derive(Clone)]
-struct StoredResponse {
-    status: StatusCode,
-    body: Option<Value>,
-    headers: Vec<(String, String)>,
-}
+        let mut response_order_id = None;
+        let mut response_original_command_id = None;
+        // The position a partial close reduces and the pending order a cancel
+        // names by the command that placed it, checked under the same lock
+        // that queues the command.
+        let mut partial_close = None;
+        let mut cancel_lookup = None;
+        let (response_order_type, response_side, response_position_id, response_volume) =
+            match command_type {
+                TradeCommandType::Open => {
+                    let side = side.ok_or_else(|| {
+                        ApiError::bad_request(
+                            "side_required",
+                            "side is required when opening a position",
+                        )
+                    })?;
 
-impl StoredResponse {
-    fn from_json<T>(status: StatusCode, payload: &T) -> Result<Self, serde_json::Error>
-    where
-        T: Serialize,
-    {
-        Ok(Self {
-            status,
-            body: Some(serde_json::to_value(payload)?),
-            headers: Vec::new(),
-        })
-    }
+                    let order_type = order_type.ok_or_else(|| {
+                        ApiError::bad_request(
+                            "order_type_required",
+                            "orderType is required when opening a position",
+                        )
+                    })?;
 
-    fn empty(status: StatusCode) -> Self {
-        Self {
-            status,
-            body: None,
-            headers: Vec::new(),
-        }
-    }
+                    let volume = volume.ok_or_else(|| {
+                        ApiError::bad_request(
+                            "volume_required",
+                            "volume is required when opening a position",
+                        )
+                    })?;
 
-    fn into_response(self) -> Response {
-        let mut response = if let Some(body) = self.body {
-            (self.status, Json(body)).into_response()
-        } else {
-            Response::builder()
-                .status(self.status)
-                .body(Body::empty())
-                .unwrap_or_else(|_| Response::new(Body::empty()))
-        };
+                    if !volume.is_finite() || volume <= 0.0 {
+                        return Err(ApiError::bad_request(
+                            "volume_invalid",
+                            "volume must be greater than zero",
+                        ));
+                    }
 
-        for (name, value) in self.headers {
-            if let (Ok(name), Ok(value)) = (
-                HeaderName::from_bytes(name.as_bytes()),
-                HeaderValue::from_str(&value),
-            ) {
-                response.headers_mut().insert(name, value);
-            }
-        }
+                    command_payload["side"] = json!(side);
+                    command_payload["orderType"] = json!(order_type);
+                    command_payload["volume"] = json!(volume);
 
-        response
-    }
-}
+                    match order_type {
+                        TradeOrderType::Limit
+                        | TradeOrderType::Stop
+                        | TradeOrderType::StopLimit => {
+                            let price = price.ok_or_else(|| {
+                                ApiError::bad_request(
+                                    "price_required",
+                                    "price is required for limit and stop orders",
+                                )
+                            })?;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "snake_case")]
-pub enum SessionStatus {
-    Pending,
-    Authenticated,
+                            if !price.is_finite() || price <= 0.0 {
+                                return Err(ApiError::bad_request(
+                                    "price_invalid",
+                                    "price must be greater than zero",
+                                ));
+                            }
+
+                            command_payload["price"] = json!(price);
+                        }
+                        TradeOrderType::Market => {
+                            if let Some(price) = price {
+                                if !price.is_finite() || price <= 0.0 {
+                                    return Err(ApiError::bad_request(
+                                        "price_invalid",
+                                        "price must be greater than zero",
+                                    ));
+                                }
+                                command_payload["price"] = json!(price);
+                            }
+                        }
+                    }
+
+                    Ok((Some(order_type), Some(side), None, Some(volume)))
+                }
+                TradeCommandType::Close => {
+                    let position_id = position_id
+                        .as_deref()
+                        .map(str::trim)
+                        .filter(|value| !value.is_empty())
+                        .ok_or_else(|| {
+                            ApiError::bad_request(
+                                "position_id_required",
+                                "positionId is required when closing a position",
+                            )
+                        })?;
+
+                    command_payload["positionId"] = Value::String(position_id.to_string());
+
+                    let order_type = order_type.unwrap_or(TradeOrderType::Market);
+                    command_payload["orderType"] = json!(order_type);
+
+                    let mut response_side = None;
+                    if let Some(side) = side {
+                        command_payload["side"] = json!(side);
+                        response_side = Some(side);
+                    }
+
+                    let mut response_volume = None;
+                    if let Some(volume) = volume {
+                        if !volume.is_finite() || volume <= 0.0 {
+                            return Err(ApiError::bad_request(
+                                "volume_invalid",
+                                "volume must be greater than zero",
+                            ));
+                        }
+                        partial_close = Some((position_id.to_string(), volume));
+                        command_payload["volume"] = json!(volume);
+                        response_volume = Some(volume);
+                    }
+
+                    if let Some(price) = price {
+                        if !price.is_finite() || price <= 0.0 {
+                            return Err(ApiError::bad_request(
+                                "price_invalid",
+                                "price must be greater than zero",
+                            ));
+                        }
+                        command_payload["price"] = json!(price);
+                    }
+
+                    Ok((
+                        Some(order_type),
+                        response_side,
+                        Some(position_id.to_string()),
+                        response_volume,
+                    ))
+                }
+                TradeCommandType::Modify => {
+                    let non_empty = |value: Option<String>| {
+                        value
+                            .map(|value| value.trim().to_string())
+                            .filter(|value| !value.is_empty())
+                    };
+                    let (position_id, order_id) = (non_empty(position_id), non_empty(order_id));
+                    if position_id.is_some() == order_id.is_some() {
+                        return Err(ApiError::bad_request(
+                            "modify_target_required",
+                            "exactly one of positionId or orderId is required when modifying",
+                        ));
+                    }
+
+                    if stop_loss.is_none() && take_profit.is_none() && price.is_none() {
+                        return Err(ApiError::bad_request(
+                            "modify_fields_required",
+                            "at least one of stopLoss, takeProfit or price must be supplied",
+                        ));
+                    }
+
+                    if side.is_some() || order_type.is_some() || time_in_force.is_some() {
+                        return Err(ApiError::bad_request(
+                            "modify_fields_not_allowed",
+                            "side, orderType and timeInForce cannot be set on a modify command",
+                        ));
+                    }
+
+                    if volume.is_some() {
+                        return Err(ApiError::bad_request(
+                            "volume_not_modifiable",
+                            "volume cannot be changed by a modify command",
+                        ));
+                    }
+
+                    if let Some(price) = price {
+                        if order_id.is_none() {
+                            return Err(ApiError::bad_request(
+                                "price_not_modifiable",
+                                "price can only be changed on a pending order",
+                            ));
+                        }
+                        if !price.is_finite() || price <= 0.0 {
+                            return Err(ApiError::bad_request(
+                                "price_invalid",
+                                "price must be greater than zero",
+                            ));
+                        }
+                        command_payload["price"] = json!(price);
+                    }
+
+                    if let Some(position_id) = &position_id {
+                        command_payload["positionId"] = Value::String(position_id.clone());
+                    }
+                    if let Some(order_id) = &order_id {
+                        command_payload["orderId"] = Value::String(order_id.clone());
+                    }
+                    response_order_id = order_id;
+
+                    Ok((None, None, position_id, None))
+                }
+                TradeCommandType::Cancel => {
+                    let order_id = order_id
+                        .map(|value| value.trim().to_string())
+                        .filter(|value| !value.is_empty());
+                    let original_client_order_id = original_client_order_id
+                        .map(|value| value.trim().to_string())
+                        .filter(|value| !value.is_empty());
+                    let targets = [
+                        order_id.is_some(),
+                        original_command_id.is_some(),
+                        original_client_order_id.is_some(),
+                    ];
+                    if targets.into_iter().filter(|target| *target).count() != 1 {
+                        return Err(ApiError::bad_request(
+                            "cancel_target_required",
+                            "exactly one of orderId, originalCommandId or originalClientOrderId is required when cancelling",
+                        ));
+                    }
+
+                    if volume.is_some()
+                        || price.is_some()
+                        || stop_loss.is_some()
+                        || take_profit.is_some()
+                        || side.is_some()
+                        || order_type.is_some()
+                        || time_in_force.is_some()
+                    {
+                        return Err(ApiError::bad_request(
+                            "cancel_fields_not_allowed",
+                            "volume, price, stopLoss, takeProfit, side, orderType and timeInForce cannot be set on a cancel command",
+                        ));
+                    }
+
+                    if order_id.is_none() {
+                        cancel_lookup =
+                            Some((original_command_id, original_client_order_id.clone()));
+                    }
+                    if let Some(original_client_order_id) = original_client_order_id {
+                        command_payload["originalClientOrderId"] =
+                            Value::String(original_client_order_id);
+                    }
+                    if let Some(order_id) = &order_id {
+                        command_payload["orderId"] = Value::String(order_id.clone());
+                    }
+                    response_order_id = order_id;
+
+                    Ok((None, None, None, None))
+                }
+            }?;
+
+        let clearable = command_type == TradeCommandType::Modify;
+        if let Some(stop_loss) =
+            protective_level(stop_loss, clearable, "stopLoss", "stop_loss_invalid")?
+        {
+            command_payload["stopLoss"] = json!(stop_loss);
+        }
+
+        if let Some(take_profit) =
+            protective_level(take_profit, clearable, "takeProfit", "take_profit_invalid")?
+        {
+            command_payload["takeProfit"] = json!(take_profit);
+        }
+
+        if let Some(time_in_force) = time_in_force {
+            command_payload["timeInForce"] = json!(time_in_force);
+        }
+
+        if let Some(metadata) = metadata {
+            command_payload["metadata"] = metadata;
+        }
+
+        if let Some(expires_at) = expires_at {
+            command_payload["expiresAt"] = timestamp::to_value(expires_at);
+        }
+
+        if let Some(client_order_id) = client_order_id {
+            let trimmed = client_order_id.trim();
+            if trimmed.is_empty() {
+                return Err(ApiError::bad_request(
+                    "client_order_id_empty",
+                    "clientOrderId must not be empty",
+                ));
+            }
+            command_payload["clientOrderId"] = Value::String(trimmed.to_string());
+        }
+
+        let event_type = match command_type {
+            TradeCommandType::Cancel => "OrderCancel",
+            _ => "OrderCommand",
+        };
+        let resolve = |account_sessions: &AccountSessions, payload: &mut Value| {
+            if let Some((position_id, volume)) = partial_close {
+                check_close_volume(account, account_sessions, &position_id, volume)?;
+            }
+            if let Some((command_id, client_order_id)) = cancel_lookup {
+                let original = cancel_target(
+                    &account_sessions.commands,
+                    command_id,
+                    client_order_id.as_deref(),
+                )?;
+                payload["originalCommandId"] = json!(original.command_id());
+                response_original_command_id = Some(original.command_id());
+                if let Some(order) = original.order() {
+                    payload["orderId"] = Value::String(order.to_string());
+                    response_order_id = Some(order.to_string());
+                }
+            }
+            Ok(())
+        };
+        let enqueue_response = self
+            .enqueue_outbox_event_with(
+                account,
+                session_id,
+                OutboxEventRequest {
+                    event_type: event_type.to_string(),
+                    payload: command_payload,
+                    requires_ack: true,
+                    expires_at,
+                },
+                resolve,
+            )
+            .await?;
+
+        info!(
+            account = %account,
+            session = %session_id,
+            event = %enqueue_response.event_id,
+            command = %command_id,
+            instrument = %instrument,
+            command_type = ?command_type,
+            pending = enqueue_response.pending_session,
+            "queued trade command",
+        );
+
+        Ok(TradeCommandQueued {
+            session_id,
+            event_id: enqueue_response.event_id,
+            sequence: enqueue_response.sequence,
+            command_id,
+            pending_session: enqueue_response.pending_session,
+            command_type,
+            instrument,
+            order_type: response_order_type,
+            side: response_side,
+            position_id: response_position_id,
+            order_id: response_order_id,
+            original_command_id: response_original_command_id,
+            volume: response_volume,
+            expires_at,
+        })
+    }
+
+    pub async fn preapprove_session_key(
+        &self,
+        account: &str,
+        auth_method: AuthMethod,
+        authentication_key: &str,
+        approved_by: Option<String>,
+        expires_at: Option<OffsetDateTime>,
+    ) -> Result<(), AdminCommandError> {
+        if authentication_key.trim().is_empty() {
+            return Err(AdminCommandError::AuthenticationKeyEmpty);
+        }
+
+        let fingerprint = hash_secret(auth_method, authentication_key, account);
+        let handle = self.account_or_default(account).await;
+        let mut account_sessions = handle.lock().await;
+
+        match approved_by.as_deref() {
+            Some(operator) if !operator.is_empty() => {
+                info!(
+                    account = %account,
+                    operator,
+                    auth_method = ?auth_method,
+                    "registered pre-approved authentication key",
+                );
+            }
+            _ => info!(
+                account = %account,
+                auth_method = ?auth_method,
+                "registered pre-approved authentication key",
+            ),
+        }
+
+        account_sessions.register_preapproval(
+            fingerprint,
+            PreapprovalRecord {
+                approved_by,
+                expires_at,
+            },
+        );
+        self.persist_sessions(account, &account_sessions).await;
+
+        Ok(())
+    }
+
+    pub async fn promote_session_with_secret(
+        &self,
+        account: &str,
+        session_id: Uuid,
+        authentication_key: &str,
+    ) -> Result<SessionPromotionResponse, AdminCommandError> {
+        if authentication_key.trim().is_empty() {
+            return Err(AdminCommandError::AuthenticationKeyEmpty);
+        }
+
+        let fingerprint = {
+            let handle = self
+                .account(account)
+                .await
+                .ok_or(AdminCommandError::SessionMissing)?;
+            let mut account_sessions = handle.lock().await;
+            let session = account_sessions
+                .get_mut_by_session_id(&session_id)
+                .ok_or(AdminCommandError::SessionMismatch)?;
+            hash_secret(session.auth_method, authentication_key, account)
+        };
+
+        self.promote_session_internal(account, session_id, fingerprint, None, None)
+            .await
+    }
+
+    pub async fn promote_session_with_fingerprint(
+        &self,
+        account: &str,
+        session_id: Uuid,
+        fingerprint: &str,
+        operator: Option<&str>,
+    ) -> Result<SessionPromotionResponse, AdminCommandError> {
+        self.promote_session_internal(account, session_id, fingerprint.to_string(), operator, None)
+            .await
+    }
+
+    pub async fn reject_session(
+        &self,
+        account: &str,
+        session_id: Uuid,
+        fingerprint: &str,
+        reason: Option<String>,
+        rejected_by: Option<String>,
+    ) -> Result<SessionRejectionResponse, AdminCommandError> {
+        let handle = self
+            .account(account)
+            .await
+            .ok_or(AdminCommandError::SessionMissing)?;
+        let mut account_sessions = handle.lock().await;
+        let (outcome, auth_hash, session_token) = {
+            let (session, sequences) = account_sessions
+                .session_with_sequences(&session_id)
+                .ok_or(AdminCommandError::SessionMismatch)?;
+
+            if !session.verify_secret(fingerprint) {
+                return Err(AdminCommandError::AuthenticationFailed);
+            }
+
+            let auth_hash = session.auth_key_hash.clone();
+            let session_token = session.session_token;
+            let outcome = session.reject(sequences, reason.clone(), rejected_by.clone());
+            (outcome, auth_hash, session_token)
+        };
+
+        if outcome.already_terminated {
+            debug!(
+                account = %account,
+                session = %session_id,
+                "reject command ignored; session already terminated"
+            );
+        } else {
+            account_sessions.remove_from_active_index(&auth_hash, session_token);
+            match rejected_by.as_deref() {
+                Some(operator) if !operator.is_empty() => {
+                    warn!(
+                        account = %account,
+                        session = %session_id,
+                        operator,
+                        reason = ?reason,
+                        "session rejected"
+                    );
+                }
+                _ => {
+                    warn!(
+                        account = %account,
+                        session = %session_id,
+                        reason = ?reason,
+                        "session rejected"
+                    );
+                }
+            }
+            self.persist_sessions(account, &account_sessions).await;
+        }
+
+        Ok(outcome.response)
+    }
+
+    pub async fn apply_admin_command(
+        &self,
+        command: AdminCommand,
+    ) -> Result<AdminCommandOutcome, AdminCommandError> {
+        match command {
+            AdminCommand::Approve(command) => {
+                let AdminApprovalCommand {
+                    account_id,
+                    session_id,
+                    auth_key_fingerprint,
+                    approved_by,
+                    expires_at,
+                } = command;
+
+                let response = self
+                    .promote_session_internal(
+                        &account_id,
+                        session_id,
+                        auth_key_fingerprint,
+                        approved_by.as_deref(),
+                        expires_at,
+                    )
+                    .await?;
+
+                Ok(AdminCommandOutcome::SessionAuthenticated(response))
+            }
+            AdminCommand::Reject(command) => {
+                let AdminRejectionCommand {
+                    account_id,
+                    session_id,
+                    auth_key_fingerprint,
+                    rejected_by,
+                    reason,
+                } = command;
+
+                let response = self
+                    .reject_session(
+                        &account_id,
+                        session_id,
+                        &auth_key_fingerprint,
+                        reason,
+                        rejected_by,
+                    )
+                    .await?;
+
+                Ok(AdminCommandOutcome::SessionRejected(response))
+            }
+        }
+    }
+
+    async fn promote_session_internal(
+        &self,
+        account: &str,
+        session_id: Uuid,
+        fingerprint: String,
+        operator: Option<&str>,
+        approval_expires_at: Option<OffsetDateTime>,
+    ) -> Result<SessionPromotionResponse, AdminCommandError> {
+        let handle = self
+            .account(account)
+            .await
+            .ok_or(AdminCommandError::SessionMissing)?;
+        let mut account_sessions = handle.lock().await;
+        let (session, sequences) = account_sessions
+            .session_with_sequences(&session_id)
+            .ok_or(AdminCommandError::SessionMismatch)?;
+
+        let was_pending = session.status.is_pending();
+        let response = session.promote(sequences, &fingerprint, approval_expires_at)?;
+        self.persist_sessions(account, &account_sessions).await;
+
+        if was_pending && response.status == SessionStatus::Authenticated {
+            match operator {
+                Some(operator) if !operator.is_empty() => {
+                    info!(
+                        account = %account,
+                        session = %session_id,
+                        operator,
+                        "session authenticated"
+                    );
+                }
+                _ => {
+                    info!(account = %account, session = %session_id, "session authenticated");
+                }
+            }
+        }
+
+        if let Some(expires_at) = approval_expires_at {
+            debug!(
+                account = %account,
+                session = %session_id,
+                %expires_at,
+                "session approval window set",
+            );
+        }
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    async fn insert_session_for_test(&self, account: &str, session: SessionRecord) {
+        self.account_or_default(account)
+            .await
+            .lock()
+            .await
+            .insert(session);
+    }
+
+    async fn outbox_events_for_test(&self, account: &str, session_id: Uuid) -> Vec<OutboundEvent> {
+        let Some(handle) = self.account(account).await else {
+            return Vec::new();
+        };
+        let mut account_sessions = handle.lock().await;
+        account_sessions
+            .get_mut_by_session_id(&session_id)
+            .map(|session| session.outbox.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Health status payload emitted by the service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthStatus {
+    /// Name of the component being checked.
+    pub component: &'static str,
+    /// Human-readable description of the current state.
+    pub message: &'static str,
+    /// Indicates whether the component is healthy.
+    pub healthy: bool,
+}
+
+impl HealthStatus {
+    /// Creates a successful health check response for the provided component.
+    pub const fn ok(component: &'static str) -> Self {
+        Self {
+            component,
+            message: "ok",
+            healthy: true,
+        }
+    }
+}
+
+/// Returns the health status of the gateway service.
+pub fn health_check() -> HealthStatus {
+    HealthStatus::ok("gateway")
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+}
+
+#[derive(Debug, Error)]
+#[error("{message}")]
+pub(crate) struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn unauthorized(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::UNAUTHORIZED,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn forbidden(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn conflict(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::CONFLICT,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn unprocessable(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn not_found(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::NOT_FOUND,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn payload_too_large(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn too_many_requests(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn internal(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            code: "internal_error",
+            message: message.into(),
+        }
+    }
+
+    pub(crate) fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub(crate) fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub(crate) fn status(&self) -> StatusCode {
+        self.status
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = Json(ErrorBody {
+            code: self.code,
+            message: self.message,
+        });
+        (self.status, body).into_response()
+    }
+}
+
+#[derive(Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    body: Option<Value>,
+    headers: Vec<(String, String)>,
+}
+
+impl StoredResponse {
+    fn from_json<T>(status: StatusCode, payload: &T) -> Result<Self, serde_json::Error>
+    where
+        T: Serialize,
+    {
+        Ok(Self {
+            status,
+            body: Some(serde_json::to_value(payload)?),
+            headers: Vec::new(),
+        })
+    }
+
+    fn empty(status: StatusCode) -> Self {
+        Self {
+            status,
+            body: None,
+            headers: Vec::new(),
+        }
+    }
+
+    fn into_response(self) -> Response {
+        let mut response = if let Some(body) = self.body {
+            (self.status, Json(body)).into_response()
+        } else {
+            Response::builder()
+                .status(self.status)
+                .body(Body::empty())
+                .unwrap_or_else(|_| Response::new(Body::empty()))
+        };
+
+        for (name, value) in self.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(&value),
+            ) {
+                response.headers_mut().insert(name, value);
+            }
+        }
+
+        response
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionStatus {
+    Pending,
+    Authenticated,
     Terminated,
 }
 
-impl SessionStatus {
-    const fn is_pending(self) -> bool {
-        matches!(self, SessionStatus::Pending)
+impl SessionStatus {
+    const fn is_pending(self) -> bool {
+        matches!(self, SessionStatus::Pending)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    AccountSessionKey,
+    PreSharedKey,
+}
+
+impl Default for AuthMethod {
+    fn default() -> Self {
+        AuthMethod::AccountSessionKey
+    }
+}
+
+impl AuthMethod {
+    const fn storage_key(self) -> &'static str {
+        match self {
+            AuthMethod::AccountSessionKey => "account_session_key",
+            AuthMethod::PreSharedKey => "pre_shared_key",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionCreateRequest {
+    #[serde(default)]
+    auth_method: AuthMethod,
+    authentication_key: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionCreateResponse {
+    session_id: Uuid,
+    session_token: Uuid,
+    status: SessionStatus,
+    auth_method: AuthMethod,
+    pending: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    last_heartbeat_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    approval_expires_at: Option<OffsetDateTime>,
+    previous_session_terminated: Option<Uuid>,
+    /// Highest outbox sequence issued to the account before this session; every
+    /// event queued for the session is numbered above it.
+    outbox_high_water_mark: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InboxBatch {
+    events: Vec<InboxEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InboxEvent {
+    #[serde(default)]
+    event_id: Option<String>,
+    event_type: String,
+    #[serde(default)]
+    payload: Value,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    occurred_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InboundEventRecord {
+    id: Uuid,
+    /// Identifier the EA assigned to the event, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event_id: Option<String>,
+    sequence: u64,
+    event_type: String,
+    payload: Value,
+    #[serde(with = "time::serde::rfc3339::option")]
+    occurred_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339")]
+    received_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TradeCommandType {
+    Open,
+    Close,
+    Modify,
+    Cancel,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TradeSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TradeOrderType {
+    Market,
+    Limit,
+    Stop,
+    StopLimit,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TradeTimeInForce {
+    Gtc,
+    Gtd,
+    Gfd,
+    Ioc,
+    Fok,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TradeOrderRequest {
+    command_type: TradeCommandType,
+    instrument: String,
+    #[serde(default)]
+    order_type: Option<TradeOrderType>,
+    #[serde(default)]
+    side: Option<TradeSide>,
+    #[serde(default)]
+    volume: Option<f64>,
+    #[serde(default)]
+    price: Option<f64>,
+    /// `Some(None)` when sent as `null`, which clears the level on `modify`.
+    #[serde(default, deserialize_with = "present")]
+    stop_loss: Option<Option<f64>>,
+    /// `Some(None)` when sent as `null`, which clears the level on `modify`.
+    #[serde(default, deserialize_with = "present")]
+    take_profit: Option<Option<f64>>,
+    #[serde(default)]
+    time_in_force: Option<TradeTimeInForce>,
+    #[serde(default)]
+    position_id: Option<String>,
+    /// Ticket of the pending order a `modify` or `cancel` command targets.
+    #[serde(default)]
+    order_id: Option<String>,
+    /// Command that placed the pending order a `cancel` command withdraws.
+    #[serde(default)]
+    original_command_id: Option<Uuid>,
+    /// `clientOrderId` of the pending order a `cancel` command withdraws.
+    #[serde(default)]
+    original_client_order_id: Option<String>,
+    #[serde(default)]
+    client_order_id: Option<String>,
+    #[serde(default)]
+    metadata: Option<Value>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    expires_at: Option<OffsetDateTime>,
+}
+
+/// Deserializes a field that was sent, keeping an explicit `null` apart from
+/// a missing field (which `#[serde(default)]` leaves as `None`).
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Validates a `stopLoss` or `takeProfit` level, returning what the EA is
+/// sent: the level itself, `0` when a `modify` command clears it with `null`
+/// or `0`, or nothing when it was left out.
+fn protective_level(
+    level: Option<Option<f64>>,
+    clearable: bool,
+    field: &str,
+    code: &'static str,
+) -> Result<Option<f64>, ApiError> {
+    match level {
+        None => Ok(None),
+        Some(level) if clearable && level.is_none_or(|level| level == 0.0) => Ok(Some(0.0)),
+        Some(None) => Ok(None),
+        Some(Some(level)) if level.is_finite() && level > 0.0 => Ok(Some(level)),
+        Some(Some(_)) => Err(ApiError::bad_request(
+            code,
+            format!("{field} must be greater than zero"),
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TradeCommandQueued {
+    session_id: Uuid,
+    event_id: Uuid,
+    sequence: u64,
+    command_id: Uuid,
+    pending_session: bool,
+    command_type: TradeCommandType,
+    instrument: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order_type: Option<TradeOrderType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    side: Option<TradeSide>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    original_command_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    volume: Option<f64>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    expires_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OutboxEventRequest {
+    event_type: String,
+    #[serde(default)]
+    payload: Value,
+    #[serde(default = "default_requires_ack")]
+    requires_ack: bool,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    expires_at: Option<OffsetDateTime>,
+}
+
+const fn default_requires_ack() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboundEvent {
+    id: Uuid,
+    sequence: u64,
+    event_type: String,
+    payload: Value,
+    #[serde(with = "timestamp")]
+    enqueued_at: OffsetDateTime,
+    requires_ack: bool,
+    #[serde(default)]
+    delivery_attempts: u32,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    last_delivered_at: Option<OffsetDateTime>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    expires_at: Option<OffsetDateTime>,
+    /// Id the event was first queued under, set when it was carried over from
+    /// a preempted session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    original_event_id: Option<Uuid>,
+    /// Set when the event was queued again by an admin resync after the EA
+    /// had already acknowledged it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    replay: bool,
+}
+
+impl OutboundEvent {
+    fn is_expired(&self, now: OffsetDateTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Returns when the event may be handed out again, or `None` if it has
+    /// never been delivered.
+    fn visible_at(&self, visibility_timeout: Duration) -> Option<OffsetDateTime> {
+        self.last_delivered_at
+            .map(|delivered_at| delivered_at + visibility_timeout)
+    }
+}
+
+impl InboxEventError {
+    fn new(index: usize, event_type: String, error: &InboxPayloadError) -> Self {
+        let code = match error {
+            InboxPayloadError::Malformed { .. } => "malformed_payload",
+            InboxPayloadError::Invalid { .. } => "invalid_payload",
+        };
+        Self {
+            index,
+            event_type,
+            code,
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Inbox events recorded from one batch, the outbox events acknowledged
+/// through `OutboxAck` entries in it, and the events that were skipped.
+struct InboxCapture {
+    records: Vec<InboundEventRecord>,
+    acknowledged: Vec<OutboundEvent>,
+    rejected: Vec<InboxEventError>,
+    /// Events skipped because their client `eventId` was already accepted.
+    duplicates: usize,
+    /// Accepted payloads that update the account portfolio or command ledger.
+    account_updates: Vec<InboxPayload>,
+}
+
+/// An outbox event that exhausted its delivery attempts without being acknowledged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    #[serde(flatten)]
+    event: OutboundEvent,
+    #[serde(with = "time::serde::rfc3339")]
+    dead_lettered_at: OffsetDateTime,
+}
+
+/// Size of a payload as it is serialized to the EA.
+fn payload_size(payload: &Value) -> usize {
+    payload.to_string().len()
+}
+
+fn expired_event_payload(event: &OutboundEvent) -> Value {
+    json!({
+        "eventId": event.id,
+        "eventType": event.event_type,
+        "sequence": event.sequence,
+        "commandId": event.payload.get("commandId"),
+        "expiresAt": event.expires_at.map(timestamp::to_value),
+    })
+}
+
+#[derive(Default)]
+struct OutboxDelivery {
+    events: Vec<OutboundEvent>,
+    dead_lettered: Vec<DeadLetter>,
+    /// Whether visible events were left behind because of the batch limit.
+    has_more: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OutboxResponse {
+    session_id: Uuid,
+    pending: bool,
+    events: Vec<OutboundEvent>,
+    next_cursor: u64,
+    has_more: bool,
+    highest_acknowledged_sequence: u64,
+    retry_after_ms: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AckResponse {
+    acknowledged_event_id: Uuid,
+    remaining_outbox_depth: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OutboxBatchAckRequest {
+    #[serde(default)]
+    event_ids: Vec<Uuid>,
+    #[serde(default)]
+    ack_through: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum OutboxAckStatus {
+    Acknowledged,
+    AlreadyAcknowledged,
+    Unknown,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OutboxAckResult {
+    event_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequence: Option<u64>,
+    status: OutboxAckStatus,
+}
+
+impl OutboxAckResult {
+    fn acknowledged(event: &OutboundEvent) -> Self {
+        Self {
+            event_id: event.id,
+            sequence: Some(event.sequence),
+            status: OutboxAckStatus::Acknowledged,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OutboxBatchAckResponse {
+    acknowledged: usize,
+    results: Vec<OutboxAckResult>,
+    remaining_outbox_depth: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InboxResponse {
+    accepted: usize,
+    duplicates: usize,
+    rejected: Vec<InboxEventError>,
+    pending_session: bool,
+}
+
+/// An inbox event that was not recorded because its payload did not match
+/// the schema for its event type.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InboxEventError {
+    /// Position of the event in the submitted batch.
+    index: usize,
+    event_type: String,
+    code: &'static str,
+    message: String,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionPromotionResponse {
+    session_id: Uuid,
+    status: SessionStatus,
+    pending: bool,
+    message: String,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    approval_expires_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionRejectionResponse {
+    session_id: Uuid,
+    status: SessionStatus,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum AdminCommandOutcome {
+    SessionAuthenticated(SessionPromotionResponse),
+    SessionRejected(SessionRejectionResponse),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OutboxEnqueueResponse {
+    session_id: Uuid,
+    event_id: Uuid,
+    sequence: u64,
+    pending_session: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DeadLetterListResponse {
+    session_id: Uuid,
+    dead_letters: Vec<DeadLetter>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OutboxDepthResponse {
+    session_id: Uuid,
+    status: SessionStatus,
+    depth: usize,
+    bytes: usize,
+    max_depth: usize,
+    max_bytes: usize,
+    dead_letters: usize,
+    #[serde(with = "time::serde::rfc3339::option")]
+    oldest_enqueued_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ResyncRequest {
+    from_sequence: u64,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ResyncResponse {
+    session_id: Uuid,
+    from_sequence: u64,
+    replayed: Vec<ReplayedEvent>,
+    skipped_expired: usize,
+    pending_session: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PortfolioResponse {
+    account_id: String,
+    #[serde(flatten)]
+    portfolio: Portfolio,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CommandQuery {
+    #[serde(default)]
+    client_order_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CommandListResponse {
+    account_id: String,
+    commands: Vec<CommandRecord>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ReplayedEvent {
+    event_id: Uuid,
+    original_event_id: Uuid,
+    sequence: u64,
+    original_sequence: u64,
+}
+
+/// Checks a partial close against the position it reduces: it may not take
+/// more than is left once the closes still unsettled for the position are
+/// taken off, and must be a whole number of volume steps.
+///
+/// The position has to be in the portfolio the EA reported. Before the EA has
+/// reported any portfolio there is nothing to check against, so the close is
+/// passed on with a warning.
+fn check_close_volume(
+    account: &str,
+    account_sessions: &AccountSessions,
+    position_id: &str,
+    volume: f64,
+) -> Result<(), ApiError> {
+    let ticket: u64 = position_id.parse().map_err(|_| {
+        ApiError::bad_request(
+            "position_id_invalid",
+            "positionId must be the broker's numeric position ticket",
+        )
+    })?;
+    if account_sessions.portfolio.is_empty() {
+        warn!(
+            account = %account,
+            position = ticket,
+            "passing on a partial close unchecked; the EA has not reported its portfolio yet",
+        );
+        return Ok(());
+    }
+    let Some(position) = account_sessions.portfolio.position(ticket) else {
+        return Err(ApiError::not_found(
+            "close_position_missing",
+            "the EA has not reported an open position with that positionId",
+        ));
+    };
+
+    let unsettled: f64 = account_sessions
+        .commands
+        .unsettled_closes(position_id)
+        .map(|command| command.volume().unwrap_or(position.volume))
+        .sum();
+    if volume > position.volume - unsettled + VOLUME_EPSILON {
+        return Err(ApiError::conflict(
+            "close_volume_exceeds_position",
+            "volume is larger than what remains of the position after unsettled closes",
+        ));
+    }
+
+    if let Some(step) = position.volume_step {
+        let steps = volume / step;
+        if (steps - steps.round()).abs() > VOLUME_EPSILON * steps.max(1.0) {
+            return Err(ApiError::bad_request(
+                "volume_step_mismatch",
+                "volume must be a multiple of the instrument's volume step",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves the pending order a `cancel` command refers to by the command
+/// that placed it.
+fn cancel_target<'a>(
+    commands: &'a CommandLedger,
+    command_id: Option<Uuid>,
+    client_order_id: Option<&str>,
+) -> Result<&'a CommandRecord, ApiError> {
+    let original = commands
+        .lookup(command_id, client_order_id)
+        .ok_or_else(|| {
+            ApiError::not_found(
+                "cancel_target_missing",
+                "no trade command matches the order to cancel",
+            )
+        })?;
+
+    if original.command_type() != Some(TradeCommandType::Open)
+        || original.order_type() == Some(TradeOrderType::Market)
+    {
+        return Err(ApiError::bad_request(
+            "cancel_target_invalid",
+            "only pending orders placed by an open command can be cancelled",
+        ));
+    }
+    if matches!(
+        original.status(),
+        CommandStatus::Rejected | CommandStatus::Expired
+    ) {
+        return Err(ApiError::conflict(
+            "cancel_target_settled",
+            "the order to cancel was rejected or expired before it was placed",
+        ));
+    }
+
+    Ok(original)
+}
+
+fn session_not_found() -> ApiError {
+    ApiError::not_found("session_missing", "no session with the supplied identifier")
+}
+
+/// Upper bound on how long a long-polling outbox request is held open.
+const MAX_OUTBOX_WAIT_MS: u64 = 30_000;
+
+/// Number of recent client `eventId`s remembered per session to drop resent inbox events.
+const INBOX_DEDUP_WINDOW: usize = 1_024;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OutboxQuery {
+    #[serde(default)]
+    cursor: Option<u64>,
+    #[serde(default)]
+    limit: Option<usize>,
+    /// Milliseconds to hold the request open while no event is available.
+    #[serde(default)]
+    wait_ms: Option<u64>,
+    /// Acknowledges every event numbered at or below this sequence before polling.
+    #[serde(default)]
+    ack_through: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionRecord {
+    session_id: Uuid,
+    session_token: Uuid,
+    status: SessionStatus,
+    auth_method: AuthMethod,
+    auth_key_hash: String,
+    #[serde(with = "timestamp")]
+    created_at: OffsetDateTime,
+    #[serde(with = "timestamp")]
+    updated_at: OffsetDateTime,
+    #[serde(default, with = "timestamp::option")]
+    last_heartbeat_at: Option<OffsetDateTime>,
+    #[serde(default, with = "timestamp::option")]
+    terminated_at: Option<OffsetDateTime>,
+    #[serde(default, with = "timestamp::option")]
+    approval_expires_at: Option<OffsetDateTime>,
+    outbox: Vec<OutboundEvent>,
+    #[serde(default)]
+    dead_letters: Vec<DeadLetter>,
+    #[serde(skip)]
+    inbox_log: Vec<InboundEventRecord>,
+    /// Client `eventId`s of the most recently accepted inbox events, oldest first.
+    #[serde(default)]
+    recent_inbox_event_ids: VecDeque<String>,
+    /// Wakes long-polling outbox requests whenever an event is enqueued.
+    #[serde(skip)]
+    outbox_notify: Arc<Notify>,
+    /// When an event was last queued, used to pace polling EAs.
+    #[serde(skip)]
+    last_enqueued_at: Option<OffsetDateTime>,
+}
+
+struct SessionRejectionOutcome {
+    response: SessionRejectionResponse,
+    already_terminated: bool,
+}
+
+enum TerminationReason {
+    Preempted,
+    Rejected {
+        rejected_by: Option<String>,
+        reason: Option<String>,
+    },
+    HeartbeatTimeout {
+        last_heartbeat_at: Option<OffsetDateTime>,
+        reconnect_after: Duration,
+    },
+    ApprovalExpired {
+        expired_at: OffsetDateTime,
+    },
+}
+
+impl TerminationReason {
+    fn into_payload(self, terminated_at: OffsetDateTime) -> Value {
+        match self {
+            TerminationReason::Preempted => json!({
+                "reason": "session_preempted",
+                "terminatedAt": timestamp::to_value(terminated_at),
+            }),
+            TerminationReason::Rejected {
+                rejected_by,
+                reason,
+            } => {
+                let mut payload = Map::new();
+                payload.insert(
+                    "reason".to_string(),
+                    Value::String("session_rejected".to_string()),
+                );
+                payload.insert(
+                    "terminatedAt".to_string(),
+                    timestamp::to_value(terminated_at),
+                );
+                if let Some(reason) = reason {
+                    if !reason.is_empty() {
+                        payload.insert("details".to_string(), Value::String(reason));
+                    }
+                }
+                if let Some(operator) = rejected_by {
+                    if !operator.is_empty() {
+                        payload.insert("rejectedBy".to_string(), Value::String(operator));
+                    }
+                }
+                Value::Object(payload)
+            }
+            TerminationReason::HeartbeatTimeout {
+                last_heartbeat_at,
+                reconnect_after,
+            } => json!({
+                "reason": "heartbeat_timeout",
+                "terminatedAt": timestamp::to_value(terminated_at),
+                "lastHeartbeatAt": last_heartbeat_at.map(timestamp::to_value),
+                "reconnectAfterMs": u64::try_from(reconnect_after.as_millis()).unwrap_or(u64::MAX),
+            }),
+            TerminationReason::ApprovalExpired { expired_at } => json!({
+                "reason": "approval_expired",
+                "terminatedAt": timestamp::to_value(terminated_at),
+                "approvalExpiredAt": timestamp::to_value(expired_at),
+            }),
+        }
+    }
+}
+
+impl SessionRecord {
+    fn new(auth_method: AuthMethod, auth_key_hash: String) -> Self {
+        let now = current_time();
+        Self {
+            session_id: Uuid::new_v4(),
+            session_token: Uuid::new_v4(),
+            status: SessionStatus::Pending,
+            auth_method,
+            auth_key_hash,
+            created_at: now,
+            updated_at: now,
+            last_heartbeat_at: None,
+            terminated_at: None,
+            approval_expires_at: None,
+            outbox: Vec::new(),
+            dead_letters: Vec::new(),
+            inbox_log: Vec::new(),
+            recent_inbox_event_ids: VecDeque::new(),
+            outbox_notify: Arc::default(),
+            last_enqueued_at: None,
+        }
+    }
+
+    fn to_create_response(
+        &self,
+        previous_session: Option<Uuid>,
+        outbox_high_water_mark: u64,
+    ) -> SessionCreateResponse {
+        SessionCreateResponse {
+            session_id: self.session_id,
+            session_token: self.session_token,
+            status: self.status,
+            auth_method: self.auth_method,
+            pending: self.status.is_pending(),
+            created_at: self.created_at,
+            last_heartbeat_at: self.last_heartbeat_at,
+            approval_expires_at: self.approval_expires_at,
+            previous_session_terminated: previous_session,
+            outbox_high_water_mark,
+        }
+    }
+
+    fn verify_secret(&self, candidate_hash: &str) -> bool {
+        self.auth_key_hash == candidate_hash
+    }
+
+    /// Authenticates the session. When `approval_expires_at` is supplied the
+    /// session is only valid until then; re-approving an authenticated session
+    /// replaces its window.
+    fn promote(
+        &mut self,
+        sequences: &mut AccountSequences,
+        fingerprint: &str,
+        approval_expires_at: Option<OffsetDateTime>,
+    ) -> Result<SessionPromotionResponse, AdminCommandError> {
+        if !self.verify_secret(fingerprint) {
+            return Err(AdminCommandError::AuthenticationFailed);
+        }
+
+        if self.status == SessionStatus::Terminated {
+            return Err(AdminCommandError::SessionTerminated);
+        }
+
+        if matches!(approval_expires_at, Some(expiry) if expiry <= current_time()) {
+            return Err(AdminCommandError::ApprovalExpired);
+        }
+
+        if self.status == SessionStatus::Authenticated {
+            if approval_expires_at.is_some() {
+                self.approval_expires_at = approval_expires_at;
+                self.updated_at = current_time();
+            }
+
+            return Ok(SessionPromotionResponse {
+                session_id: self.session_id,
+                status: self.status,
+                pending: false,
+                message: "session already authenticated".to_string(),
+                approval_expires_at: self.approval_expires_at,
+            });
+        }
+
+        self.approval_expires_at = approval_expires_at;
+        self.mark_authenticated();
+        self.enqueue_init_ack(sequences);
+
+        Ok(SessionPromotionResponse {
+            session_id: self.session_id,
+            status: self.status,
+            pending: false,
+            message: "session authenticated".to_string(),
+            approval_expires_at: self.approval_expires_at,
+        })
+    }
+
+    /// Terminates an authenticated session once its approval window has lapsed.
+    fn expire_lapsed_approval(
+        &mut self,
+        sequences: &mut AccountSequences,
+        now: OffsetDateTime,
+    ) -> bool {
+        let Some(expired_at) = self.approval_expires_at else {
+            return false;
+        };
+
+        if self.status != SessionStatus::Authenticated || expired_at > now {
+            return false;
+        }
+
+        self.mark_terminated(sequences, TerminationReason::ApprovalExpired { expired_at });
+        true
+    }
+
+    fn reject(
+        &mut self,
+        sequences: &mut AccountSequences,
+        reason: Option<String>,
+        rejected_by: Option<String>,
+    ) -> SessionRejectionOutcome {
+        let already_terminated = self.status == SessionStatus::Terminated;
+
+        if !already_terminated {
+            self.mark_terminated(
+                sequences,
+                TerminationReason::Rejected {
+                    rejected_by: rejected_by.clone(),
+                    reason: reason.clone(),
+                },
+            );
+        }
+
+        let message = if already_terminated {
+            "session already terminated"
+        } else {
+            "session rejected"
+        };
+
+        SessionRejectionOutcome {
+            response: SessionRejectionResponse {
+                session_id: self.session_id,
+                status: self.status,
+                message: message.to_string(),
+                reason,
+            },
+            already_terminated,
+        }
+    }
+
+    fn mark_authenticated(&mut self) {
+        self.status = SessionStatus::Authenticated;
+        self.updated_at = current_time();
+    }
+
+    fn mark_terminated(&mut self, sequences: &mut AccountSequences, reason: TerminationReason) {
+        if self.status == SessionStatus::Terminated {
+            return;
+        }
+
+        self.status = SessionStatus::Terminated;
+        let terminated_at = current_time();
+        self.terminated_at = Some(terminated_at);
+        let payload = reason.into_payload(terminated_at);
+
+        let request = OutboxEventRequest {
+            event_type: "ShutdownNotice".to_string(),
+            payload,
+            requires_ack: true,
+            expires_at: None,
+        };
+
+        self.enqueue_outbox(sequences, request);
+    }
+
+    fn heartbeat_expired(&self, now: OffsetDateTime, timeout: Duration) -> bool {
+        if self.status == SessionStatus::Terminated {
+            return false;
+        }
+
+        let last_seen = self.last_heartbeat_at.unwrap_or(self.created_at);
+        now - last_seen >= timeout
+    }
+
+    /// Terminated sessions are kept until the EA drains its outbox (including the
+    /// `ShutdownNotice`), or until nobody has polled them for a full heartbeat timeout.
+    fn is_reapable(&self, now: OffsetDateTime, timeout: Duration) -> bool {
+        if self.status != SessionStatus::Terminated {
+            return false;
+        }
+
+        let abandoned = self
+            .terminated_at
+            .is_some_and(|terminated_at| now - terminated_at >= timeout);
+        self.outbox.is_empty() || abandoned
+    }
+
+    fn enqueue_outbox(
+        &mut self,
+        sequences: &mut AccountSequences,
+        event: OutboxEventRequest,
+    ) -> OutboundEvent {
+        let enqueued_at = current_time();
+        let outbound = OutboundEvent {
+            id: Uuid::new_v4(),
+            sequence: sequences.next_outbox(),
+            event_type: event.event_type,
+            payload: event.payload,
+            enqueued_at,
+            requires_ack: event.requires_ack,
+            delivery_attempts: 0,
+            last_delivered_at: None,
+            expires_at: event.expires_at,
+            original_event_id: None,
+            replay: false,
+        };
+        self.outbox.push(outbound.clone());
+        self.updated_at = enqueued_at;
+        self.last_enqueued_at = Some(enqueued_at);
+        self.outbox_notify.notify_waiters();
+        outbound
+    }
+
+    /// Removes the events still awaiting an acknowledgement, other than the
+    /// session handshake (`InitAck`) and `ShutdownNotice`.
+    fn take_unacknowledged(&mut self) -> Vec<OutboundEvent> {
+        let (unacknowledged, remaining): (Vec<_>, Vec<_>) =
+            self.outbox.drain(..).partition(|event| {
+                event.requires_ack
+                    && !matches!(event.event_type.as_str(), "InitAck" | "ShutdownNotice")
+            });
+        self.outbox = remaining;
+        unacknowledged
+    }
+
+    /// Queues an event carried over from a preempted session under a new id
+    /// and sequence, remembering the id it was originally queued under.
+    fn adopt_outbox_event(
+        &mut self,
+        sequences: &mut AccountSequences,
+        event: OutboundEvent,
+    ) -> OutboundEvent {
+        let adopted = OutboundEvent {
+            id: Uuid::new_v4(),
+            sequence: sequences.next_outbox(),
+            delivery_attempts: 0,
+            last_delivered_at: None,
+            original_event_id: Some(event.original_event_id.unwrap_or(event.id)),
+            ..event
+        };
+        self.outbox.push(adopted.clone());
+        self.updated_at = current_time();
+        self.last_enqueued_at = Some(self.updated_at);
+        self.outbox_notify.notify_waiters();
+        adopted
+    }
+
+    /// Queues an acknowledged event again under a new id and sequence, marked
+    /// as a replay of the id it was originally queued under.
+    fn replay_outbox_event(
+        &mut self,
+        sequences: &mut AccountSequences,
+        event: OutboundEvent,
+    ) -> OutboundEvent {
+        self.adopt_outbox_event(
+            sequences,
+            OutboundEvent {
+                enqueued_at: current_time(),
+                replay: true,
+                ..event
+            },
+        )
+    }
+
+    /// Queues the `InitAck` ahead of anything queued while the session was
+    /// pending, renumbering those events to follow it. A pending session has
+    /// delivered nothing, so the EA never sees the earlier sequences.
+    fn enqueue_init_ack(&mut self, sequences: &mut AccountSequences) {
+        let queued_while_pending = std::mem::take(&mut self.outbox);
+        let request = OutboxEventRequest {
+            event_type: "InitAck".to_string(),
+            payload: json!({
+                "message": "Session authenticated",
+            }),
+            requires_ack: true,
+            expires_at: None,
+        };
+        self.enqueue_outbox(sequences, request);
+        for event in queued_while_pending {
+            self.outbox.push(OutboundEvent {
+                sequence: sequences.next_outbox(),
+                ..event
+            });
+        }
+    }
+
+    fn apply_outbox_ack(&mut self, ack: &OutboxAck) -> Option<OutboundEvent> {
+        let (event_uuid, sequence) = (ack.event_id, ack.sequence);
+        let removed = self.acknowledge_outbox(event_uuid);
+
+        if removed.is_some() {
+            debug!(%event_uuid, sequence, "acknowledged outbox event via inbox");
+        } else {
+            debug!(%event_uuid, sequence, "outbox ack did not match a pending event");
+        }
+        removed
+    }
+
+    fn capture_inbox(
+        &mut self,
+        sequences: &mut AccountSequences,
+        batch: Vec<InboxEvent>,
+    ) -> InboxCapture {
+        let mut captured = Vec::with_capacity(batch.len());
+        let mut acknowledged = Vec::new();
+        let mut rejected = Vec::new();
+        let mut duplicates = 0;
+        let mut account_updates = Vec::new();
+        for (index, event) in batch.into_iter().enumerate() {
+            if event
+                .event_id
+                .as_ref()
+                .is_some_and(|event_id| self.recent_inbox_event_ids.contains(event_id))
+            {
+                debug!(event_id = ?event.event_id, "skipping duplicate inbox event");
+                duplicates += 1;
+                continue;
+            }
+
+            let payload = match InboxPayload::parse(&event.event_type, &event.payload) {
+                Ok(payload) => payload,
+                Err(error) => {
+                    rejected.push(InboxEventError::new(index, event.event_type, &error));
+                    continue;
+                }
+            };
+
+            let sequence = sequences.next_inbox();
+            let received_at = current_time();
+            match &payload {
+                InboxPayload::StatusHeartbeat(_) => self.last_heartbeat_at = Some(received_at),
+                InboxPayload::OutboxAck(ack) => acknowledged.extend(self.apply_outbox_ack(ack)),
+                _ => {}
+            }
+            if matches!(
+                payload,
+                InboxPayload::SyncSnapshot(_)
+                    | InboxPayload::ExecutionReport(_)
+                    | InboxPayload::OutboxAck(_)
+            ) {
+                account_updates.push(payload);
+            }
+
+            if let Some(event_id) = &event.event_id {
+                if self.recent_inbox_event_ids.len() >= INBOX_DEDUP_WINDOW {
+                    self.recent_inbox_event_ids.pop_front();
+                }
+                self.recent_inbox_event_ids.push_back(event_id.clone());
+            }
+
+            let record = InboundEventRecord {
+                id: Uuid::new_v4(),
+                event_id: event.event_id,
+                sequence,
+                event_type: event.event_type,
+                payload: event.payload,
+                occurred_at: event.occurred_at,
+                received_at,
+            };
+            self.inbox_log.push(record.clone());
+            captured.push(record);
+        }
+
+        if !captured.is_empty() {
+            self.updated_at = current_time();
+        }
+
+        InboxCapture {
+            records: captured,
+            acknowledged,
+            rejected,
+            duplicates,
+            account_updates,
+        }
+    }
+
+    fn acknowledge_outbox(&mut self, event_id: Uuid) -> Option<OutboundEvent> {
+        let index = self.outbox.iter().position(|event| event.id == event_id)?;
+        let event = self.outbox.remove(index);
+        self.updated_at = current_time();
+        Some(event)
+    }
+
+    /// Removes every event numbered `sequence` or lower, in sequence order.
+    fn acknowledge_through(&mut self, sequence: u64) -> Vec<OutboundEvent> {
+        let (mut acknowledged, remaining): (Vec<_>, Vec<_>) = self
+            .outbox
+            .drain(..)
+            .partition(|event| event.sequence <= sequence);
+        self.outbox = remaining;
+        if !acknowledged.is_empty() {
+            acknowledged.sort_by_key(|event| event.sequence);
+            self.updated_at = current_time();
+        }
+        acknowledged
+    }
+
+    fn highest_outbox_sequence(&self) -> Option<u64> {
+        self.outbox
+            .iter()
+            .chain(
+                self.dead_letters
+                    .iter()
+                    .map(|dead_letter| &dead_letter.event),
+            )
+            .map(|event| event.sequence)
+            .max()
+    }
+
+    fn outbox_bytes(&self) -> usize {
+        self.outbox
+            .iter()
+            .map(|event| payload_size(&event.payload))
+            .sum()
+    }
+
+    /// Whether one more event with a payload of `size` bytes stays within the
+    /// outbox depth and byte limits.
+    fn outbox_has_room(&self, size: usize, config: &OutboxDeliveryConfig) -> bool {
+        self.outbox.len() < config.max_depth && self.outbox_bytes() + size <= config.max_bytes
+    }
+
+    /// Drops outbox events whose `expiresAt` has passed and queues a
+    /// `CommandExpired` notification for each, returning the dropped events.
+    /// Events the EA received but never acknowledged are dropped as well, so
+    /// that a stale command is not handed out again after the visibility
+    /// timeout.
+    fn expire_outbox(
+        &mut self,
+        sequences: &mut AccountSequences,
+        now: OffsetDateTime,
+    ) -> Vec<OutboundEvent> {
+        let is_stale = |event: &OutboundEvent| event.is_expired(now);
+        if !self.outbox.iter().any(is_stale) {
+            return Vec::new();
+        }
+
+        let (expired, remaining): (Vec<_>, Vec<_>) = self.outbox.drain(..).partition(is_stale);
+        self.outbox = remaining;
+
+        for event in &expired {
+            self.enqueue_outbox(
+                sequences,
+                OutboxEventRequest {
+                    event_type: "CommandExpired".to_string(),
+                    payload: expired_event_payload(event),
+                    requires_ack: false,
+                    expires_at: None,
+                },
+            );
+        }
+
+        expired
+    }
+
+    /// Outbox events numbered after `cursor` that have not expired, in
+    /// sequence order, for observers that must not disturb delivery. Nothing is
+    /// listed while the session is pending, since promotion renumbers the
+    /// events queued in the meantime.
+    fn events_after(&self, cursor: u64, now: OffsetDateTime) -> Vec<OutboundEvent> {
+        if self.status.is_pending() {
+            return Vec::new();
+        }
+
+        let mut events: Vec<OutboundEvent> = self
+            .outbox
+            .iter()
+            .filter(|event| event.sequence > cursor && !event.is_expired(now))
+            .cloned()
+            .collect();
+        events.sort_by_key(|event| event.sequence);
+        events
+    }
+
+    /// Hands out the outbox events that are due for delivery and records the
+    /// attempt. Expired events are never handed out.
+    ///
+    /// Events after `cursor` are delivered once; events awaiting an
+    /// acknowledgement become visible again after the visibility timeout
+    /// regardless of the cursor and are dead-lettered once they have used up
+    /// their delivery attempts, raising an `ErrorAlert` for the EA. Events
+    /// that need no acknowledgement leave the outbox as soon as they are
+    /// delivered.
+    fn deliver_outbox(
+        &mut self,
+        sequences: &mut AccountSequences,
+        cursor: u64,
+        limit: Option<usize>,
+        config: &OutboxDeliveryConfig,
+        now: OffsetDateTime,
+    ) -> OutboxDelivery {
+        if self.status.is_pending() {
+            return OutboxDelivery::default();
+        }
+
+        let is_visible = |event: &OutboundEvent| {
+            !event.is_expired(now)
+                && match event.visible_at(config.visibility_timeout) {
+                    Some(visible_at) => event.requires_ack && visible_at <= now,
+                    None => event.sequence > cursor,
+                }
+        };
+
+        let mut dead_lettered = Vec::new();
+        let mut remaining = Vec::with_capacity(self.outbox.len());
+        for event in self.outbox.drain(..) {
+            if event.delivery_attempts >= config.max_attempts && is_visible(&event) {
+                dead_lettered.push(DeadLetter {
+                    event,
+                    dead_lettered_at: now,
+                });
+            } else {
+                remaining.push(event);
+            }
+        }
+        self.outbox = remaining;
+        self.dead_letters.extend(dead_lettered.iter().cloned());
+        for DeadLetter { event, .. } in &dead_lettered {
+            self.enqueue_outbox(
+                sequences,
+                OutboxEventRequest {
+                    event_type: "ErrorAlert".to_string(),
+                    payload: json!({
+                        "code": "outbox_event_dead_lettered",
+                        "message": "an outbox event was not acknowledged after repeated deliveries",
+                        "eventId": event.id,
+                        "eventType": event.event_type,
+                        "sequence": event.sequence,
+                        "deliveryAttempts": event.delivery_attempts,
+                    }),
+                    requires_ack: false,
+                    expires_at: None,
+                },
+            );
+        }
+
+        let mut events = Vec::new();
+        let mut has_more = false;
+        for event in self.outbox.iter_mut() {
+            if !is_visible(event) {
+                continue;
+            }
+            if limit.is_some_and(|limit| events.len() >= limit) {
+                has_more = true;
+                break;
+            }
+
+            event.delivery_attempts += 1;
+            event.last_delivered_at = Some(now);
+            events.push(event.clone());
+        }
+
+        // Events that need no acknowledgement are done with once handed out or
+        // once the EA's cursor has moved past them.
+        self.outbox.retain(|event| {
+            event.requires_ack || (event.last_delivered_at.is_none() && event.sequence > cursor)
+        });
+
+        OutboxDelivery {
+            events,
+            dead_lettered,
+            has_more,
+        }
+    }
+
+    /// Poll back-off hint: the maximum while pending or terminated, the minimum
+    /// while events are being delivered, are waiting to be, or still await an
+    /// acknowledgement, and growing with the time since the last event was
+    /// queued otherwise.
+    fn retry_after(
+        &self,
+        config: &OutboxDeliveryConfig,
+        delivering: bool,
+        now: OffsetDateTime,
+    ) -> Duration {
+        if self.status.is_pending() || self.status == SessionStatus::Terminated {
+            return config.max_retry_after;
+        }
+        // Delivered notices and expired events no longer keep the session busy.
+        let busy = self.outbox.iter().any(|event| {
+            !event.is_expired(now) && (event.requires_ack || event.last_delivered_at.is_none())
+        });
+        if delivering || busy {
+            return config.min_retry_after;
+        }
+
+        let idle = self
+            .last_enqueued_at
+            .map(|enqueued_at| Duration::try_from(now - enqueued_at).unwrap_or_default());
+        config.retry_after(idle)
+    }
+
+    /// Earliest time at which an in-flight outbox event becomes visible again.
+    fn next_redelivery_at(&self, config: &OutboxDeliveryConfig) -> Option<OffsetDateTime> {
+        self.outbox
+            .iter()
+            .filter(|event| event.requires_ack)
+            .filter_map(|event| event.visible_at(config.visibility_timeout))
+            .min()
+    }
+
+    /// Moves a dead-lettered event back into the outbox under a fresh sequence
+    /// number so that cursor-following clients pick it up again.
+    fn requeue_dead_letter(
+        &mut self,
+        sequences: &mut AccountSequences,
+        event_id: Uuid,
+    ) -> Option<OutboundEvent> {
+        let index = self
+            .dead_letters
+            .iter()
+            .position(|dead_letter| dead_letter.event.id == event_id)?;
+        let mut event = self.dead_letters.remove(index).event;
+
+        event.sequence = sequences.next_outbox();
+        event.delivery_attempts = 0;
+        event.last_delivered_at = None;
+        self.outbox.push(event.clone());
+        self.updated_at = current_time();
+        self.last_enqueued_at = Some(self.updated_at);
+        self.outbox_notify.notify_waiters();
+        Some(event)
+    }
+}
+
+async fn health_handler() -> impl IntoResponse {
+    Json(health_check())
+}
+
+async fn create_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    FingerprintedJson {
+        payload,
+        fingerprint,
+    }: FingerprintedJson<SessionCreateRequest>,
+) -> Result<Response, ApiError> {
+    let account = account_from_headers(&headers)?;
+    let idempotency = idempotency_key(&headers)?;
+    let storage_key =
+        idempotency_storage_key("POST", "/trade-agent/v1/sessions", &account, &idempotency);
+
+    state
+        .run_idempotent(
+            storage_key,
+            fingerprint,
+            open_session(state.clone(), account, payload),
+        )
+        .await
+}
+
+async fn open_session(
+    state: AppState,
+    account: String,
+    payload: SessionCreateRequest,
+) -> Result<StoredResponse, ApiError> {
+    if payload.authentication_key.trim().is_empty() {
+        return Err(ApiError::bad_request(
+            "authentication_key_empty",
+            "authentication_key must not be empty",
+        ));
+    }
+
+    let auth_hash = hash_secret(payload.auth_method, &payload.authentication_key, &account);
+
+    let handle = state.account_or_default(&account).await;
+    let mut account_sessions = handle.lock().await;
+
+    let preempted = account_sessions.preempt_existing(&auth_hash);
+    let previous_session_id = preempted.as_ref().map(|preempted| preempted.session_id);
+
+    if let Some(previous) = previous_session_id {
+        info!(account = %account, previous_session = %previous, "preempting previous session");
+    }
+
+    // Everything queued for the new session is numbered above this mark, so the
+    // EA can resume its cursor from it after a reconnect.
+    let outbox_high_water_mark = account_sessions.outbox_high_water_mark();
+    let mut session = SessionRecord::new(payload.auth_method, auth_hash.clone());
+
+    if let Some(preapproval) = account_sessions.consume_preapproval(&auth_hash) {
+        match session.promote(
+            &mut account_sessions.sequences,
+            &auth_hash,
+            preapproval.expires_at,
+        ) {
+            Ok(_) => match preapproval.approved_by.as_deref() {
+                Some(operator) if !operator.is_empty() => info!(
+                    account = %account,
+                    session = %session.session_id,
+                    operator,
+                    "session authenticated via pre-approval",
+                ),
+                _ => info!(
+                    account = %account,
+                    session = %session.session_id,
+                    "session authenticated via pre-approval",
+                ),
+            },
+            Err(error) => warn!(
+                account = %account,
+                session = %session.session_id,
+                %error,
+                "failed to promote pre-approved session",
+            ),
+        }
     }
+
+    if let Some(preempted) = preempted {
+        state.carry_over_outbox(
+            &account,
+            preempted,
+            &mut session,
+            &mut account_sessions.sequences,
+        );
+    }
+
+    let response_body = session.to_create_response(previous_session_id, outbox_high_water_mark);
+
+    let stored = StoredResponse::from_json(StatusCode::CREATED, &response_body)
+        .map_err(|error| ApiError::internal(error.to_string()))?;
+
+    account_sessions.insert(session);
+    state.persist_sessions(&account, &account_sessions).await;
+
+    info!(account = %account, session = %response_body.session_id, "session created");
+
+    Ok(stored)
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "snake_case")]
-pub enum AuthMethod {
-    AccountSessionKey,
-    PreSharedKey,
+async fn delete_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let account = account_from_headers(&headers)?;
+    let idempotency = idempotency_key(&headers)?;
+    let token = bearer_token(&headers)?;
+    let fingerprint = body_fingerprint(&Value::Null);
+    let storage_key = idempotency_storage_key(
+        "DELETE",
+        "/trade-agent/v1/sessions/current",
+        &account,
+        &idempotency,
+    );
+
+    state
+        .run_idempotent(
+            storage_key,
+            fingerprint,
+            close_session(state.clone(), account, token),
+        )
+        .await
 }
 
-impl Default for AuthMethod {
-    fn default() -> Self {
-        AuthMethod::AccountSessionKey
-    }
-}
+async fn close_session(
+    state: AppState,
+    account: String,
+    token: Uuid,
+) -> Result<StoredResponse, ApiError> {
+    let Some(handle) = state.account(&account).await else {
+        return Err(ApiError::unauthorized(
+            "session_missing",
+            "no active session for the supplied account",
+        ));
+    };
+    let mut account_sessions = handle.lock().await;
 
-impl AuthMethod {
-    const fn storage_key(self) -> &'static str {
-        match self {
-            AuthMethod::AccountSessionKey => "account_session_key",
-            AuthMethod::PreSharedKey => "pre_shared_key",
-        }
-    }
-}
+    let (session_id, remove_account) = {
+        let Some(session) = account_sessions.get_mut_by_token(&token) else {
+            return Err(ApiError::unauthorized(
+                "invalid_session_token",
+                "the provided session token is not valid for this account",
+            ));
+        };
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct SessionCreateRequest {
-    #[serde(default)]
-    auth_method: AuthMethod,
-    authentication_key: String,
-}
+        let session_id = session.session_id;
+        account_sessions.remove_by_token(&token);
+        (session_id, account_sessions.is_empty())
+    };
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct SessionCreateResponse {
-    session_id: Uuid,
-    session_token: Uuid,
-    status: SessionStatus,
-    auth_method: AuthMethod,
-    pending: bool,
-    created_at: OffsetDateTime,
-    last_heartbeat_at: Option<OffsetDateTime>,
-    previous_session_terminated: Option<Uuid>,
-}
+    state.persist_sessions(&account, &account_sessions).await;
+    drop(account_sessions);
+    drop(handle);
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct InboxBatch {
-    events: Vec<InboxEvent>,
-}
+    if remove_account {
+        state.release_account_if_empty(&account).await;
+    }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct InboxEvent {
-    event_type: String,
-    #[serde(default)]
-    payload: Value,
-    #[serde(default, with = "time::serde::rfc3339::option")]
-    occurred_at: Option<OffsetDateTime>,
-}
+    info!(account = %account, session = %session_id, "session deleted");
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct InboundEventRecord {
-    id: Uuid,
-    sequence: u64,
-    event_type: String,
-    payload: Value,
-    #[serde(with = "time::serde::rfc3339::option")]
-    occurred_at: Option<OffsetDateTime>,
-    #[serde(with = "time::serde::rfc3339")]
-    received_at: OffsetDateTime,
+    Ok(StoredResponse::empty(StatusCode::NO_CONTENT))
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "lowercase")]
-pub(crate) enum TradeCommandType {
-    Open,
-    Close,
-}
+async fn ingest_inbox_events(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    FingerprintedJson {
+        payload,
+        fingerprint,
+    }: FingerprintedJson<InboxBatch>,
+) -> Result<Response, ApiError> {
+    let account = account_from_headers(&headers)?;
+    let idempotency = idempotency_key(&headers)?;
+    let token = bearer_token(&headers)?;
+    let storage_key = idempotency_storage_key(
+        "POST",
+        "/trade-agent/v1/sessions/current/inbox",
+        &account,
+        &idempotency,
+    );
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "lowercase")]
-pub(crate) enum TradeSide {
-    Buy,
-    Sell,
+    state
+        .run_idempotent(
+            storage_key,
+            fingerprint,
+            capture_inbox_events(state.clone(), account, token, payload.events),
+        )
+        .await
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "snake_case")]
-pub(crate) enum TradeOrderType {
-    Market,
-    Limit,
-    Stop,
-    StopLimit,
+async fn capture_inbox_events(
+    state: AppState,
+    account: String,
+    token: Uuid,
+    events: Vec<InboxEvent>,
+) -> Result<StoredResponse, ApiError> {
+    let response_body = state.capture_inbox(&account, token, events).await?;
+    StoredResponse::from_json(StatusCode::ACCEPTED, &response_body)
+        .map_err(|error| ApiError::internal(error.to_string()))
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "snake_case")]
-pub(crate) enum TradeTimeInForce {
-    Gtc,
-    Gtd,
-    Gfd,
-    Ioc,
-    Fok,
-}
+async fn fetch_outbox_events(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<OutboxQuery>,
+) -> Result<Response, ApiError> {
+    let account = account_from_headers(&headers)?;
+    let token = bearer_token(&headers)?;
+    let wait = Duration::from_millis(query.wait_ms.unwrap_or_default().min(MAX_OUTBOX_WAIT_MS));
+    let deadline = Instant::now() + wait;
+    let cursor = query.cursor.unwrap_or_default();
+    let mut ack_through = query.ack_through;
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub(crate) struct TradeOrderRequest {
-    command_type: TradeCommandType,
-    instrument: String,
-    #[serde(default)]
-    order_type: Option<TradeOrderType>,
-    #[serde(default)]
-    side: Option<TradeSide>,
-    #[serde(default)]
-    volume: Option<f64>,
-    #[serde(default)]
-    price: Option<f64>,
-    #[serde(default)]
-    stop_loss: Option<f64>,
-    #[serde(default)]
-    take_profit: Option<f64>,
-    #[serde(default)]
-    time_in_force: Option<TradeTimeInForce>,
-    #[serde(default)]
-    position_id: Option<String>,
-    #[serde(default)]
-    client_order_id: Option<String>,
-    #[serde(default)]
-    metadata: Option<Value>,
-}
+    loop {
+        let Some(handle) = state.account(&account).await else {
+            return Err(ApiError::unauthorized(
+                "session_missing",
+                "no active session for the supplied account",
+            ));
+        };
+        let mut account_sessions = handle.lock().await;
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub(crate) struct TradeCommandQueued {
-    session_id: Uuid,
-    event_id: Uuid,
-    sequence: u64,
-    command_id: Uuid,
-    pending_session: bool,
-    command_type: TradeCommandType,
-    instrument: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    order_type: Option<TradeOrderType>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    side: Option<TradeSide>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    position_id: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    volume: Option<f64>,
-}
+        let now = current_time();
+        let lapsed = account_sessions.expire_lapsed_approvals(now);
+        let expired = account_sessions.expire_outbox_events(now);
+        if !lapsed.is_empty() || !expired.is_empty() {
+            state.persist_sessions(&account, &account_sessions).await;
+        }
+        state.report_expired_events(&account, &expired);
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub(crate) struct OutboxEventRequest {
-    event_type: String,
-    #[serde(default)]
-    payload: Value,
-    #[serde(default = "default_requires_ack")]
-    requires_ack: bool,
-}
+        let Some((session, sequences)) = account_sessions.session_with_sequences_by_token(&token)
+        else {
+            return Err(ApiError::unauthorized(
+                "invalid_session_token",
+                "the provided session token is not valid for this account",
+            ));
+        };
 
-const fn default_requires_ack() -> bool {
-    true
-}
+        let acknowledged = ack_through
+            .take()
+            .map(|sequence| session.acknowledge_through(sequence))
+            .unwrap_or_default();
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct OutboundEvent {
-    id: Uuid,
-    sequence: u64,
-    event_type: String,
-    payload: Value,
-    enqueued_at: OffsetDateTime,
-    requires_ack: bool,
-}
+        let OutboxDelivery {
+            events,
+            dead_lettered,
+            has_more,
+        } = session.deliver_outbox(sequences, cursor, query.limit, &state.delivery, now);
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct OutboxResponse {
-    session_id: Uuid,
-    pending: bool,
-    events: Vec<OutboundEvent>,
-    retry_after_ms: u64,
-}
+        state.report_dead_letters(&account, session.session_id, &dead_lettered);
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct AckResponse {
-    acknowledged_event_id: Uuid,
-    remaining_outbox_depth: usize,
-}
+        let changed = !events.is_empty() || !dead_lettered.is_empty() || !acknowledged.is_empty();
+        let session_id = session.session_id;
+        let pending = session.status.is_pending();
+        let terminated = session.status == SessionStatus::Terminated;
+        let next_redelivery_at = session.next_redelivery_at(&state.delivery);
+        let notify = session.outbox_notify.clone();
+        // A long-polling client can reconnect straight away; a plain poll backs
+        // off according to how busy the session is.
+        let retry_after = if wait.is_zero() {
+            session.retry_after(&state.delivery, !events.is_empty(), now)
+        } else {
+            Duration::ZERO
+        };
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct InboxResponse {
-    accepted: usize,
-    pending_session: bool,
-}
+        account_sessions.commands.update(
+            |ledger| ledger.mark_delivered(&events, now),
+            |delivered| *delivered > 0,
+        );
+        if !acknowledged.is_empty() {
+            debug!(
+                account = %account,
+                session = %session_id,
+                acknowledged = acknowledged.len(),
+                "acknowledged outbox events through poll cursor",
+            );
+            account_sessions.record_acknowledged(acknowledged, &state.delivery, now);
+        }
 
-#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
-#[serde(rename_all = "camelCase")]
-pub struct SessionPromotionResponse {
-    session_id: Uuid,
-    status: SessionStatus,
-    pending: bool,
-    message: String,
-}
+        if !events.is_empty() || terminated || Instant::now() >= deadline {
+            let next_cursor = events
+                .iter()
+                .map(|event| event.sequence)
+                .fold(cursor, u64::max);
+            let response = OutboxResponse {
+                session_id,
+                pending,
+                events,
+                next_cursor,
+                has_more,
+                highest_acknowledged_sequence: account_sessions.highest_acknowledged_sequence,
+                retry_after_ms: u64::try_from(retry_after.as_millis()).unwrap_or(u64::MAX),
+            };
+            if changed {
+                state.persist_sessions(&account, &account_sessions).await;
+            }
 
-#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
-#[serde(rename_all = "camelCase")]
-pub struct SessionRejectionResponse {
-    session_id: Uuid,
-    status: SessionStatus,
-    message: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    reason: Option<String>,
+            let retry_after_secs = retry_after.as_millis().div_ceil(1_000);
+            return Ok((
+                StatusCode::OK,
+                [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                Json(response),
+            )
+                .into_response());
+        }
+
+        // Also wake up when an in-flight event becomes visible again.
+        let redelivery = next_redelivery_at.map(|visible_at| {
+            let remaining = (visible_at - now).max(time::Duration::ZERO);
+            Instant::now() + Duration::try_from(remaining).unwrap_or_default()
+        });
+        let wake_at = redelivery.map_or(deadline, |redelivery| redelivery.min(deadline));
+
+        // Register for the wake-up before releasing the account lock so that an
+        // event enqueued in between cannot be missed.
+        let notified = notify.notified();
+        if changed {
+            state.persist_sessions(&account, &account_sessions).await;
+        }
+        drop(account_sessions);
+
+        let _ = timeout_at(wake_at, notified).await;
+    }
 }
 
-#[derive(Debug, Clone)]
-pub enum AdminCommandOutcome {
-    SessionAuthenticated(SessionPromotionResponse),
-    SessionRejected(SessionRejectionResponse),
+async fn fetch_outbox_depth(
+    State(state): State<AppState>,
+    Path((account, session_id)): Path<(String, Uuid)>,
+) -> Result<Json<OutboxDepthResponse>, ApiError> {
+    state.outbox_depth(&account, session_id).await.map(Json)
 }
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub(crate) struct OutboxEnqueueResponse {
-    session_id: Uuid,
-    event_id: Uuid,
-    sequence: u64,
-    pending_session: bool,
+async fn list_dead_letters(
+    State(state): State<AppState>,
+    Path((account, session_id)): Path<(String, Uuid)>,
+) -> Result<Json<DeadLetterListResponse>, ApiError> {
+    state.dead_letters(&account, session_id).await.map(Json)
 }
 
-#[derive(Debug, Deserialize)]
-struct OutboxQuery {
-    #[serde(default)]
-    cursor: Option<u64>,
-    #[serde(default)]
-    limit: Option<usize>,
+async fn requeue_dead_letter(
+    State(state): State<AppState>,
+    Path((account, session_id, event_id)): Path<(String, Uuid, Uuid)>,
+) -> Result<Json<OutboxEnqueueResponse>, ApiError> {
+    state
+        .requeue_dead_letter(&account, session_id, event_id)
+        .await
+        .map(Json)
 }
 
-struct SessionRecord {
-    session_id: Uuid,
-    session_token: Uuid,
-    status: SessionStatus,
-    auth_method: AuthMethod,
-    auth_key_hash: String,
-    created_at: OffsetDateTime,
-    updated_at: OffsetDateTime,
-    last_heartbeat_at: Option<OffsetDateTime>,
-    next_sequence: u64,
-    next_inbox_sequence: u64,
-    outbox: Vec<OutboundEvent>,
-    inbox_log: Vec<InboundEventRecord>,
+async fn resync_outbox(
+    State(state): State<AppState>,
+    Path((account, session_id)): Path<(String, Uuid)>,
+    Json(request): Json<ResyncRequest>,
+) -> Result<Json<ResyncResponse>, ApiError> {
+    state
+        .resync_outbox(&account, session_id, request)
+        .await
+        .map(Json)
 }
 
-struct SessionRejectionOutcome {
-    response: SessionRejectionResponse,
-    already_terminated: bool,
+async fn fetch_portfolio(
+    State(state): State<AppState>,
+    Path(account): Path<String>,
+) -> Result<Json<PortfolioResponse>, ApiError> {
+    state.portfolio(&account).await.map(Json)
 }
 
-enum TerminationReason {
-    Preempted,
-    Rejected {
-        rejected_by: Option<String>,
-        reason: Option<String>,
-    },
+async fn fetch_command(
+    State(state): State<AppState>,
+    Path((account, command_id)): Path<(String, Uuid)>,
+) -> Result<Json<CommandRecord>, ApiError> {
+    state.command(&account, command_id).await.map(Json)
 }
 
-impl TerminationReason {
-    fn into_payload(self, terminated_at: OffsetDateTime) -> Value {
-        match self {
-            TerminationReason::Preempted => json!({
-                "reason": "session_preempted",
-                "terminatedAt": terminated_at,
-            }),
-            TerminationReason::Rejected {
-                rejected_by,
-                reason,
-            } => {
-                let mut payload = Map::new();
-                payload.insert(
-                    "reason".to_string(),
-                    Value::String("session_rejected".to_string()),
-                );
-                payload.insert("terminatedAt".to_string(), json!(terminated_at));
-                if let Some(reason) = reason {
-                    if !reason.is_empty() {
-                        payload.insert("details".to_string(), Value::String(reason));
-                    }
-                }
-                if let Some(operator) = rejected_by {
-                    if !operator.is_empty() {
-                        payload.insert("rejectedBy".to_string(), Value::String(operator));
-                    }
-                }
-                Value::Object(payload)
-            }
-        }
-    }
+async fn list_commands(
+    State(state): State<AppState>,
+    Path(account): Path<String>,
+    Query(query): Query<CommandQuery>,
+) -> Result<Json<CommandListResponse>, ApiError> {
+    state.commands(&account, query).await.map(Json)
 }
 
-impl SessionRecord {
-    fn new(auth_method: AuthMethod, auth_key_hash: String) -> Self {
-        let now = current_time();
-        Self {
-            session_id: Uuid::new_v4(),
-            session_token: Uuid::new_v4(),
-            status: SessionStatus::Pending,
-            auth_method,
-            auth_key_hash,
-            created_at: now,
-            updated_at: now,
-            last_heartbeat_at: None,
-            next_sequence: 1,
-            next_inbox_sequence: 1,
-            outbox: Vec::new(),
-            inbox_log: Vec::new(),
-        }
-    }
+async fn acknowledge_outbox_batch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    FingerprintedJson {
+        payload,
+        fingerprint,
+    }: FingerprintedJson<OutboxBatchAckRequest>,
+) -> Result<Response, ApiError> {
+    let account = account_from_headers(&headers)?;
+    let idempotency = idempotency_key(&headers)?;
+    let token = bearer_token(&headers)?;
+    let storage_key = idempotency_storage_key(
+        "POST",
+        "/trade-agent/v1/sessions/current/outbox/ack",
+        &account,
+        &idempotency,
+    );
 
-    fn to_create_response(&self, previous_session: Option<Uuid>) -> SessionCreateResponse {
-        SessionCreateResponse {
-            session_id: self.session_id,
-            session_token: self.session_token,
-            status: self.status,
-            auth_method: self.auth_method,
-            pending: self.status.is_pending(),
-            created_at: self.created_at,
-            last_heartbeat_at: self.last_heartbeat_at,
-            previous_session_terminated: previous_session,
-        }
-    }
+    state
+        .run_idempotent(
+            storage_key,
+            fingerprint,
+            acknowledge_batch(state.clone(), account, token, payload),
+        )
+        .await
+}
 
-    fn verify_secret(&self, candidate_hash: &str) -> bool {
-        self.auth_key_hash == candidate_hash
-    }
+async fn acknowledge_batch(
+    state: AppState,
+    account: String,
+    token: Uuid,
+    request: OutboxBatchAckRequest,
+) -> Result<StoredResponse, ApiError> {
+    let response_body = state
+        .acknowledge_outbox_batch(&account, token, request)
+        .await?;
+    StoredResponse::from_json(StatusCode::OK, &response_body)
+        .map_err(|error| ApiError::internal(error.to_string()))
+}
 
-    fn promote(
-        &mut self,
-        fingerprint: &str,
-    ) -> Result<SessionPromotionResponse, AdminCommandError> {
-        if !self.verify_secret(fingerprint) {
-            return Err(AdminCommandError::AuthenticationFailed);
-        }
+async fn acknowledge_outbox_event(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(event_id): Path<Uuid>,
+) -> Result<Response, ApiError> {
+    let account = account_from_headers(&headers)?;
+    let idempotency = idempotency_key(&headers)?;
+    let token = bearer_token(&headers)?;
+    let fingerprint = body_fingerprint(&Value::Null);
+    let storage_key = idempotency_storage_key(
+        "POST",
+        &format!("/trade-agent/v1/sessions/current/outbox/{event_id}/ack"),
+        &account,
+        &idempotency,
+    );
 
-        if self.status == SessionStatus::Terminated {
-            return Err(AdminCommandError::SessionTerminated);
-        }
+    state
+        .run_idempotent(
+            storage_key,
+            fingerprint,
+            acknowledge_event(state.clone(), account, token, event_id),
+        )
+        .await
+}
 
-        if self.status == SessionStatus::Authenticated {
-            return Ok(SessionPromotionResponse {
-                session_id: self.session_id,
-                status: self.status,
-                pending: false,
-                message: "session already authenticated".to_string(),
-            });
-        }
+async fn acknowledge_event(
+    state: AppState,
+    account: String,
+    token: Uuid,
+    event_id: Uuid,
+) -> Result<StoredResponse, ApiError> {
+    let response_body = state.acknowledge_outbox(&account, token, event_id).await?;
+    StoredResponse::from_json(StatusCode::OK, &response_body)
+        .map_err(|error| ApiError::internal(error.to_string()))
+}
 
-        self.mark_authenticated();
-        self.enqueue_init_ack();
+fn account_from_headers(headers: &HeaderMap) -> Result<String, ApiError> {
+    header_value(headers, "X-TradeAgent-Account", "missing_account_header")
+}
 
-        Ok(SessionPromotionResponse {
-            session_id: self.session_id,
-            status: self.status,
-            pending: false,
-            message: "session authenticated".to_string(),
-        })
-    }
+fn idempotency_key(headers: &HeaderMap) -> Result<String, ApiError> {
+    header_value(headers, "Idempotency-Key", "missing_idempotency_key")
+}
 
-    fn reject(
-        &mut self,
-        reason: Option<String>,
-        rejected_by: Option<String>,
-    ) -> SessionRejectionOutcome {
-        let already_terminated = self.status == SessionStatus::Terminated;
+fn header_value(
+    headers: &HeaderMap,
+    name: &str,
+    error_code: &'static str,
+) -> Result<String, ApiError> {
+    headers
+        .get(name)
+        .ok_or_else(|| ApiError::bad_request(error_code, format!("{name} header is required")))
+        .and_then(|value| {
+            value.to_str().map_err(|_| {
+                ApiError::bad_request(
+                    "invalid_header_encoding",
+                    format!("{name} header must contain valid UTF-8"),
+                )
+            })
+        })
+        .and_then(|value| {
+            let trimmed = value.trim();
+            if trimmed.is_empty() {
+                Err(ApiError::bad_request(
+                    "empty_header_value",
+                    format!("{name} header must not be empty"),
+                ))
+            } else {
+                Ok(trimmed.to_string())
+            }
+        })
+}
 
-        if !already_terminated {
-            self.mark_terminated(TerminationReason::Rejected {
-                rejected_by: rejected_by.clone(),
-                reason: reason.clone(),
-            });
-        }
+fn bearer_token(headers: &HeaderMap) -> Result<Uuid, ApiError> {
+    let value = headers.get(header::AUTHORIZATION).ok_or_else(|| {
+        ApiError::bad_request(
+            "missing_authorization_header",
+            "Authorization header with Bearer token is required",
+        )
+    })?;
 
-        let message = if already_terminated {
-            "session already terminated"
-        } else {
-            "session rejected"
-        };
+    let value = value.to_str().map_err(|_| {
+        ApiError::bad_request(
+            "invalid_header_encoding",
+            "Authorization header must be valid UTF-8",
+        )
+    })?;
 
-        SessionRejectionOutcome {
-            response: SessionRejectionResponse {
-                session_id: self.session_id,
-                status: self.status,
-                message: message.to_string(),
-                reason,
-            },
-            already_terminated,
-        }
-    }
+    let Some(token) = value.strip_prefix("Bearer ") else {
+        return Err(ApiError::bad_request(
+            "invalid_authorization_scheme",
+            "Authorization header must use the Bearer scheme",
+        ));
+    };
 
-    fn mark_authenticated(&mut self) {
-        self.status = SessionStatus::Authenticated;
-        self.updated_at = current_time();
-    }
+    Uuid::parse_str(token.trim()).map_err(|_| {
+        ApiError::bad_request(
+            "invalid_session_token",
+            "Authorization token must be a valid UUID",
+        )
+    })
+}
 
-    fn mark_terminated(&mut self, reason: TerminationReason) {
-        if self.status == SessionStatus::Terminated {
-            return;
-        }
+fn idempotency_storage_key(method: &str, path: &str, account: &str, key: &str) -> String {
+    format!("{method}:{path}:{account}:{key}")
+}
 
-        self.status = SessionStatus::Terminated;
-        let terminated_at = current_time();
-        let payload = reason.into_payload(terminated_at);
+fn hash_secret(method: AuthMethod, secret: &str, account: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.storage_key().as_bytes());
+    hasher.update(b":");
+    hasher.update(account.as_bytes());
+    hasher.update(b":");
+    hasher.update(secret.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
 
-        let request = OutboxEventRequest {
-            event_type: "ShutdownNotice".to_string(),
-            payload,
-            requires_ack: true,
-        };
+fn current_time() -> OffsetDateTime {
+    OffsetDateTime::now_utc()
+}
 
-        self.enqueue_outbox(request);
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn enqueue_outbox(&mut self, event: OutboxEventRequest) -> OutboundEvent {
-        let enqueued_at = current_time();
-        let outbound = OutboundEvent {
-            id: Uuid::new_v4(),
-            sequence: self.next_sequence,
-            event_type: event.event_type,
-            payload: event.payload,
-            enqueued_at,
-            requires_ack: event.requires_ack,
-        };
-        self.next_sequence += 1;
-        self.outbox.push(outbound.clone());
-        self.updated_at = enqueued_at;
-        outbound
+    /// Identifiers of a session inserted by [`promoted_session`].
+    struct PromotedSession {
+        session_id: Uuid,
+        token: Uuid,
+        init_ack: Uuid,
     }
 
-    fn enqueue_init_ack(&mut self) {
-        let request = OutboxEventRequest {
-            event_type: "InitAck".to_string(),
-            payload: json!({
-                "message": "Session authenticated",
-            }),
-            requires_ack: true,
+    /// Inserts an authenticated session for `account` whose `InitAck` is still
+    /// waiting in the outbox.
+    async fn promoted_session(state: &AppState, account: &str) -> PromotedSession {
+        let auth_hash = hash_secret(AuthMethod::AccountSessionKey, "secret", account);
+        let mut session = SessionRecord::new(AuthMethod::AccountSessionKey, auth_hash.clone());
+        session
+            .promote(&mut AccountSequences::default(), &auth_hash, None)
+            .expect("promotion should succeed");
+        let promoted = PromotedSession {
+            session_id: session.session_id,
+            token: session.session_token,
+            init_ack: session.outbox[0].id,
         };
-        self.enqueue_outbox(request);
+        state.insert_session_for_test(account, session).await;
+        promoted
     }
 
-    fn apply_outbox_ack(&mut self, payload: &Value) {
-        let Some(event_id_value) = payload.get("eventId") else {
-            debug!("outbox ack missing eventId field");
-            return;
-        };
-
-        let Some(event_id) = event_id_value.as_str() else {
-            debug!(?event_id_value, "outbox ack eventId is not a string");
-            return;
-        };
-
-        let Ok(event_uuid) = Uuid::parse_str(event_id) else {
-            debug!(%event_id, "failed to parse outbox ack eventId");
-            return;
-        };
-
-        let sequence = payload.get("sequence").and_then(Value::as_u64);
-        let removed = self.acknowledge_outbox(event_uuid);
-
-        if removed {
-            debug!(%event_uuid, sequence, "acknowledged outbox event via inbox");
-        } else {
-            debug!(%event_uuid, sequence, "outbox ack did not match a pending event");
+    impl TradeOrderRequest {
+        /// A request with only its command type and instrument set, for tests
+        /// to fill in with struct update syntax.
+        fn new(command_type: TradeCommandType, instrument: &str) -> Self {
+            Self {
+                command_type,
+                instrument: instrument.to_string(),
+                order_type: None,
+                side: None,
+                volume: None,
+                price: None,
+                stop_loss: None,
+                take_profit: None,
+                time_in_force: None,
+                position_id: None,
+                order_id: None,
+                original_command_id: None,
+                original_client_order_id: None,
+                client_order_id: None,
+                metadata: None,
+                expires_at: None,
+            }
         }
     }
 
-    fn capture_inbox(&mut self, batch: Vec<InboxEvent>) -> Vec<InboundEventRecord> {
-        let mut captured = Vec::with_capacity(batch.len());
-        for event in batch {
-            let sequence = self.next_inbox_sequence;
-            self.next_inbox_sequence += 1;
-            let received_at = current_time();
-            let event_type_lower = event.event_type.to_ascii_lowercase();
-            if event_type_lower.contains("heartbeat") {
-                self.last_heartbeat_at = Some(received_at);
-            }
+    #[tokio::test]
+    async fn session_enqueue_and_ack_flow() {
+        let account = "12345".to_string();
+        let hash = hash_secret(AuthMethod::AccountSessionKey, "secret", &account);
+        let mut sequences = AccountSequences::default();
+        let mut session = SessionRecord::new(AuthMethod::AccountSessionKey, hash);
 
-            if event_type_lower == "outboxack" {
-                self.apply_outbox_ack(&event.payload);
-            }
+        assert_eq!(session.status, SessionStatus::Pending);
+        assert!(session.outbox.is_empty());
 
-            let record = InboundEventRecord {
-                id: Uuid::new_v4(),
-                sequence,
-                event_type: event.event_type,
-                payload: event.payload,
-                occurred_at: event.occurred_at,
-                received_at,
-            };
-            self.inbox_log.push(record.clone());
-            captured.push(record);
-        }
+        let event = session.enqueue_outbox(
+            &mut sequences,
+            OutboxEventRequest {
+                event_type: "OrderCommand".to_string(),
+                payload: json!({"order_id": "abc"}),
+                requires_ack: true,
+                expires_at: None,
+            },
+        );
 
-        if !captured.is_empty() {
-            self.updated_at = current_time();
-        }
+        assert_eq!(event.sequence, 1);
+        assert_eq!(session.outbox.len(), 1);
+
+        let delivery = session.deliver_outbox(
+            &mut sequences,
+            0,
+            None,
+            &OutboxDeliveryConfig::default(),
+            current_time(),
+        );
+        assert!(
+            delivery.events.is_empty(),
+            "pending sessions hold events back"
+        );
 
-        captured
+        assert!(session.acknowledge_outbox(event.id).is_some());
+        assert!(session.outbox.is_empty());
     }
 
-    fn acknowledge_outbox(&mut self, event_id: Uuid) -> bool {
-        let initial_len = self.outbox.len();
-        self.outbox.retain(|event| event.id != event_id);
-        let removed = initial_len != self.outbox.len();
-        if removed {
-            self.updated_at = current_time();
-        }
-        removed
+    #[test]
+    fn timestamps_are_rfc3339() {
+        let hash = hash_secret(AuthMethod::AccountSessionKey, "secret", "account");
+        let session = SessionRecord::new(AuthMethod::AccountSessionKey, hash);
+        let mut snapshot = serde_json::to_value(&session).expect("serialize session");
+        assert!(snapshot["createdAt"].is_string());
+
+        snapshot["createdAt"] = json!("2024-03-27T02:09:11.124Z");
+        let restored: SessionRecord = serde_json::from_value(snapshot).expect("snapshot");
+        assert_eq!(restored.created_at.year(), 2024);
+        assert_eq!(restored.created_at.ordinal(), 87);
+        assert_eq!(
+            timestamp::to_value(restored.created_at),
+            json!("2024-03-27T02:09:11.124Z")
+        );
     }
 
-    fn events_after(
-        &self,
-        cursor: u64,
-        limit: Option<usize>,
-        include_when_pending: bool,
-    ) -> Vec<OutboundEvent> {
-        if self.status.is_pending() && !include_when_pending {
-            return Vec::new();
-        }
+    #[test]
+    fn hashing_is_deterministic() {
+        let hash1 = hash_secret(AuthMethod::AccountSessionKey, "secret", "account");
+        let hash2 = hash_secret(AuthMethod::AccountSessionKey, "secret", "account");
+        let hash3 = hash_secret(AuthMethod::AccountSessionKey, "secret", "other");
 
-        let iter = self.outbox.iter().filter(|event| event.sequence > cursor);
-        match limit {
-            Some(limit) => iter.take(limit).cloned().collect(),
-            None => iter.cloned().collect(),
-        }
+        assert_eq!(hash1, hash2);
+        assert_ne!(hash1, hash3);
     }
-}
-
-async fn health_handler() -> impl IntoResponse {
-    Json(health_check())
-}
 
-async fn create_session(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<SessionCreateRequest>,
-) -> Result<Response, ApiError> {
-    let account = account_from_headers(&headers)?;
-    let idempotency = idempotency_key(&headers)?;
-    let storage_key =
-        idempotency_storage_key("POST", "/trade-agent/v1/sessions", &account, &idempotency);
+    #[test]
+    fn health_check_returns_ok_status() {
+        let status = health_check();
 
-    if let Some(stored) = state.stored_response(&storage_key).await {
-        return Ok(stored.into_response());
+        assert_eq!(status, HealthStatus::ok("gateway"));
+        assert!(status.healthy);
+        assert_eq!(status.message, "ok");
     }
 
-    if payload.authentication_key.trim().is_empty() {
-        return Err(ApiError::bad_request(
-            "authentication_key_empty",
-            "authentication_key must not be empty",
-        ));
-    }
+    #[tokio::test]
+    async fn service_bus_promotion_authenticates_session() {
+        let state = AppState::default();
+        let account = "acct-service".to_string();
+        let fingerprint = hash_secret(AuthMethod::AccountSessionKey, "secret", &account);
+        let session_id;
 
-    let auth_hash = hash_secret(payload.auth_method, &payload.authentication_key, &account);
+        {
+            let session = SessionRecord::new(AuthMethod::AccountSessionKey, fingerprint.clone());
+            session_id = session.session_id;
+            state.insert_session_for_test(&account, session).await;
+        }
 
-    let mut inner = state.inner.lock().await;
-    let account_sessions = inner.sessions.entry(account.clone()).or_default();
+        let promotion = state
+            .promote_session_with_fingerprint(&account, session_id, &fingerprint, Some("ops"))
+            .await
+            .expect("promotion should succeed");
 
-    let previous_session_id = account_sessions.preempt_existing(&auth_hash);
+        assert_eq!(promotion.session_id, session_id);
+        assert_eq!(promotion.status, SessionStatus::Authenticated);
+        assert!(!promotion.pending);
 
-    if let Some(previous) = previous_session_id {
-        info!(account = %account, previous_session = %previous, "preempting previous session");
+        let handle = state.account(&account).await.expect("account missing");
+        let mut account_sessions = handle.lock().await;
+        let session = account_sessions
+            .get_mut_by_session_id(&session_id)
+            .expect("session missing");
+        assert_eq!(session.status, SessionStatus::Authenticated);
+        assert_eq!(session.outbox.len(), 1);
+        assert_eq!(session.outbox[0].event_type, "InitAck");
     }
 
-    let mut session = SessionRecord::new(payload.auth_method, auth_hash.clone());
+    #[tokio::test]
+    async fn reject_session_marks_session_terminated() {
+        let state = AppState::default();
+        let account = "acct-reject".to_string();
+        let fingerprint = hash_secret(AuthMethod::AccountSessionKey, "secret", &account);
+        let session_id;
 
-    if let Some(preapproval) = account_sessions.consume_preapproval(&auth_hash) {
-        match session.promote(&auth_hash) {
-            Ok(_) => match preapproval.approved_by.as_deref() {
-                Some(operator) if !operator.is_empty() => info!(
-                    account = %account,
-                    session = %session.session_id,
-                    operator,
-                    "session authenticated via pre-approval",
-                ),
-                _ => info!(
-                    account = %account,
-                    session = %session.session_id,
-                    "session authenticated via pre-approval",
-                ),
-            },
-            Err(error) => warn!(
-                account = %account,
-                session = %session.session_id,
-                %error,
-                "failed to promote pre-approved session",
-            ),
+        {
+            let session = SessionRecord::new(AuthMethod::AccountSessionKey, fingerprint.clone());
+            session_id = session.session_id;
+            state.insert_session_for_test(&account, session).await;
         }
-    }
 
-    let response_body = session.to_create_response(previous_session_id);
+        let rejection = state
+            .reject_session(
+                &account,
+                session_id,
+                &fingerprint,
+                Some("not approved".to_string()),
+                Some("operator".to_string()),
+            )
+            .await
+            .expect("rejection should succeed");
 
-    let stored = StoredResponse::from_json(StatusCode::CREATED, &response_body)
-        .map_err(|error| ApiError::internal(error.to_string()))?;
+        assert_eq!(rejection.session_id, session_id);
+        assert_eq!(rejection.status, SessionStatus::Terminated);
+        assert_eq!(rejection.reason.as_deref(), Some("not approved"));
 
-    account_sessions.insert(session);
-    inner.idempotency.insert(storage_key, stored.clone());
+        let handle = state.account(&account).await.expect("account missing");
+        let mut account_sessions = handle.lock().await;
+        let session = account_sessions
+            .get_mut_by_session_id(&session_id)
+            .expect("session missing");
+        assert_eq!(session.status, SessionStatus::Terminated);
+        let shutdown = session.outbox.last().expect("missing shutdown");
+        assert_eq!(shutdown.event_type, "ShutdownNotice");
+        assert_eq!(
+            shutdown
+                .payload
+                .get("reason")
+                .and_then(|value| value.as_str()),
+            Some("session_rejected")
+        );
+        assert_eq!(
+            shutdown
+                .payload
+                .get("rejectedBy")
+                .and_then(|value| value.as_str()),
+            Some("operator")
+        );
+    }
 
-    info!(account = %account, session = %response_body.session_id, "session created");
+    #[tokio::test]
+    async fn reaper_times_out_silent_sessions_and_removes_drained_ones() {
+        let state = AppState::default();
+        let account = "acct-reaper";
+        let PromotedSession {
+            session_id,
+            init_ack,
+            ..
+        } = promoted_session(&state, account).await;
+
+        let config = SessionReaperConfig {
+            interval: Duration::from_secs(1),
+            heartbeat_timeout: Duration::from_secs(30),
+            reconnect_window: Duration::from_secs(5),
+        };
 
-    Ok(stored.into_response())
-}
+        let fresh = state.reap_sessions(&config, current_time()).await;
+        assert_eq!(fresh, ReapSummary::default());
+
+        let later = current_time() + time::Duration::seconds(31);
+        let summary = state.reap_sessions(&config, later).await;
+        assert_eq!(summary.timed_out, 1);
+        assert_eq!(summary.removed, 0);
+
+        let events = state.outbox_events_for_test(account, session_id).await;
+        let shutdown = events.last().expect("missing shutdown");
+        assert_eq!(shutdown.event_type, "ShutdownNotice");
+        assert_eq!(
+            shutdown.payload.get("reason").and_then(Value::as_str),
+            Some("heartbeat_timeout")
+        );
+        assert_eq!(
+            shutdown
+                .payload
+                .get("reconnectAfterMs")
+                .and_then(Value::as_u64),
+            Some(5_000)
+        );
 
-async fn delete_session(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-) -> Result<Response, ApiError> {
-    let account = account_from_headers(&headers)?;
-    let idempotency = idempotency_key(&headers)?;
-    let token = bearer_token(&headers)?;
-    let storage_key = idempotency_storage_key(
-        "DELETE",
-        "/trade-agent/v1/sessions/current",
-        &account,
-        &idempotency,
-    );
+        {
+            let handle = state.account(account).await.expect("account missing");
+            let mut account_sessions = handle.lock().await;
+            let session = account_sessions
+                .get_mut_by_session_id(&session_id)
+                .expect("session missing");
+            assert!(session.acknowledge_outbox(init_ack).is_some());
+            assert!(session.acknowledge_outbox(shutdown.id).is_some());
+        }
 
-    if let Some(stored) = state
-        .inner
-        .lock()
-        .await
-        .idempotency
-        .get(&storage_key)
-        .cloned()
-    {
-        return Ok(stored.into_response());
+        let summary = state.reap_sessions(&config, later).await;
+        assert_eq!(summary.removed, 1);
+        assert!(state.account(account).await.is_none());
     }
 
-    let mut inner = state.inner.lock().await;
-
-    let (session_id, remove_account) = {
-        let Some(account_sessions) = inner.sessions.get_mut(&account) else {
-            return Err(ApiError::unauthorized(
-                "session_missing",
-                "no active session for the supplied account",
-            ));
+    #[tokio::test]
+    async fn sequences_continue_after_the_account_is_reaped() {
+        let store = Arc::new(InMemorySessionStore::default());
+        let state = AppState::with_store(store.clone()).expect("store should load");
+        let account = "acct-reaped";
+        let session_id = promoted_session(&state, account).await.session_id;
+
+        let config = SessionReaperConfig {
+            interval: Duration::from_secs(1),
+            heartbeat_timeout: Duration::from_secs(30),
+            reconnect_window: Duration::from_secs(5),
         };
+        let later = current_time() + time::Duration::seconds(31);
+        assert_eq!(state.reap_sessions(&config, later).await.timed_out, 1);
+        {
+            let handle = state.account(account).await.expect("account missing");
+            let mut account_sessions = handle.lock().await;
+            let session = account_sessions
+                .get_mut_by_session_id(&session_id)
+                .expect("session missing");
+            let ids: Vec<Uuid> = session.outbox.iter().map(|event| event.id).collect();
+            for id in ids {
+                assert!(session.acknowledge_outbox(id).is_some());
+            }
+        }
+        assert_eq!(state.reap_sessions(&config, later).await.removed, 1);
+        assert!(state.account(account).await.is_none());
+        state.flush_sessions().await;
 
-        let Some(session) = account_sessions.get_mut_by_token(&token) else {
-            return Err(ApiError::unauthorized(
-                "invalid_session_token",
-                "the provided session token is not valid for this account",
-            ));
+        let reconnect = || SessionCreateRequest {
+            auth_method: AuthMethod::AccountSessionKey,
+            authentication_key: "secret".to_string(),
         };
+        let restarted = AppState::with_store(store).expect("store should load");
+        for state in [&state, &restarted] {
+            let created = open_session(state.clone(), account.to_string(), reconnect())
+                .await
+                .expect("reconnect should succeed");
+            let body = created.body.expect("missing create response body");
+            assert_eq!(body["outboxHighWaterMark"], json!(2));
+        }
+    }
 
+    #[tokio::test]
+    async fn approval_expiry_terminates_authenticated_session() {
+        let state = AppState::default();
+        let account = "acct-expiring";
+        let fingerprint = hash_secret(AuthMethod::AccountSessionKey, "secret", account);
+        let session = SessionRecord::new(AuthMethod::AccountSessionKey, fingerprint.clone());
         let session_id = session.session_id;
-        account_sessions.remove_by_token(&token);
-        (session_id, account_sessions.is_empty())
-    };
+        state.insert_session_for_test(account, session).await;
 
-    if remove_account {
-        inner.sessions.remove(&account);
-    }
+        let expires_at = current_time() + time::Duration::minutes(5);
+        let outcome = state
+            .apply_admin_command(AdminCommand::Approve(AdminApprovalCommand {
+                account_id: account.to_string(),
+                session_id,
+                auth_key_fingerprint: fingerprint,
+                approved_by: Some("ops".to_string()),
+                expires_at: Some(expires_at),
+            }))
+            .await
+            .expect("approval should succeed");
 
-    let stored = StoredResponse::empty(StatusCode::NO_CONTENT);
-    inner.idempotency.insert(storage_key, stored.clone());
+        let AdminCommandOutcome::SessionAuthenticated(promotion) = outcome else {
+            panic!("unexpected admin command outcome");
+        };
+        assert_eq!(promotion.approval_expires_at, Some(expires_at));
 
-    info!(account = %account, session = %session_id, "session deleted");
+        let config = SessionReaperConfig {
+            heartbeat_timeout: Duration::from_secs(3_600),
+            ..SessionReaperConfig::default()
+        };
+        let summary = state
+            .reap_sessions(&config, expires_at + time::Duration::seconds(1))
+            .await;
+        assert_eq!(summary.approvals_expired, 1);
+        assert_eq!(summary.timed_out, 0);
 
-    Ok(stored.into_response())
-}
+        let events = state.outbox_events_for_test(account, session_id).await;
+        let shutdown = events.last().expect("missing shutdown");
+        assert_eq!(shutdown.event_type, "ShutdownNotice");
+        assert_eq!(
+            shutdown.payload.get("reason").and_then(Value::as_str),
+            Some("approval_expired")
+        );
+    }
 
-async fn ingest_inbox_events(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<InboxBatch>,
-) -> Result<Response, ApiError> {
-    let account = account_from_headers(&headers)?;
-    let idempotency = idempotency_key(&headers)?;
-    let token = bearer_token(&headers)?;
-    let storage_key = idempotency_storage_key(
-        "POST",
-        "/trade-agent/v1/sessions/current/inbox",
-        &account,
-        &idempotency,
-    );
+    #[tokio::test]
+    async fn approval_with_elapsed_expiry_is_rejected() {
+        let state = AppState::default();
+        let account = "acct-stale-approval";
+        let fingerprint = hash_secret(AuthMethod::AccountSessionKey, "secret", account);
+        let session = SessionRecord::new(AuthMethod::AccountSessionKey, fingerprint.clone());
+        let session_id = session.session_id;
+        state.insert_session_for_test(account, session).await;
 
-    if let Some(stored) = state
-        .inner
-        .lock()
-        .await
-        .idempotency
-        .get(&storage_key)
-        .cloned()
-    {
-        return Ok(stored.into_response());
+        let error = state
+            .apply_admin_command(AdminCommand::Approve(AdminApprovalCommand {
+                account_id: account.to_string(),
+                session_id,
+                auth_key_fingerprint: fingerprint,
+                approved_by: None,
+                expires_at: Some(current_time() - time::Duration::seconds(1)),
+            }))
+            .await
+            .expect_err("expired approval should be rejected");
+
+        assert!(matches!(error, AdminCommandError::ApprovalExpired));
     }
 
-    let mut inner = state.inner.lock().await;
-    let events = payload.events;
+    #[tokio::test]
+    async fn enqueue_trade_command_generates_outbox_event() {
+        let state = AppState::default();
+        let account = "acct-trade";
+        let session_id = promoted_session(&state, account).await.session_id;
 
-    let (accepted, pending) = {
-        let Some(account_sessions) = inner.sessions.get_mut(&account) else {
-            return Err(ApiError::unauthorized(
-                "session_missing",
-                "no active session for the supplied account",
-            ));
-        };
+        let queued = state
+            .enqueue_trade_command(
+                account,
+                session_id,
+                TradeOrderRequest {
+                    order_type: Some(TradeOrderType::Market),
+                    side: Some(TradeSide::Buy),
+                    volume: Some(1.25),
+                    stop_loss: Some(Some(1.1)),
+                    time_in_force: Some(TradeTimeInForce::Gtc),
+                    client_order_id: Some("client-1".to_string()),
+                    ..TradeOrderRequest::new(TradeCommandType::Open, "EURUSD")
+                },
+            )
+            .await
+            .expect("trade command should be accepted");
 
-        let Some(session) = account_sessions.get_mut_by_token(&token) else {
-            return Err(ApiError::unauthorized(
-                "invalid_session_token",
-                "the provided session token is not valid for this account",
-            ));
-        };
+        assert_eq!(queued.session_id, session_id);
+        assert_eq!(queued.command_type, TradeCommandType::Open);
+        assert_eq!(queued.order_type, Some(TradeOrderType::Market));
+        assert_eq!(queued.side, Some(TradeSide::Buy));
+        assert_eq!(queued.volume, Some(1.25));
+        assert_eq!(queued.instrument, "EURUSD");
 
-        if session.status == SessionStatus::Terminated {
-            return Err(ApiError::forbidden(
-                "session_terminated",
-                "the session has been terminated and no longer accepts events",
-            ));
-        }
+        let events = state.outbox_events_for_test(account, session_id).await;
+        assert_eq!(events.len(), 2);
+        let event = events.last().expect("missing order event");
+        assert_eq!(event.id, queued.event_id);
+        assert_eq!(event.event_type, "OrderCommand");
+        assert!(event.requires_ack);
+    }
 
-        let captured = session.capture_inbox(events);
-        let accepted = captured.len();
-        let pending = session.status.is_pending();
-        debug!(account = %account, captured = accepted, "captured inbox events");
-        (accepted, pending)
-    };
+    #[tokio::test]
+    async fn enqueue_trade_command_requires_side_for_open() {
+        let state = AppState::default();
+        let account = "acct-invalid";
+        let session_id = promoted_session(&state, account).await.session_id;
 
-    let response_body = InboxResponse {
-        accepted,
-        pending_session: pending,
-    };
+        let error = state
+            .enqueue_trade_command(
+                account,
+                session_id,
+                TradeOrderRequest {
+                    order_type: Some(TradeOrderType::Market),
+                    volume: Some(1.0),
+                    ..TradeOrderRequest::new(TradeCommandType::Open, "EURUSD")
+                },
+            )
+            .await
+            .expect_err("missing side should be rejected");
 
-    let stored = StoredResponse::from_json(StatusCode::ACCEPTED, &response_body)
-        .map_err(|error| ApiError::internal(error.to_string()))?;
+        assert_eq!(error.code(), "side_required");
+    }
 
-    inner.idempotency.insert(storage_key, stored.clone());
+    #[tokio::test]
+    async fn modify_commands_need_one_target_and_a_change() {
+        let state = AppState::default();
+        let account = "acct-modify";
+        let session_id = promoted_session(&state, account).await.session_id;
 
-    Ok(stored.into_response())
-}
+        let modify =
+            |position_id: Option<&str>, order_id: Option<&str>, stop_loss: Option<f64>, price| {
+                TradeOrderRequest {
+                    price,
+                    stop_loss: stop_loss.map(Some),
+                    position_id: position_id.map(str::to_string),
+                    order_id: order_id.map(str::to_string),
+                    ..TradeOrderRequest::new(TradeCommandType::Modify, "EURUSD")
+                }
+            };
 
-async fn fetch_outbox_events(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Query(query): Query<OutboxQuery>,
-) -> Result<Response, ApiError> {
-    let account = account_from_headers(&headers)?;
-    let token = bearer_token(&headers)?;
+        for (request, code) in [
+            (
+                modify(None, None, Some(1.05), None),
+                "modify_target_required",
+            ),
+            (
+                modify(Some("101"), Some("202"), Some(1.05), None),
+                "modify_target_required",
+            ),
+            (
+                modify(Some("101"), None, None, None),
+                "modify_fields_required",
+            ),
+            (
+                modify(Some("101"), None, None, Some(1.1)),
+                "price_not_modifiable",
+            ),
+            (
+                TradeOrderRequest {
+                    side: Some(TradeSide::Sell),
+                    ..modify(Some("101"), None, Some(1.05), None)
+                },
+                "modify_fields_not_allowed",
+            ),
+            (
+                TradeOrderRequest {
+                    time_in_force: Some(TradeTimeInForce::Gtc),
+                    ..modify(Some("101"), None, Some(1.05), None)
+                },
+                "modify_fields_not_allowed",
+            ),
+        ] {
+            let error = state
+                .enqueue_trade_command(account, session_id, request)
+                .await
+                .expect_err("invalid modify command should be rejected");
+            assert_eq!(error.code(), code);
+        }
 
-    let mut inner = state.inner.lock().await;
+        let queued = state
+            .enqueue_trade_command(
+                account,
+                session_id,
+                modify(None, Some("202"), Some(1.05), Some(1.1)),
+            )
+            .await
+            .expect("modify command should be accepted");
+        assert_eq!(queued.command_type, TradeCommandType::Modify);
+        assert_eq!(queued.order_id.as_deref(), Some("202"));
+        assert!(queued.expires_at.is_some());
 
-    let response = {
-        let Some(account_sessions) = inner.sessions.get_mut(&account) else {
-            return Err(ApiError::unauthorized(
-                "session_missing",
-                "no active session for the supplied account",
-            ));
-        };
+        let events = state.outbox_events_for_test(account, session_id).await;
+        let event = events.last().expect("missing order event");
+        assert_eq!(event.id, queued.event_id);
+        assert_eq!(event.payload["commandType"], json!("modify"));
+        assert_eq!(event.payload["orderId"], json!("202"));
+        assert_eq!(event.payload["stopLoss"], json!(1.05));
+
+        // `null` and `0` both clear a level.
+        let clear: TradeOrderRequest = serde_json::from_value(json!({
+            "commandType": "modify",
+            "instrument": "EURUSD",
+            "positionId": "101",
+            "stopLoss": null,
+            "takeProfit": 0,
+        }))
+        .expect("valid modify request");
+        let cleared = state
+            .enqueue_trade_command(account, session_id, clear)
+            .await
+            .expect("clearing both levels should be accepted");
+        let events = state.outbox_events_for_test(account, session_id).await;
+        let event = events.last().expect("missing order event");
+        assert_eq!(event.id, cleared.event_id);
+        assert_eq!(event.payload["stopLoss"], json!(0.0));
+        assert_eq!(event.payload["takeProfit"], json!(0.0));
+    }
 
-        let Some(session) = account_sessions.get_mut_by_token(&token) else {
-            return Err(ApiError::unauthorized(
-                "invalid_session_token",
-                "the provided session token is not valid for this account",
-            ));
-        };
+    #[tokio::test]
+    async fn cancel_commands_resolve_the_original_pending_order() {
+        let state = AppState::default();
+        let account = "acct-cancel";
+        let PromotedSession {
+            session_id, token, ..
+        } = promoted_session(&state, account).await;
 
-        let cursor = query.cursor.unwrap_or_default();
-        let events = session.events_after(cursor, query.limit, false);
+        let placed = state
+            .enqueue_trade_command(
+                account,
+                session_id,
+                TradeOrderRequest {
+                    order_type: Some(TradeOrderType::Limit),
+                    side: Some(TradeSide::Buy),
+                    volume: Some(0.2),
+                    price: Some(1.05),
+                    client_order_id: Some("limit-1".to_string()),
+                    ..TradeOrderRequest::new(TradeCommandType::Open, "EURUSD")
+                },
+            )
+            .await
+            .expect("limit order should be accepted");
+        state
+            .capture_inbox(
+                account,
+                token,
+                vec![InboxEvent {
+                    event_id: None,
+                    event_type: "ExecutionReport".to_string(),
+                    payload: json!({"commandId": placed.command_id, "order": 5151, "retcode": 10008}),
+                    occurred_at: None,
+                }],
+            )
+            .await
+            .expect("inbox capture should succeed");
+
+        let cancel = |body: Value| {
+            let mut message = json!({
+                "type": "tradeOrder",
+                "accountId": account,
+                "sessionId": session_id,
+                "commandType": "cancel",
+                "instrument": "EURUSD",
+            });
+            message
+                .as_object_mut()
+                .expect("message is an object")
+                .extend(body.as_object().cloned().unwrap_or_default());
+            serde_json::from_value::<admin::AdminEnqueueRequest>(message)
+                .expect("trade order message should parse")
+        };
 
-        OutboxResponse {
-            session_id: session.session_id,
-            pending: session.status.is_pending(),
-            events,
-            retry_after_ms: 1_000,
+        for body in [
+            json!({}),
+            json!({"orderId": "5151", "originalClientOrderId": "limit-1"}),
+            json!({"originalClientOrderId": "unknown"}),
+            json!({"originalClientOrderId": "limit-1", "volume": 0.1}),
+            json!({"originalClientOrderId": "limit-1", "side": "sell"}),
+            json!({"originalClientOrderId": "limit-1", "orderType": "limit"}),
+        ] {
+            admin::apply_envelope(&state, cancel(body))
+                .await
+                .expect_err("invalid cancel command should be rejected");
         }
-    };
 
-    Ok((StatusCode::OK, Json(response)).into_response())
-}
+        state
+            .enqueue_trade_command(
+                account,
+                session_id,
+                TradeOrderRequest {
+                    order_type: Some(TradeOrderType::Market),
+                    side: Some(TradeSide::Buy),
+                    volume: Some(0.1),
+                    client_order_id: Some("market-1".to_string()),
+                    ..TradeOrderRequest::new(TradeCommandType::Open, "EURUSD")
+                },
+            )
+            .await
+            .expect("market order should be accepted");
+        let market_cancel = state
+            .enqueue_trade_command(
+                account,
+                session_id,
+                TradeOrderRequest {
+                    original_client_order_id: Some("market-1".to_string()),
+                    ..TradeOrderRequest::new(TradeCommandType::Cancel, "EURUSD")
+                },
+            )
+            .await
+            .expect_err("a market order cannot be cancelled");
+        assert_eq!(market_cancel.code(), "cancel_target_invalid");
 
-async fn acknowledge_outbox_event(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Path(event_id): Path<Uuid>,
-) -> Result<Response, ApiError> {
-    let account = account_from_headers(&headers)?;
-    let idempotency = idempotency_key(&headers)?;
-    let token = bearer_token(&headers)?;
-    let storage_key = idempotency_storage_key(
-        "POST",
-        &format!("/trade-agent/v1/sessions/current/outbox/{event_id}/ack"),
-        &account,
-        &idempotency,
-    );
+        admin::apply_envelope(&state, cancel(json!({"originalClientOrderId": "limit-1"})))
+            .await
+            .expect("cancel command should be accepted");
 
-    if let Some(stored) = state
-        .inner
-        .lock()
-        .await
-        .idempotency
-        .get(&storage_key)
-        .cloned()
-    {
-        return Ok(stored.into_response());
-    }
+        let events = state.outbox_events_for_test(account, session_id).await;
+        let event = events.last().expect("missing cancel event");
+        assert_eq!(event.event_type, "OrderCancel");
+        assert!(event.requires_ack);
+        assert_eq!(event.payload["orderId"], json!("5151"));
+        assert_eq!(event.payload["originalCommandId"], json!(placed.command_id));
 
-    let mut inner = state.inner.lock().await;
+        let cancel_id: Uuid = serde_json::from_value(event.payload["commandId"].clone())
+            .expect("cancel carries a command id");
+        state
+            .acknowledge_outbox(account, token, event.id)
+            .await
+            .expect("ack should succeed");
+        let tracked = state
+            .command(account, cancel_id)
+            .await
+            .expect("cancel should be tracked");
+        assert_eq!(tracked.status(), CommandStatus::Acknowledged);
+    }
 
-    let remaining = {
-        let Some(account_sessions) = inner.sessions.get_mut(&account) else {
-            return Err(ApiError::unauthorized(
-                "session_missing",
-                "no active session for the supplied account",
-            ));
+    #[tokio::test]
+    async fn partial_close_is_checked_against_the_tracked_position() {
+        let state = AppState::default();
+        let account = "acct-partial-close";
+        let PromotedSession {
+            session_id, token, ..
+        } = promoted_session(&state, account).await;
+
+        let inbox_event = |event_type: &str, payload: Value| InboxEvent {
+            event_id: None,
+            event_type: event_type.to_string(),
+            payload,
+            occurred_at: None,
         };
+        state
+            .capture_inbox(
+                account,
+                token,
+                vec![inbox_event(
+                    "SyncSnapshot",
+                    json!({
+                        "balance": 1000.0,
+                        "equity": 1000.0,
+                        "openTrades": [
+                            {"ticket": 11, "symbol": "EURUSD", "volume": 0.5, "volumeStep": 0.1},
+                        ],
+                    }),
+                )],
+            )
+            .await
+            .expect("inbox capture should succeed");
 
-        let Some(session) = account_sessions.get_mut_by_token(&token) else {
-            return Err(ApiError::unauthorized(
-                "invalid_session_token",
-                "the provided session token is not valid for this account",
-            ));
+        let close = |volume: f64| TradeOrderRequest {
+            volume: Some(volume),
+            position_id: Some("11".to_string()),
+            ..TradeOrderRequest::new(TradeCommandType::Close, "EURUSD")
         };
 
-        if !session.acknowledge_outbox(event_id) {
-            return Err(ApiError::not_found(
-                "event_not_found",
-                "no outbox event with the supplied identifier",
-            ));
-        }
-
-        session.outbox.len()
-    };
+        let untracked = state
+            .enqueue_trade_command(
+                account,
+                session_id,
+                TradeOrderRequest {
+                    position_id: Some("12".to_string()),
+                    ..close(0.1)
+                },
+            )
+            .await
+            .expect_err("closing an untracked position should be rejected");
+        assert_eq!(untracked.code(), "close_position_missing");
+        let not_a_ticket = state
+            .enqueue_trade_command(
+                account,
+                session_id,
+                TradeOrderRequest {
+                    position_id: Some("EURUSD-11".to_string()),
+                    ..close(0.1)
+                },
+            )
+            .await
+            .expect_err("position ids must be tickets");
+        assert_eq!(not_a_ticket.code(), "position_id_invalid");
 
-    let response_body = AckResponse {
-        acknowledged_event_id: event_id,
-        remaining_outbox_depth: remaining,
-    };
+        let over_close = state
+            .enqueue_trade_command(account, session_id, close(0.6))
+            .await
+            .expect_err("closing more than the position should be rejected");
+        assert_eq!(over_close.code(), "close_volume_exceeds_position");
+        let off_step = state
+            .enqueue_trade_command(account, session_id, close(0.15))
+            .await
+            .expect_err("volumes off the volume step should be rejected");
+        assert_eq!(off_step.code(), "volume_step_mismatch");
 
-    let stored = StoredResponse::from_json(StatusCode::OK, &response_body)
-        .map_err(|error| ApiError::internal(error.to_string()))?;
+        let queued = state
+            .enqueue_trade_command(account, session_id, close(0.2))
+            .await
+            .expect("partial close should be accepted");
+        let beyond_unsettled = state
+            .enqueue_trade_command(account, session_id, close(0.4))
+            .await
+            .expect_err("unsettled closes should count against the position");
+        assert_eq!(beyond_unsettled.code(), "close_volume_exceeds_position");
 
-    inner.idempotency.insert(storage_key, stored.clone());
+        // A repeated report must not take the volume off a second time.
+        let report = json!({"commandId": queued.command_id, "volume": 0.2, "retcode": 10009});
+        state
+            .capture_inbox(
+                account,
+                token,
+                vec![
+                    inbox_event("ExecutionReport", report.clone()),
+                    inbox_event("ExecutionReport", report),
+                ],
+            )
+            .await
+            .expect("inbox capture should succeed");
 
-    Ok(stored.into_response())
-}
+        let handle = state.account(account).await.expect("account exists");
+        let remaining = handle
+            .lock()
+            .await
+            .portfolio
+            .position(11)
+            .map(|position| position.volume)
+            .expect("position should remain open");
+        assert!((remaining - 0.3).abs() < VOLUME_EPSILON);
+    }
 
-fn account_from_headers(headers: &HeaderMap) -> Result<String, ApiError> {
-    header_value(headers, "X-TradeAgent-Account", "missing_account_header")
-}
+    #[tokio::test]
+    async fn aborted_idempotent_requests_still_record_their_outcome() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
 
-fn idempotency_key(headers: &HeaderMap) -> Result<String, ApiError> {
-    header_value(headers, "Idempotency-Key", "missing_idempotency_key")
-}
+        let state = AppState::default();
+        let fingerprint = body_fingerprint(&Value::Null);
+        let runs = Arc::new(AtomicUsize::new(0));
+        let proceed = Arc::new(Notify::new());
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+
+        let operation = {
+            let (runs, proceed) = (runs.clone(), proceed.clone());
+            async move {
+                let _ = started_tx.send(());
+                proceed.notified().await;
+                runs.fetch_add(1, Ordering::SeqCst);
+                Ok(StoredResponse::empty(StatusCode::CREATED))
+            }
+        };
+        let handler = tokio::spawn({
+            let (state, fingerprint) = (state.clone(), fingerprint.clone());
+            async move {
+                state
+                    .run_idempotent("key".to_string(), fingerprint, operation)
+                    .await
+            }
+        });
 
-fn header_value(
-    headers: &HeaderMap,
-    name: &str,
-    error_code: &'static str,
-) -> Result<String, ApiError> {
-    headers
-        .get(name)
-        .ok_or_else(|| ApiError::bad_request(error_code, format!("{name} header is required")))
-        .and_then(|value| {
-            value.to_str().map_err(|_| {
-                ApiError::bad_request(
-                    "invalid_header_encoding",
-                    format!("{name} header must contain valid UTF-8"),
-                )
-            })
-        })
-        .and_then(|value| {
-            let trimmed = value.trim();
-            if trimmed.is_empty() {
-                Err(ApiError::bad_request(
-                    "empty_header_value",
-                    format!("{name} header must not be empty"),
-                ))
-            } else {
-                Ok(trimmed.to_string())
+        started_rx.await.expect("operation should start");
+        handler.abort();
+        assert!(handler
+            .await
+            .expect_err("handler should be aborted")
+            .is_cancelled());
+        proceed.notify_one();
+
+        let retry = {
+            let runs = runs.clone();
+            async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                Ok(StoredResponse::empty(StatusCode::CREATED))
             }
-        })
-}
+        };
+        let replayed = state
+            .run_idempotent("key".to_string(), fingerprint, retry)
+            .await
+            .expect("retry should replay the original outcome");
+        assert_eq!(replayed.status(), StatusCode::CREATED);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
 
-fn bearer_token(headers: &HeaderMap) -> Result<Uuid, ApiError> {
-    let value = headers.get(header::AUTHORIZATION).ok_or_else(|| {
-        ApiError::bad_request(
-            "missing_authorization_header",
-            "Authorization header with Bearer token is required",
-        )
-    })?;
+    #[tokio::test]
+    async fn enqueue_outbox_event_rejects_empty_type() {
+        let state = AppState::default();
+        let account = "acct-events";
+        let session_id = promoted_session(&state, account).await.session_id;
 
-    let value = value.to_str().map_err(|_| {
-        ApiError::bad_request(
-            "invalid_header_encoding",
-            "Authorization header must be valid UTF-8",
-        )
-    })?;
+        let error = state
+            .enqueue_outbox_event(
+                account,
+                session_id,
+                OutboxEventRequest {
+                    event_type: "   ".to_string(),
+                    payload: Value::Null,
+                    requires_ack: true,
+                    expires_at: None,
+                },
+            )
+            .await
+            .expect_err("blank event type should be rejected");
 
-    let Some(token) = value.strip_prefix("Bearer ") else {
-        return Err(ApiError::bad_request(
-            "invalid_authorization_scheme",
-            "Authorization header must use the Bearer scheme",
-        ));
-    };
+        assert_eq!(error.code(), "event_type_empty");
+    }
 
-    Uuid::parse_str(token.trim()).map_err(|_| {
-        ApiError::bad_request(
-            "invalid_session_token",
-            "Authorization token must be a valid UUID",
-        )
-    })
-}
+    #[tokio::test]
+    async fn expired_trade_commands_are_purged_and_reported() {
+        assert_eq!(
+            OutboxDeliveryConfig::default().open_command_ttl,
+            Some(Duration::from_secs(60))
+        );
+        let state = AppState::default();
+        let account = "acct-expiry";
+        let session_id = promoted_session(&state, account).await.session_id;
 
-fn idempotency_storage_key(method: &str, path: &str, account: &str, key: &str) -> String {
-    format!("{method}:{path}:{account}:{key}")
-}
+        let queued = state
+            .enqueue_trade_command(
+                account,
+                session_id,
+                TradeOrderRequest {
+                    order_type: Some(TradeOrderType::Market),
+                    side: Some(TradeSide::Buy),
+                    volume: Some(1.0),
+                    ..TradeOrderRequest::new(TradeCommandType::Open, "EURUSD")
+                },
+            )
+            .await
+            .expect("trade command should be accepted");
+        let expires_at = queued
+            .expires_at
+            .expect("open commands should get the default expiry");
+
+        let before_expiry = state
+            .reap_sessions(
+                &SessionReaperConfig::default(),
+                expires_at - time::Duration::SECOND,
+            )
+            .await;
+        assert_eq!(before_expiry.commands_expired, 0);
 
-fn hash_secret(method: AuthMethod, secret: &str, account: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(method.storage_key().as_bytes());
-    hasher.update(b":");
-    hasher.update(account.as_bytes());
-    hasher.update(b":");
-    hasher.update(secret.as_bytes());
-    format!("{:x}", hasher.finalize())
-}
+        let after_expiry = state
+            .reap_sessions(&SessionReaperConfig::default(), expires_at)
+            .await;
+        assert_eq!(after_expiry.commands_expired, 1);
 
-fn current_time() -> OffsetDateTime {
-    OffsetDateTime::now_utc()
-}
+        let events = state.outbox_events_for_test(account, session_id).await;
+        assert!(events.iter().all(|event| event.id != queued.event_id));
+        let notice = events.last().expect("missing expiry notification");
+        assert_eq!(notice.event_type, "CommandExpired");
+        assert_eq!(notice.payload["eventId"], json!(queued.event_id));
+        assert_eq!(notice.payload["commandId"], json!(queued.command_id));
+
+        // A command the EA received but never acknowledged is not handed out
+        // again once it has expired, and is purged like an undelivered one.
+        let delivered = state
+            .enqueue_trade_command(
+                account,
+                session_id,
+                TradeOrderRequest {
+                    order_type: Some(TradeOrderType::Market),
+                    side: Some(TradeSide::Buy),
+                    volume: Some(1.0),
+                    ..TradeOrderRequest::new(TradeCommandType::Open, "EURUSD")
+                },
+            )
+            .await
+            .expect("trade command should be accepted");
+        {
+            let handle = state.account(account).await.expect("account state");
+            let mut account_sessions = handle.lock().await;
+            let (session, sequences) = account_sessions
+                .session_with_sequences(&session_id)
+                .expect("session");
+            let first = session.deliver_outbox(sequences, 0, None, &state.delivery, current_time());
+            assert!(first
+                .events
+                .iter()
+                .any(|event| event.id == delivered.event_id));
+
+            let redelivery_at =
+                delivered.expires_at.expect("default expiry") + state.delivery.visibility_timeout;
+            let redelivery =
+                session.deliver_outbox(sequences, u64::MAX, None, &state.delivery, redelivery_at);
+            assert!(redelivery
+                .events
+                .iter()
+                .all(|event| event.id != delivered.event_id));
+        }
+        let after_delivery = state
+            .reap_sessions(
+                &SessionReaperConfig::default(),
+                delivered.expires_at.expect("default expiry"),
+            )
+            .await;
+        assert_eq!(after_delivery.commands_expired, 1);
+        let events = state.outbox_events_for_test(account, session_id).await;
+        assert!(events.iter().all(|event| event.id != delivered.event_id));
+        let notice = events.last().expect("missing expiry notification");
+        assert_eq!(notice.event_type, "CommandExpired");
+        assert_eq!(notice.payload["eventId"], json!(delivered.event_id));
+        let tracked = state
+            .command(account, delivered.command_id)
+            .await
+            .expect("command should be tracked");
+        assert_eq!(tracked.status(), CommandStatus::Expired);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let error = state
+            .enqueue_outbox_event(
+                account,
+                session_id,
+                OutboxEventRequest {
+                    event_type: "OrderCommand".to_string(),
+                    payload: Value::Null,
+                    requires_ack: true,
+                    expires_at: Some(current_time() - time::Duration::SECOND),
+                },
+            )
+            .await
+            .expect_err("an already elapsed expiry should be rejected");
+        assert_eq!(error.code(), "expires_at_elapsed");
+    }
 
     #[tokio::test]
-    async fn session_enqueue_and_ack_flow() {
-        let account = "12345".to_string();
-        let hash = hash_secret(AuthMethod::AccountSessionKey, "secret", &account);
-        let mut session = SessionRecord::new(AuthMethod::AccountSessionKey, hash);
-
-        assert_eq!(session.status, SessionStatus::Pending);
-        assert!(session.outbox.is_empty());
+    async fn full_outbox_rejects_new_events_until_drained() {
+        let state = AppState::default().with_outbox_delivery(OutboxDeliveryConfig {
+            max_depth: 2,
+            max_bytes: 64,
+            ..OutboxDeliveryConfig::default()
+        });
+        let account = "acct-backpressure";
+        let PromotedSession {
+            session_id,
+            token,
+            init_ack,
+        } = promoted_session(&state, account).await;
 
-        let event = session.enqueue_outbox(OutboxEventRequest {
+        let event = |payload: Value| OutboxEventRequest {
             event_type: "OrderCommand".to_string(),
-            payload: json!({"order_id": "abc"}),
+            payload,
             requires_ack: true,
-        });
+            expires_at: None,
+        };
 
-        assert_eq!(event.sequence, 1);
-        assert_eq!(session.outbox.len(), 1);
+        let oversized = state
+            .enqueue_outbox_event(account, session_id, event(json!({"note": "x".repeat(64)})))
+            .await
+            .expect_err("payload above the byte limit should be rejected");
+        assert_eq!(oversized.code(), "payload_too_large");
+        assert_eq!(oversized.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let crowded = state
+            .enqueue_outbox_event(account, session_id, event(json!({"note": "x".repeat(24)})))
+            .await
+            .expect_err("payload fits alone but not next to the queued InitAck");
+        assert_eq!(crowded.code(), "outbox_full");
+        assert_eq!(crowded.status(), StatusCode::TOO_MANY_REQUESTS);
 
-        let pending_events = session.events_after(0, None, true);
-        assert_eq!(pending_events.len(), 1);
+        state
+            .enqueue_outbox_event(account, session_id, event(json!({"order": 1})))
+            .await
+            .expect("second event fits");
+        let full = state
+            .enqueue_outbox_event(account, session_id, event(json!({"order": 2})))
+            .await
+            .expect_err("third event exceeds the depth limit");
+        assert_eq!(full.code(), "outbox_full");
 
-        assert!(session.acknowledge_outbox(event.id));
-        assert!(session.outbox.is_empty());
+        let depth = state
+            .outbox_depth(account, session_id)
+            .await
+            .expect("depth should be reported");
+        assert_eq!(depth.depth, 2);
+        assert_eq!(depth.max_depth, 2);
+
+        state
+            .acknowledge_outbox(account, token, init_ack)
+            .await
+            .expect("ack should drain the outbox");
+        state
+            .enqueue_outbox_event(account, session_id, event(json!({"order": 2})))
+            .await
+            .expect("event fits once the outbox drained");
+    }
+
+    #[tokio::test]
+    async fn resync_replays_acknowledged_events_within_retention() {
+        let state = AppState::default();
+        let account = "acct-resync";
+        let PromotedSession {
+            session_id,
+            token,
+            init_ack,
+        } = promoted_session(&state, account).await;
+        state
+            .acknowledge_outbox(account, token, init_ack)
+            .await
+            .expect("init ack should be acknowledged");
+
+        let mut queued = Vec::new();
+        for order in 1..=3 {
+            let request = OutboxEventRequest {
+                event_type: "OrderCommand".to_string(),
+                payload: json!({"order": order}),
+                requires_ack: true,
+                expires_at: None,
+            };
+            queued.push(
+                state
+                    .enqueue_outbox_event(account, session_id, request)
+                    .await
+                    .expect("event should be queued"),
+            );
+        }
+
+        state
+            .acknowledge_outbox(account, token, queued[1].event_id)
+            .await
+            .expect("ack via the REST endpoint");
+        state
+            .capture_inbox(
+                account,
+                token,
+                vec![InboxEvent {
+                    event_id: None,
+                    event_type: "OutboxAck".to_string(),
+                    payload: json!({"eventId": queued[2].event_id}),
+                    occurred_at: None,
+                }],
+            )
+            .await
+            .expect("ack via the inbox");
+
+        let resync = state
+            .resync_outbox(
+                account,
+                session_id,
+                ResyncRequest {
+                    from_sequence: queued[1].sequence,
+                    reason: Some("ea state lost".to_string()),
+                },
+            )
+            .await
+            .expect("resync should succeed");
+        let originals: Vec<Uuid> = resync
+            .replayed
+            .iter()
+            .map(|replayed| replayed.original_event_id)
+            .collect();
+        assert_eq!(originals, vec![queued[1].event_id, queued[2].event_id]);
+        assert_eq!(resync.skipped_expired, 0);
+
+        let handle = state.account(account).await.expect("account should exist");
+        let mut account_sessions = handle.lock().await;
+        let session = account_sessions
+            .get_mut_by_session_id(&session_id)
+            .expect("session should exist");
+        let replays: Vec<&OutboundEvent> =
+            session.outbox.iter().filter(|event| event.replay).collect();
+        assert_eq!(replays.len(), 2);
+        assert!(replays
+            .iter()
+            .all(|event| event.sequence > queued[2].sequence));
+
+        let retention = state.delivery.ack_history_retention;
+        let later = current_time() + retention;
+        // The acknowledged InitAck is not part of the history.
+        assert_eq!(account_sessions.acknowledged.prune(retention, later), 2);
+        assert!(account_sessions.acknowledged.since(0).is_empty());
     }
 
     #[test]
-    fn hashing_is_deterministic() {
-        let hash1 = hash_secret(AuthMethod::AccountSessionKey, "secret", "account");
-        let hash2 = hash_secret(AuthMethod::AccountSessionKey, "secret", "account");
-        let hash3 = hash_secret(AuthMethod::AccountSessionKey, "secret", "other");
+    fn ack_history_skips_session_notices_and_keeps_the_newest_events() {
+        let auth_hash = hash_secret(AuthMethod::AccountSessionKey, "secret", "acct-history");
+        let mut sequences = AccountSequences::default();
+        let mut session = SessionRecord::new(AuthMethod::AccountSessionKey, auth_hash.clone());
+        session
+            .promote(&mut sequences, &auth_hash, None)
+            .expect("promotion should succeed");
+        let commands: Vec<OutboundEvent> = (1..=3)
+            .map(|order| {
+                session.enqueue_outbox(
+                    &mut sequences,
+                    OutboxEventRequest {
+                        event_type: "OrderCommand".to_string(),
+                        payload: json!({"order": order}),
+                        requires_ack: true,
+                        expires_at: None,
+                    },
+                )
+            })
+            .collect();
+        let init_ack = session.outbox[0].clone();
+
+        let mut history = AckHistory::default();
+        history.record(
+            std::iter::once(init_ack.clone()).chain(commands.clone()),
+            2,
+            current_time(),
+        );
 
-        assert_eq!(hash1, hash2);
-        assert_ne!(hash1, hash3);
+        assert_eq!(history.sequence_of(init_ack.id), None);
+        assert_eq!(history.sequence_of(commands[0].id), None);
+        assert_eq!(
+            history.sequence_of(commands[1].id),
+            Some(commands[1].sequence)
+        );
+        let kept: Vec<Uuid> = history.since(0).iter().map(|event| event.id).collect();
+        assert_eq!(kept, vec![commands[1].id, commands[2].id]);
+
+        let restored: AckHistory =
+            serde_json::from_value(serde_json::to_value(&history).expect("serialize history"))
+                .expect("deserialize history");
+        assert_eq!(
+            restored.sequence_of(commands[2].id),
+            Some(commands[2].sequence)
+        );
     }
 
-    #[test]
-    fn health_check_returns_ok_status() {
-        let status = health_check();
+    #[tokio::test]
+    async fn batch_ack_reports_each_event_and_remaining_depth() {
+        let state = AppState::default();
+        let account = "acct-batch-ack";
+        let PromotedSession {
+            session_id,
+            token,
+            init_ack,
+        } = promoted_session(&state, account).await;
+
+        let mut queued = Vec::new();
+        for order in 1..=3 {
+            let request = OutboxEventRequest {
+                event_type: "OrderCommand".to_string(),
+                payload: json!({"order": order}),
+                requires_ack: true,
+                expires_at: None,
+            };
+            queued.push(
+                state
+                    .enqueue_outbox_event(account, session_id, request)
+                    .await
+                    .expect("event should be queued"),
+            );
+        }
 
-        assert_eq!(status, HealthStatus::ok("gateway"));
-        assert!(status.healthy);
-        assert_eq!(status.message, "ok");
+        let empty = state
+            .acknowledge_outbox_batch(
+                account,
+                token,
+                OutboxBatchAckRequest {
+                    event_ids: Vec::new(),
+                    ack_through: None,
+                },
+            )
+            .await
+            .expect_err("an empty batch should be rejected");
+        assert_eq!(empty.code(), "ack_batch_empty");
+
+        let unknown = Uuid::new_v4();
+        let batch = state
+            .acknowledge_outbox_batch(
+                account,
+                token,
+                OutboxBatchAckRequest {
+                    event_ids: vec![queued[1].event_id, unknown, queued[1].event_id],
+                    ack_through: Some(queued[0].sequence),
+                },
+            )
+            .await
+            .expect("batch ack should succeed");
+        let statuses: Vec<(Uuid, OutboxAckStatus)> = batch
+            .results
+            .iter()
+            .map(|result| (result.event_id, result.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (queued[1].event_id, OutboxAckStatus::Acknowledged),
+                (unknown, OutboxAckStatus::Unknown),
+                (queued[1].event_id, OutboxAckStatus::AlreadyAcknowledged),
+                (init_ack, OutboxAckStatus::Acknowledged),
+                (queued[0].event_id, OutboxAckStatus::Acknowledged),
+            ]
+        );
+        assert_eq!(batch.acknowledged, 3);
+        assert_eq!(batch.remaining_outbox_depth, 1);
     }
 
     #[tokio::test]
-    async fn service_bus_promotion_authenticates_session() {
+    async fn portfolio_follows_snapshots_and_execution_reports() {
         let state = AppState::default();
-        let account = "acct-service".to_string();
-        let fingerprint = hash_secret(AuthMethod::AccountSessionKey, "secret", &account);
-        let session_id;
+        let account = "acct-portfolio";
+        let auth_hash = hash_secret(AuthMethod::AccountSessionKey, "secret", account);
+        let session = SessionRecord::new(AuthMethod::AccountSessionKey, auth_hash);
+        let token = session.session_token;
+        state.insert_session_for_test(account, session).await;
 
-        {
-            let mut inner = state.inner.lock().await;
-            let account_sessions = inner.sessions.entry(account.clone()).or_default();
-            let session = SessionRecord::new(AuthMethod::AccountSessionKey, fingerprint.clone());
-            session_id = session.session_id;
-            account_sessions.insert(session);
-        }
+        let missing = state
+            .portfolio(account)
+            .await
+            .expect_err("no portfolio has been reported yet");
+        assert_eq!(missing.code(), "portfolio_missing");
 
-        let promotion = state
-            .promote_session_with_fingerprint(&account, session_id, &fingerprint, Some("ops"))
+        let inbox_event = |event_type: &str, payload: Value| InboxEvent {
+            event_id: None,
+            event_type: event_type.to_string(),
+            payload,
+            occurred_at: None,
+        };
+        state
+            .capture_inbox(
+                account,
+                token,
+                vec![
+                    inbox_event(
+                        "SyncSnapshot",
+                        json!({
+                            "balance": 1000.0,
+                            "equity": 1012.5,
+                            "openTrades": [
+                                {"ticket": 11, "symbol": "EURUSD", "volume": 0.3},
+                                {"ticket": 7, "symbol": "GBPUSD", "volume": 0.1},
+                            ],
+                        }),
+                    ),
+                    inbox_event(
+                        "ExecutionReport",
+                        json!({"position": 11, "remainingVolume": 0.1, "symbol": "EURUSD"}),
+                    ),
+                    inbox_event(
+                        "ExecutionReport",
+                        json!({"position": 7, "remainingVolume": 0.0}),
+                    ),
+                    inbox_event(
+                        "ExecutionReport",
+                        json!({"position": 15, "remainingVolume": 0.2, "symbol": "USDJPY"}),
+                    ),
+                ],
+            )
             .await
-            .expect("promotion should succeed");
+            .expect("inbox capture should succeed");
 
-        assert_eq!(promotion.session_id, session_id);
-        assert_eq!(promotion.status, SessionStatus::Authenticated);
-        assert!(!promotion.pending);
+        let portfolio = serde_json::to_value(
+            state
+                .portfolio(account)
+                .await
+                .expect("portfolio should be reported"),
+        )
+        .expect("portfolio should serialize");
+        assert_eq!(portfolio["equity"], json!(1012.5));
+        let positions: Vec<(u64, f64)> = portfolio["positions"]
+            .as_array()
+            .expect("positions should be listed")
+            .iter()
+            .map(|position| {
+                (
+                    position["ticket"].as_u64().unwrap_or_default(),
+                    position["volume"].as_f64().unwrap_or_default(),
+                )
+            })
+            .collect();
+        assert_eq!(positions, vec![(11, 0.1), (15, 0.2)]);
 
-        let mut inner = state.inner.lock().await;
-        let account_sessions = inner.sessions.get_mut(&account).expect("account missing");
-        let session = account_sessions
-            .get_mut_by_session_id(&session_id)
-            .expect("session missing");
-        assert_eq!(session.status, SessionStatus::Authenticated);
-        assert_eq!(session.outbox.len(), 1);
-        assert_eq!(session.outbox[0].event_type, "InitAck");
+        // The portfolio outlives the session that reported it.
+        close_session(state.clone(), account.to_string(), token)
+            .await
+            .expect("session should close");
+        state
+            .portfolio(account)
+            .await
+            .expect("portfolio should be kept after the session closed");
     }
 
     #[tokio::test]
-    async fn reject_session_marks_session_terminated() {
+    async fn command_ledger_follows_trade_commands_to_their_outcome() {
         let state = AppState::default();
-        let account = "acct-reject".to_string();
-        let fingerprint = hash_secret(AuthMethod::AccountSessionKey, "secret", &account);
-        let session_id;
+        let account = "acct-ledger";
+        let PromotedSession {
+            session_id, token, ..
+        } = promoted_session(&state, account).await;
+
+        let open = |client_order_id: &str| TradeOrderRequest {
+            order_type: Some(TradeOrderType::Market),
+            side: Some(TradeSide::Buy),
+            volume: Some(0.5),
+            client_order_id: Some(client_order_id.to_string()),
+            ..TradeOrderRequest::new(TradeCommandType::Open, "EURUSD")
+        };
+        let filled = state
+            .enqueue_trade_command(account, session_id, open("client-fill"))
+            .await
+            .expect("trade command should be accepted");
+        let refused = state
+            .enqueue_trade_command(account, session_id, open("client-refuse"))
+            .await
+            .expect("trade command should be accepted");
 
-        {
-            let mut inner = state.inner.lock().await;
-            let account_sessions = inner.sessions.entry(account.clone()).or_default();
-            let session = SessionRecord::new(AuthMethod::AccountSessionKey, fingerprint.clone());
-            session_id = session.session_id;
-            account_sessions.insert(session);
-        }
+        let status = |record: CommandRecord| {
+            serde_json::to_value(record).expect("serializable")["status"].clone()
+        };
+        let queued = state
+            .command(account, filled.command_id)
+            .await
+            .expect("command should be tracked");
+        assert_eq!(status(queued), json!("queued"));
 
-        let rejection = state
-            .reject_session(
-                &account,
-                session_id,
-                &fingerprint,
-                Some("not approved".to_string()),
-                Some("operator".to_string()),
+        state
+            .acknowledge_outbox(account, token, filled.event_id)
+            .await
+            .expect("ack should succeed");
+        let acknowledged = state
+            .command(account, filled.command_id)
+            .await
+            .expect("command should be tracked");
+        assert_eq!(status(acknowledged), json!("acknowledged"));
+
+        let report = |payload: Value| InboxEvent {
+            event_id: None,
+            event_type: "ExecutionReport".to_string(),
+            payload,
+            occurred_at: None,
+        };
+        state
+            .capture_inbox(
+                account,
+                token,
+                vec![
+                    report(json!({"commandId": filled.command_id, "order": 8001, "type": 0, "retcode": 0})),
+                    report(json!({"commandId": refused.command_id, "order": 8002, "type": 0})),
+                ],
             )
             .await
-            .expect("rejection should succeed");
+            .expect("inbox capture should succeed");
+        let placed = state
+            .command(account, filled.command_id)
+            .await
+            .expect("command should be tracked");
+        assert_eq!(placed.order(), Some(8001));
+        assert_eq!(
+            status(placed),
+            json!("acknowledged"),
+            "an order update is no verdict"
+        );
+        let pending = state
+            .command(account, refused.command_id)
+            .await
+            .expect("command should be tracked");
+        assert_eq!(status(pending), json!("queued"));
 
-        assert_eq!(rejection.session_id, session_id);
-        assert_eq!(rejection.status, SessionStatus::Terminated);
-        assert_eq!(rejection.reason.as_deref(), Some("not approved"));
+        state
+            .capture_inbox(
+                account,
+                token,
+                vec![
+                    report(json!({"clientOrderId": "client-fill", "deal": 9001, "type": 6, "retcode": 0})),
+                    report(json!({"commandId": refused.command_id, "type": 10, "retcode": 10019})),
+                ],
+            )
+            .await
+            .expect("inbox capture should succeed");
 
-        let mut inner = state.inner.lock().await;
-        let account_sessions = inner.sessions.get_mut(&account).expect("account missing");
-        let session = account_sessions
-            .get_mut_by_session_id(&session_id)
-            .expect("session missing");
-        assert_eq!(session.status, SessionStatus::Terminated);
-        let shutdown = session.outbox.last().expect("missing shutdown");
-        assert_eq!(shutdown.event_type, "ShutdownNotice");
+        let by_client_order = state
+            .commands(
+                account,
+                CommandQuery {
+                    client_order_id: Some("client-fill".to_string()),
+                },
+            )
+            .await
+            .expect("lookup should succeed");
+        assert_eq!(by_client_order.commands.len(), 1);
         assert_eq!(
-            shutdown
-                .payload
-                .get("reason")
-                .and_then(|value| value.as_str()),
-            Some("session_rejected")
+            status(by_client_order.commands[0].clone()),
+            json!("executed")
         );
-        assert_eq!(
-            shutdown
-                .payload
-                .get("rejectedBy")
-                .and_then(|value| value.as_str()),
-            Some("operator")
+        let rejected = state
+            .command(account, refused.command_id)
+            .await
+            .expect("command should be tracked");
+        assert_eq!(status(rejected), json!("rejected"));
+
+        let missing = state
+            .command(account, Uuid::new_v4())
+            .await
+            .expect_err("unknown commands should not be found");
+        assert_eq!(missing.code(), "command_missing");
+    }
+
+    #[test]
+    fn command_ledger_prunes_settled_and_stale_unsettled_commands() {
+        let auth_hash = hash_secret(AuthMethod::AccountSessionKey, "secret", "acct-ledger-prune");
+        let mut sequences = AccountSequences::default();
+        let mut session = SessionRecord::new(AuthMethod::AccountSessionKey, auth_hash);
+        let mut ledger = CommandLedger::default();
+        let command_ids = [Uuid::new_v4(), Uuid::new_v4()];
+        for command_id in command_ids {
+            let event = session.enqueue_outbox(
+                &mut sequences,
+                OutboxEventRequest {
+                    event_type: "OrderCommand".to_string(),
+                    payload: json!({"commandId": command_id}),
+                    requires_ack: true,
+                    expires_at: None,
+                },
+            );
+            ledger.record_queued(session.session_id, &event);
+        }
+        let report: ExecutionReport =
+            serde_json::from_value(json!({"commandId": command_ids[0], "retcode": 10009}))
+                .expect("valid execution report");
+        let settled_at = current_time();
+        ledger.record_execution(&report, settled_at);
+
+        let retention = Duration::from_secs(60);
+        let max_unsettled_age = Duration::from_secs(600);
+        assert_eq!(ledger.prune(retention, max_unsettled_age, settled_at), 0);
+        let later = settled_at + Duration::from_secs(61);
+        assert_eq!(ledger.prune(retention, max_unsettled_age, later), 1);
+        assert!(ledger.get(command_ids[1]).is_some());
+        let much_later = settled_at + Duration::from_secs(601);
+        assert_eq!(ledger.prune(retention, max_unsettled_age, much_later), 1);
+        assert!(ledger.is_empty());
+    }
+
+    #[test]
+    fn limited_delivery_reports_remaining_visible_events() {
+        let auth_hash = hash_secret(AuthMethod::AccountSessionKey, "secret", "acct-paging");
+        let mut sequences = AccountSequences::default();
+        let mut session = SessionRecord::new(AuthMethod::AccountSessionKey, auth_hash.clone());
+        session
+            .promote(&mut sequences, &auth_hash, None)
+            .expect("promotion should succeed");
+        session.enqueue_outbox(
+            &mut sequences,
+            OutboxEventRequest {
+                event_type: "OrderCommand".to_string(),
+                payload: json!({"order": 1}),
+                requires_ack: true,
+                expires_at: None,
+            },
+        );
+
+        let config = OutboxDeliveryConfig::default();
+        let first = session.deliver_outbox(&mut sequences, 0, Some(1), &config, current_time());
+        assert_eq!(first.events.len(), 1);
+        assert!(first.has_more);
+
+        let second = session.deliver_outbox(
+            &mut sequences,
+            first.events[0].sequence,
+            Some(1),
+            &config,
+            current_time(),
         );
+        assert_eq!(second.events.len(), 1);
+        assert!(!second.has_more);
     }
 
-    #[tokio::test]
-    async fn enqueue_trade_command_generates_outbox_event() {
-        let state = AppState::default();
-        let account = "acct-trade";
-        let auth_hash = hash_secret(AuthMethod::AccountSessionKey, "secret", account);
+    #[test]
+    fn retry_after_hint_tracks_session_activity() {
+        let config = OutboxDeliveryConfig {
+            min_retry_after: Duration::from_millis(500),
+            max_retry_after: Duration::from_secs(10),
+            ..OutboxDeliveryConfig::default()
+        };
+        let auth_hash = hash_secret(AuthMethod::AccountSessionKey, "secret", "acct-pacing");
+        let mut sequences = AccountSequences::default();
         let mut session = SessionRecord::new(AuthMethod::AccountSessionKey, auth_hash.clone());
+        let now = current_time();
+        assert_eq!(
+            session.retry_after(&config, false, now),
+            config.max_retry_after
+        );
+
         session
-            .promote(&auth_hash)
+            .promote(&mut sequences, &auth_hash, None)
             .expect("promotion should succeed");
-        let session_id = session.session_id;
+        assert_eq!(
+            session.retry_after(&config, false, now),
+            config.min_retry_after
+        );
 
-        state.insert_session_for_test(account, session).await;
+        let init_ack = session.outbox[0].id;
+        session.acknowledge_outbox(init_ack);
+        let notice = session.enqueue_outbox(
+            &mut sequences,
+            OutboxEventRequest {
+                event_type: "CommandExpired".to_string(),
+                payload: json!({}),
+                requires_ack: false,
+                expires_at: None,
+            },
+        );
+        session.outbox[0].last_delivered_at = Some(notice.enqueued_at);
+        let enqueued_at = session.last_enqueued_at.expect("notice was queued");
+        assert_eq!(
+            session.retry_after(&config, false, enqueued_at + Duration::from_secs(5)),
+            config.min_retry_after
+        );
+        assert_eq!(
+            session.retry_after(&config, false, enqueued_at + Duration::from_secs(65)),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            session.retry_after(&config, false, enqueued_at + Duration::from_secs(3_600)),
+            config.max_retry_after
+        );
+    }
 
-        let queued = state
-            .enqueue_trade_command(
-                account,
-                session_id,
-                TradeOrderRequest {
-                    command_type: TradeCommandType::Open,
-                    instrument: "EURUSD".to_string(),
-                    order_type: Some(TradeOrderType::Market),
-                    side: Some(TradeSide::Buy),
-                    volume: Some(1.25),
-                    price: None,
-                    stop_loss: Some(1.1),
-                    take_profit: None,
-                    time_in_force: Some(TradeTimeInForce::Gtc),
-                    position_id: None,
-                    client_order_id: Some("client-1".to_string()),
-                    metadata: None,
+    #[tokio::test]
+    async fn preemption_migrates_or_cancels_unacknowledged_events() {
+        for policy in [
+            OutboxPreemptionPolicy::Migrate,
+            OutboxPreemptionPolicy::Cancel,
+        ] {
+            let state = AppState::default().with_outbox_delivery(OutboxDeliveryConfig {
+                preemption_policy: policy,
+                ..OutboxDeliveryConfig::default()
+            });
+            let account = "acct-preempt";
+            let auth_hash = hash_secret(AuthMethod::AccountSessionKey, "secret", account);
+            let mut sequences = AccountSequences::default();
+            let mut session = SessionRecord::new(AuthMethod::AccountSessionKey, auth_hash.clone());
+            session
+                .promote(&mut sequences, &auth_hash, None)
+                .expect("promotion should succeed");
+            let previous_id = session.session_id;
+            let init_ack = session.outbox[0].id;
+            assert!(session.acknowledge_outbox(init_ack).is_some());
+
+            state.insert_session_for_test(account, session).await;
+
+            let command = state
+                .enqueue_outbox_event(
+                    account,
+                    previous_id,
+                    OutboxEventRequest {
+                        event_type: "OrderCommand".to_string(),
+                        payload: json!({"commandId": "cmd-1"}),
+                        requires_ack: true,
+                        expires_at: None,
+                    },
+                )
+                .await
+                .expect("command should be queued");
+
+            let created = open_session(
+                state.clone(),
+                account.to_string(),
+                SessionCreateRequest {
+                    auth_method: AuthMethod::AccountSessionKey,
+                    authentication_key: "secret".to_string(),
                 },
             )
             .await
-            .expect("trade command should be accepted");
-
-        assert_eq!(queued.session_id, session_id);
-        assert_eq!(queued.command_type, TradeCommandType::Open);
-        assert_eq!(queued.order_type, Some(TradeOrderType::Market));
-        assert_eq!(queued.side, Some(TradeSide::Buy));
-        assert_eq!(queued.volume, Some(1.25));
-        assert_eq!(queued.instrument, "EURUSD");
-
-        let events = state.outbox_events_for_test(account, session_id).await;
-        assert_eq!(events.len(), 2);
-        let event = events.last().expect("missing order event");
-        assert_eq!(event.id, queued.event_id);
-        assert_eq!(event.event_type, "OrderCommand");
-        assert!(event.requires_ack);
+            .expect("new session should preempt the previous one");
+            let body = created.body.expect("missing create response body");
+            let session_id: Uuid =
+                serde_json::from_value(body["sessionId"].clone()).expect("invalid session id");
+            assert_eq!(body["previousSessionTerminated"], json!(previous_id));
+
+            let previous = state.outbox_events_for_test(account, previous_id).await;
+            assert_eq!(previous.len(), 1);
+            assert_eq!(previous[0].event_type, "ShutdownNotice");
+
+            let current = state.outbox_events_for_test(account, session_id).await;
+            assert_eq!(current.len(), 1);
+            match policy {
+                OutboxPreemptionPolicy::Migrate => {
+                    assert_eq!(current[0].event_type, "OrderCommand");
+                    assert_eq!(current[0].original_event_id, Some(command.event_id));
+                    assert_ne!(current[0].id, command.event_id);
+
+                    state
+                        .promote_session_internal(
+                            account,
+                            session_id,
+                            auth_hash.clone(),
+                            Some("ops"),
+                            None,
+                        )
+                        .await
+                        .expect("promotion should succeed");
+                    let promoted = state.outbox_events_for_test(account, session_id).await;
+                    let types: Vec<_> = promoted.iter().map(|e| e.event_type.as_str()).collect();
+                    assert_eq!(types, ["InitAck", "OrderCommand"]);
+                    assert!(promoted[0].sequence < promoted[1].sequence);
+                }
+                OutboxPreemptionPolicy::Cancel => {
+                    assert_eq!(current[0].event_type, "CommandCancelled");
+                    assert_eq!(current[0].payload["eventId"], json!(command.event_id));
+                    assert_eq!(current[0].payload["commandId"], "cmd-1");
+                }
+            }
+        }
     }
 
     #[tokio::test]
-    async fn enqueue_trade_command_requires_side_for_open() {
-        let state = AppState::default();
-        let account = "acct-invalid";
+    async fn migrated_events_beyond_the_outbox_limits_are_cancelled() {
+        let state = AppState::default().with_outbox_delivery(OutboxDeliveryConfig {
+            max_depth: 2,
+            ..OutboxDeliveryConfig::default()
+        });
+        let account = "acct-preempt-full";
         let auth_hash = hash_secret(AuthMethod::AccountSessionKey, "secret", account);
+        let mut sequences = AccountSequences::default();
         let mut session = SessionRecord::new(AuthMethod::AccountSessionKey, auth_hash.clone());
         session
-            .promote(&auth_hash)
+            .promote(&mut sequences, &auth_hash, None)
             .expect("promotion should succeed");
-        let session_id = session.session_id;
-
+        let previous_id = session.session_id;
+        let init_ack = session.outbox[0].id;
+        assert!(session.acknowledge_outbox(init_ack).is_some());
         state.insert_session_for_test(account, session).await;
 
-        let error = state
-            .enqueue_trade_command(
+        for command_id in ["cmd-1", "cmd-2"] {
+            state
+                .enqueue_outbox_event(
+                    account,
+                    previous_id,
+                    OutboxEventRequest {
+                        event_type: "OrderCommand".to_string(),
+                        payload: json!({"commandId": command_id}),
+                        requires_ack: true,
+                        expires_at: None,
+                    },
+                )
+                .await
+                .expect("command should be queued");
+        }
+
+        state
+            .preapprove_session_key(
                 account,
-                session_id,
-                TradeOrderRequest {
-                    command_type: TradeCommandType::Open,
-                    instrument: "EURUSD".to_string(),
-                    order_type: Some(TradeOrderType::Market),
-                    side: None,
-                    volume: Some(1.0),
-                    price: None,
-                    stop_loss: None,
-                    take_profit: None,
-                    time_in_force: None,
-                    position_id: None,
-                    client_order_id: None,
-                    metadata: None,
-                },
+                AuthMethod::AccountSessionKey,
+                "secret",
+                Some("ops".to_string()),
+                None,
             )
             .await
-            .expect_err("missing side should be rejected");
-
-        assert_eq!(error.code(), "side_required");
+            .expect("preapproval should succeed");
+        let created = open_session(
+            state.clone(),
+            account.to_string(),
+            SessionCreateRequest {
+                auth_method: AuthMethod::AccountSessionKey,
+                authentication_key: "secret".to_string(),
+            },
+        )
+        .await
+        .expect("new session should preempt the previous one");
+        let body = created.body.expect("missing create response body");
+        let session_id: Uuid =
+            serde_json::from_value(body["sessionId"].clone()).expect("invalid session id");
+
+        let current = state.outbox_events_for_test(account, session_id).await;
+        let types: Vec<_> = current.iter().map(|e| e.event_type.as_str()).collect();
+        assert_eq!(types, ["InitAck", "OrderCommand", "CommandCancelled"]);
+        assert_eq!(current[1].payload["commandId"], "cmd-1");
+        assert_eq!(current[2].payload["commandId"], "cmd-2");
+        assert_eq!(current[2].payload["reason"], "outbox_full");
     }
 
     #[tokio::test]
-    async fn enqueue_outbox_event_rejects_empty_type() {
-        let state = AppState::default();
-        let account = "acct-events";
+    async fn unacked_events_are_redelivered_then_dead_lettered_and_requeued() {
+        let state = AppState::default().with_outbox_delivery(OutboxDeliveryConfig {
+            visibility_timeout: Duration::from_secs(30),
+            max_attempts: 2,
+            ..OutboxDeliveryConfig::default()
+        });
+        let account = "acct-dead-letter";
         let auth_hash = hash_secret(AuthMethod::AccountSessionKey, "secret", account);
+        let mut sequences = AccountSequences::default();
         let mut session = SessionRecord::new(AuthMethod::AccountSessionKey, auth_hash.clone());
         session
-            .promote(&auth_hash)
+            .promote(&mut sequences, &auth_hash, None)
             .expect("promotion should succeed");
         let session_id = session.session_id;
+        let init_ack = session.outbox[0].id;
+        assert!(session.acknowledge_outbox(init_ack).is_some());
+        let command = session.enqueue_outbox(
+            &mut sequences,
+            OutboxEventRequest {
+                event_type: "OrderCommand".to_string(),
+                payload: json!({"order_id": "abc"}),
+                requires_ack: true,
+                expires_at: None,
+            },
+        );
+
+        let config = state.delivery.clone();
+        let now = current_time();
+        let visible_again = time::Duration::seconds(30);
+
+        let first = session.deliver_outbox(&mut sequences, 0, None, &config, now);
+        assert_eq!(first.events.len(), 1);
+        assert_eq!(first.events[0].delivery_attempts, 1);
+
+        // Hidden while in flight, even when the cursor is rewound.
+        assert!(session
+            .deliver_outbox(
+                &mut sequences,
+                0,
+                None,
+                &config,
+                now + time::Duration::seconds(29),
+            )
+            .events
+            .is_empty());
+
+        let second = session.deliver_outbox(
+            &mut sequences,
+            command.sequence,
+            None,
+            &config,
+            now + visible_again,
+        );
+        assert_eq!(second.events.len(), 1);
+        assert_eq!(second.events[0].delivery_attempts, 2);
+
+        let exhausted = session.deliver_outbox(
+            &mut sequences,
+            command.sequence,
+            None,
+            &config,
+            now + visible_again + visible_again,
+        );
+        assert_eq!(exhausted.dead_lettered.len(), 1);
+        assert_eq!(exhausted.events.len(), 1);
+        let alert = &exhausted.events[0];
+        assert_eq!(alert.event_type, "ErrorAlert");
+        assert_eq!(alert.payload["code"], json!("outbox_event_dead_lettered"));
+        assert_eq!(alert.payload["eventId"], json!(command.id));
+        assert!(session.outbox.is_empty());
+
+        // Notices that need no acknowledgement leave the outbox once delivered.
+        let notice = session.enqueue_outbox(
+            &mut sequences,
+            OutboxEventRequest {
+                event_type: "CommandExpired".to_string(),
+                payload: json!({"eventId": command.id}),
+                requires_ack: false,
+                expires_at: None,
+            },
+        );
+        let delivered =
+            session.deliver_outbox(&mut sequences, command.sequence, None, &config, now);
+        assert_eq!(delivered.events[0].id, notice.id);
+        assert!(session.outbox.is_empty());
 
         state.insert_session_for_test(account, session).await;
 
-        let error = state
-            .enqueue_outbox_event(
-                account,
-                session_id,
-                OutboxEventRequest {
-                    event_type: "   ".to_string(),
-                    payload: Value::Null,
-                    requires_ack: true,
-                },
-            )
+        let listed = state
+            .dead_letters(account, session_id)
             .await
-            .expect_err("blank event type should be rejected");
+            .expect("dead letters should be listed");
+        assert_eq!(listed.dead_letters.len(), 1);
+        assert_eq!(listed.dead_letters[0].event.id, command.id);
 
-        assert_eq!(error.code(), "event_type_empty");
+        let requeued = state
+            .requeue_dead_letter(account, session_id, command.id)
+            .await
+            .expect("dead letter should be requeued");
+        assert_eq!(requeued.event_id, command.id);
+        assert!(requeued.sequence > command.sequence);
+
+        let error = state
+            .requeue_dead_letter(account, session_id, command.id)
+            .await
+            .expect_err("dead letter was already requeued");
+        assert_eq!(error.code(), "dead_letter_missing");
+
+        let outbox = state.outbox_events_for_test(account, session_id).await;
+        let event = outbox
+            .iter()
+            .find(|event| event.id == command.id)
+            .expect("requeued event should be back in the outbox");
+        assert_eq!(event.delivery_attempts, 0);
+        assert!(event.last_delivered_at.is_none());
     }
 }
@@ -1,6 +1,9 @@
 use std::{env, net::SocketAddr};
 
-use gateway::{router, AppState, ServiceBusConfig, ServiceBusWorker};
+use gateway::{
+    admin_router, router, AppState, IdempotencyEvictor, OutboxDeliveryConfig, ServiceBusConfig,
+    ServiceBusWorker, SessionReaper, SessionReaperConfig, SessionStoreConfig,
+};
 use tokio::net::TcpListener;
 use tracing::{info, warn};
 use tracing_subscriber::EnvFilter;
@@ -9,7 +12,20 @@ use tracing_subscriber::EnvFilter;
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     init_tracing();
 
-    let state = AppState::default();
+    let store_config = SessionStoreConfig::from_env()
+        .map_err(|error| -> Box<dyn std::error::Error> { Box::new(error) })?;
+    let delivery_config = OutboxDeliveryConfig::from_env()
+        .map_err(|error| -> Box<dyn std::error::Error> { Box::new(error) })?;
+    let state = AppState::with_store(store_config.open()?)?.with_outbox_delivery(delivery_config);
+    info!(store = ?store_config, "session store initialized");
+
+    let reaper_config = SessionReaperConfig::from_env()
+        .map_err(|error| -> Box<dyn std::error::Error> { Box::new(error) })?;
+    let heartbeat_timeout_secs = reaper_config.heartbeat_timeout.as_secs();
+    SessionReaper::new(reaper_config).spawn(state.clone());
+    info!(heartbeat_timeout_secs, "session reaper started");
+
+    IdempotencyEvictor::default().spawn(state.clone());
 
     let bus_config = ServiceBusConfig::from_env()
         .map_err(|error| -> Box<dyn std::error::Error> { Box::new(error) })?;
@@ -28,15 +44,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         warn!("Service Bus configuration not provided; admin command processing disabled");
     }
 
-    let app = router(state);
+    let admin_app = admin_router(state.clone());
+    let app = router(state.clone());
 
-    let host = env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
-    let port = env::var("PORT")
-        .ok()
-        .and_then(|value| value.parse::<u16>().ok())
-        .unwrap_or(8080);
-    let addr: SocketAddr = format!("{host}:{port}").parse()?;
+    let admin_addr = listen_addr("ADMIN_HOST", "127.0.0.1", "ADMIN_PORT", 8081)?;
+    let admin_listener = TcpListener::bind(admin_addr).await?;
+    info!(addr = %admin_addr, "management endpoints listening");
+    tokio::spawn(async move {
+        if let Err(error) = axum::serve(admin_listener, admin_app).await {
+            warn!(%error, "management listener stopped");
+        }
+    });
 
+    let addr = listen_addr("HOST", "0.0.0.0", "PORT", 8080)?;
     let listener = TcpListener::bind(addr).await?;
     info!(%addr, "EA counterparty service listening");
 
@@ -44,11 +64,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with_graceful_shutdown(shutdown_signal())
         .await?;
 
+    state.flush_sessions().await;
+
     info!("shutdown complete");
 
     Ok(())
 }
 
+fn listen_addr(
+    host_var: &str,
+    default_host: &str,
+    port_var: &str,
+    default_port: u16,
+) -> Result<SocketAddr, std::net::AddrParseError> {
+    let host = env::var(host_var).unwrap_or_else(|_| default_host.to_string());
+    let port = env::var(port_var)
+        .ok()
+        .and_then(|value| value.parse::<u16>().ok())
+        .unwrap_or(default_port);
+    format!("{host}:{port}").parse()
+}
+
 fn init_tracing() {
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
 
@@ -0,0 +1,57 @@
+use serde::Serialize;
+use serde_json::Value;
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+use crate::current_time;
+
+/// Tracing target that the operations forwarder ships to `tradeagent.operations`.
+pub(crate) const OPERATIONS_TARGET: &str = "tradeagent.operations";
+
+/// Envelope describing an operational event for management processing, using
+/// the `tradeagent.operations` message schema.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OperationalEvent<'a> {
+    message_type: &'static str,
+    account: &'a str,
+    sequence: u64,
+    #[serde(with = "time::serde::rfc3339")]
+    occurred_at: OffsetDateTime,
+    payload: Value,
+}
+
+impl<'a> OperationalEvent<'a> {
+    pub(crate) fn new(
+        message_type: &'static str,
+        account: &'a str,
+        sequence: u64,
+        payload: Value,
+    ) -> Self {
+        Self {
+            message_type,
+            account,
+            sequence,
+            occurred_at: current_time(),
+            payload,
+        }
+    }
+
+    pub(crate) fn emit(&self) {
+        match serde_json::to_string(self) {
+            Ok(envelope) => info!(
+                target: OPERATIONS_TARGET,
+                message_type = self.message_type,
+                account = %self.account,
+                %envelope,
+                "operational event",
+            ),
+            Err(error) => warn!(
+                message_type = self.message_type,
+                account = %self.account,
+                %error,
+                "failed to serialize operational event",
+            ),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{ExecutionReport, SnapshotTrade, SyncSnapshot};
+
+/// Tolerance for comparing lot sizes reported as floating point numbers.
+pub(crate) const VOLUME_EPSILON: f64 = 1e-8;
+
+/// What an EA last reported holding: balances and open trades from its most
+/// recent `SyncSnapshot`, with positions kept current by the `ExecutionReport`s
+/// received since.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Portfolio {
+    balance: Option<f64>,
+    equity: Option<f64>,
+    margin: Option<f64>,
+    margin_free: Option<f64>,
+    currency: Option<String>,
+    /// Open positions ordered by ticket.
+    positions: Vec<PortfolioTrade>,
+    pending_orders: Vec<PortfolioTrade>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    snapshot_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    updated_at: Option<OffsetDateTime>,
+}
+
+/// An open position or pending order held by the EA.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PortfolioTrade {
+    pub(crate) ticket: u64,
+    pub(crate) symbol: String,
+    pub(crate) volume: f64,
+    pub(crate) volume_step: Option<f64>,
+    /// MetaTrader position or order type code.
+    #[serde(rename = "type")]
+    trade_type: Option<i32>,
+    price: Option<f64>,
+    stop_loss: Option<f64>,
+    take_profit: Option<f64>,
+    profit: Option<f64>,
+}
+
+impl From<&SnapshotTrade> for PortfolioTrade {
+    fn from(trade: &SnapshotTrade) -> Self {
+        Self {
+            ticket: trade.ticket,
+            symbol: trade.symbol.clone(),
+            volume: trade.volume,
+            volume_step: trade.volume_step,
+            trade_type: trade.trade_type,
+            price: trade.price,
+            stop_loss: trade.stop_loss,
+            take_profit: trade.take_profit,
+            profit: trade.profit,
+        }
+    }
+}
+
+impl Portfolio {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.updated_at.is_none()
+    }
+
+    pub(crate) fn position(&self, ticket: u64) -> Option<&PortfolioTrade> {
+        self.positions
+            .binary_search_by_key(&ticket, |position| position.ticket)
+            .ok()
+            .map(|index| &self.positions[index])
+    }
+
+    /// Replaces the portfolio with the state reported in `snapshot`.
+    pub(crate) fn apply_snapshot(&mut self, snapshot: &SyncSnapshot, now: OffsetDateTime) {
+        let mut positions: Vec<PortfolioTrade> = snapshot
+            .open_trades
+            .iter()
+            .map(PortfolioTrade::from)
+            .collect();
+        positions.sort_by_key(|position| position.ticket);
+
+        *self = Self {
+            balance: Some(snapshot.balance),
+            equity: Some(snapshot.equity),
+            margin: snapshot.margin,
+            margin_free: snapshot.margin_free,
+            currency: snapshot.currency.clone(),
+            positions,
+            pending_orders: snapshot
+                .pending_orders
+                .iter()
+                .map(PortfolioTrade::from)
+                .collect(),
+            snapshot_at: Some(now),
+            updated_at: Some(now),
+        };
+    }
+
+    /// Applies the position size reported by an execution. Reports that do
+    /// not name a position and its remaining volume leave the portfolio as
+    /// is; a remaining volume of zero closes the position.
+    pub(crate) fn apply_execution(
+        &mut self,
+        report: &ExecutionReport,
+        now: OffsetDateTime,
+    ) -> bool {
+        let (Some(ticket), Some(remaining)) = (report.position, report.remaining_volume) else {
+            return false;
+        };
+
+        match self
+            .positions
+            .binary_search_by_key(&ticket, |position| position.ticket)
+        {
+            Ok(index) if remaining <= 0.0 => {
+                self.positions.remove(index);
+            }
+            Ok(index) => self.positions[index].volume = remaining,
+            Err(_) if remaining <= 0.0 => return false,
+            Err(index) => {
+                let Some(symbol) = report.symbol.clone() else {
+                    return false;
+                };
+                self.positions.insert(
+                    index,
+                    PortfolioTrade {
+                        ticket,
+                        symbol,
+                        volume: remaining,
+                        volume_step: None,
+                        trade_type: None,
+                        price: report.price,
+                        stop_loss: None,
+                        take_profit: None,
+                        profit: None,
+                    },
+                );
+            }
+        }
+
+        self.updated_at = Some(now);
+        true
+    }
+
+    /// Takes `volume` off a tracked position after a close executed without
+    /// reporting what remains, removing the position once nothing is left.
+    pub(crate) fn reduce_position(
+        &mut self,
+        ticket: u64,
+        volume: f64,
+        now: OffsetDateTime,
+    ) -> bool {
+        let Ok(index) = self
+            .positions
+            .binary_search_by_key(&ticket, |position| position.ticket)
+        else {
+            return false;
+        };
+
+        let remaining = self.positions[index].volume - volume;
+        if remaining <= VOLUME_EPSILON {
+            self.positions.remove(index);
+        } else {
+            self.positions[index].volume = remaining;
+        }
+        self.updated_at = Some(now);
+        true
+    }
+}
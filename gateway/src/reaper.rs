@@ -0,0 +1,124 @@
+use std::{env, time::Duration};
+
+use thiserror::Error;
+use tokio::{
+    task::JoinHandle,
+    time::{interval, MissedTickBehavior},
+};
+use tracing::info;
+
+use crate::{current_time, AppState};
+
+const INTERVAL_ENV: &str = "EA_SESSION_REAPER_INTERVAL_SECS";
+const HEARTBEAT_TIMEOUT_ENV: &str = "EA_HEARTBEAT_TIMEOUT_SECS";
+const RECONNECT_WINDOW_ENV: &str = "EA_RECONNECT_WINDOW_SECS";
+
+#[derive(Debug, Error)]
+pub enum SessionReaperConfigError {
+    #[error("environment variable {name} contains invalid UTF-8 characters")]
+    InvalidUnicode {
+        name: &'static str,
+        #[source]
+        source: env::VarError,
+    },
+    #[error("failed to parse {name}: {source}")]
+    InvalidDuration {
+        name: &'static str,
+        #[source]
+        source: std::num::ParseIntError,
+    },
+}
+
+/// Controls how often sessions are scanned and when a silent EA is considered gone.
+#[derive(Debug, Clone)]
+pub struct SessionReaperConfig {
+    /// Delay between two scans of the session table.
+    pub interval: Duration,
+    /// Maximum time since the last `StatusHeartbeat` before a session is terminated.
+    pub heartbeat_timeout: Duration,
+    /// Back-off suggested to the EA in the `ShutdownNotice` before it reconnects.
+    pub reconnect_window: Duration,
+}
+
+impl Default for SessionReaperConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(15),
+            heartbeat_timeout: Duration::from_secs(90),
+            reconnect_window: Duration::from_secs(5),
+        }
+    }
+}
+
+impl SessionReaperConfig {
+    pub fn from_env() -> Result<Self, SessionReaperConfigError> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            interval: read_secs(INTERVAL_ENV, defaults.interval)?,
+            heartbeat_timeout: read_secs(HEARTBEAT_TIMEOUT_ENV, defaults.heartbeat_timeout)?,
+            reconnect_window: read_secs(RECONNECT_WINDOW_ENV, defaults.reconnect_window)?,
+        })
+    }
+}
+
+fn read_secs(name: &'static str, default: Duration) -> Result<Duration, SessionReaperConfigError> {
+    match env::var(name) {
+        Ok(value) => {
+            let secs: u64 = value
+                .trim()
+                .parse()
+                .map_err(|source| SessionReaperConfigError::InvalidDuration { name, source })?;
+            Ok(Duration::from_secs(secs.max(1)))
+        }
+        Err(env::VarError::NotPresent) => Ok(default),
+        Err(error) => Err(SessionReaperConfigError::InvalidUnicode {
+            name,
+            source: error,
+        }),
+    }
+}
+
+/// Background task that terminates sessions whose EA stopped sending heartbeats
+/// or whose approval window lapsed, purges expired outbox events, and drops
+/// terminated sessions once drained.
+pub struct SessionReaper {
+    config: SessionReaperConfig,
+}
+
+impl SessionReaper {
+    pub fn new(config: SessionReaperConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn spawn(self, state: AppState) -> JoinHandle<()> {
+        let config = self.config;
+        tokio::spawn(async move {
+            let mut ticker = interval(config.interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+                let summary = state.reap_sessions(&config, current_time()).await;
+                if summary.timed_out > 0
+                    || summary.approvals_expired > 0
+                    || summary.commands_expired > 0
+                    || summary.acknowledged_pruned > 0
+                    || summary.commands_pruned > 0
+                    || summary.removed > 0
+                {
+                    info!(
+                        timed_out = summary.timed_out,
+                        approvals_expired = summary.approvals_expired,
+                        commands_expired = summary.commands_expired,
+                        acknowledged_pruned = summary.acknowledged_pruned,
+                        commands_pruned = summary.commands_pruned,
+                        removed = summary.removed,
+                        "session reaper pass completed",
+                    );
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,706 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, io,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+use crate::current_time;
+
+const STORE_KIND_ENV: &str = "EA_SESSION_STORE";
+const STORE_PATH_ENV: &str = "EA_SESSION_STORE_PATH";
+const DEFAULT_STORE_PATH: &str = "gateway-sessions.db";
+
+/// Saves the writer thread may have queued before senders have to wait.
+const WRITE_QUEUE_CAPACITY: usize = 256;
+
+/// Persisted sections of one account, keyed by section name.
+///
+/// An account is stored as several independent JSON documents (its session
+/// index, each session, its portfolio, its command ledger and so on) so that
+/// a change to one of them does not rewrite the others.
+pub type StoredSections = BTreeMap<String, String>;
+
+/// Sections of an account to write and to delete in a single save.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SectionChanges {
+    pub written: StoredSections,
+    pub removed: Vec<String>,
+}
+
+impl SectionChanges {
+    pub fn is_empty(&self) -> bool {
+        self.written.is_empty() && self.removed.is_empty()
+    }
+
+    /// Folds `later` into these changes, as if both were saved in turn.
+    fn merge(&mut self, later: SectionChanges) {
+        for name in &later.removed {
+            self.written.remove(name);
+        }
+        self.removed
+            .retain(|name| !later.written.contains_key(name) && !later.removed.contains(name));
+        self.removed.extend(later.removed);
+        self.written.extend(later.written);
+    }
+}
+
+/// The sections of an account handed to the store writer: those serialized
+/// for this save, and the names of those left out because they have not
+/// changed since they were last serialized. Any other section is deleted.
+#[derive(Debug, Default)]
+pub(crate) struct SectionUpdate {
+    pub(crate) sections: StoredSections,
+    pub(crate) unchanged: Vec<String>,
+}
+
+/// A value persisted as a section of its own that remembers whether it was
+/// changed since it was last serialized, so that saves can leave it out.
+///
+/// Every mutable access counts as a change; [`Tracked::update`] is for
+/// mutations that frequently turn out to change nothing.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Tracked<T> {
+    value: T,
+    #[serde(skip)]
+    changed: AtomicBool,
+}
+
+impl<T> Tracked<T> {
+    /// Applies `update`, counting it as a change only if `changed` says so.
+    pub(crate) fn update<R>(
+        &mut self,
+        update: impl FnOnce(&mut T) -> R,
+        changed: impl FnOnce(&R) -> bool,
+    ) -> R {
+        let result = update(&mut self.value);
+        if changed(&result) {
+            *self.changed.get_mut() = true;
+        }
+        result
+    }
+
+    /// Reports whether the value changed since the last call.
+    pub(crate) fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl<T> Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Tracked<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        *self.changed.get_mut() = true;
+        &mut self.value
+    }
+}
+
+/// Persistence backend for per-account session state.
+///
+/// Implementations receive the sections of an account that changed and hand
+/// every section back when the gateway starts. Stores whose writes may block
+/// are only ever called from the store writer thread or during startup.
+pub trait SessionStore: Send + Sync {
+    /// Loads every persisted section, grouped by account.
+    fn load(&self) -> Result<Vec<(String, StoredSections)>, SessionStoreError>;
+
+    /// Writes and deletes the supplied sections of an account atomically.
+    fn save(&self, account: &str, changes: &SectionChanges) -> Result<(), SessionStoreError>;
+
+    /// Whether [`SessionStore::save`] may block. Stores that never do are
+    /// written directly by the request that changed the account.
+    fn blocks(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SessionStoreError {
+    #[error("failed to serialize session snapshot: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("session database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("session store lock poisoned")]
+    Poisoned,
+    #[error("failed to start the session store writer: {0}")]
+    Writer(#[from] io::Error),
+}
+
+/// Keeps account sections in process memory.
+///
+/// This is the default backend; sections survive for as long as the store
+/// itself, which makes it useful for rebuilding an [`crate::AppState`] in tests.
+#[derive(Default)]
+pub struct InMemorySessionStore {
+    accounts: Mutex<HashMap<String, StoredSections>>,
+}
+
+impl SessionStore for InMemorySessionStore {
+    fn load(&self) -> Result<Vec<(String, StoredSections)>, SessionStoreError> {
+        let accounts = self
+            .accounts
+            .lock()
+            .map_err(|_| SessionStoreError::Poisoned)?;
+        Ok(accounts
+            .iter()
+            .map(|(account, sections)| (account.clone(), sections.clone()))
+            .collect())
+    }
+
+    fn save(&self, account: &str, changes: &SectionChanges) -> Result<(), SessionStoreError> {
+        let mut accounts = self
+            .accounts
+            .lock()
+            .map_err(|_| SessionStoreError::Poisoned)?;
+        let sections = accounts.entry(account.to_string()).or_default();
+        for name in &changes.removed {
+            sections.remove(name);
+        }
+        sections.extend(changes.written.clone());
+        if sections.is_empty() {
+            accounts.remove(account);
+        }
+        Ok(())
+    }
+
+    fn blocks(&self) -> bool {
+        false
+    }
+}
+
+/// Persists account sections to an embedded SQLite database file.
+pub struct SqliteSessionStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteSessionStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SessionStoreError> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS account_sections (
+                 account TEXT NOT NULL,
+                 section TEXT NOT NULL,
+                 snapshot TEXT NOT NULL,
+                 updated_at TEXT NOT NULL,
+                 PRIMARY KEY (account, section)
+             );",
+        )?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+impl SessionStore for SqliteSessionStore {
+    fn load(&self) -> Result<Vec<(String, StoredSections)>, SessionStoreError> {
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| SessionStoreError::Poisoned)?;
+        let mut statement =
+            connection.prepare("SELECT account, section, snapshot FROM account_sections")?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+
+        let mut accounts: BTreeMap<String, StoredSections> = BTreeMap::new();
+        for row in rows {
+            let (account, section, snapshot) = row?;
+            accounts
+                .entry(account)
+                .or_default()
+                .insert(section, snapshot);
+        }
+        Ok(accounts.into_iter().collect())
+    }
+
+    fn save(&self, account: &str, changes: &SectionChanges) -> Result<(), SessionStoreError> {
+        let updated_at = current_time().unix_timestamp_nanos().to_string();
+        let mut connection = self
+            .connection
+            .lock()
+            .map_err(|_| SessionStoreError::Poisoned)?;
+        let transaction = connection.transaction()?;
+        for section in &changes.removed {
+            transaction.execute(
+                "DELETE FROM account_sections WHERE account = ?1 AND section = ?2",
+                params![account, section],
+            )?;
+        }
+        for (section, snapshot) in &changes.written {
+            transaction.execute(
+                "INSERT INTO account_sections (account, section, snapshot, updated_at)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT(account, section) DO UPDATE SET
+                     snapshot = excluded.snapshot,
+                     updated_at = excluded.updated_at",
+                params![account, section, snapshot, updated_at],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+enum WriterMessage {
+    Save {
+        account: String,
+        update: SectionUpdate,
+        done: oneshot::Sender<()>,
+    },
+    Flush(oneshot::Sender<()>),
+}
+
+/// Hands account sections to the store, passing on only those that changed,
+/// and deleting those that disappeared, since the account was last saved.
+///
+/// Stores whose writes may block are written on a dedicated thread fed by a
+/// bounded queue: request handlers never wait on the database itself, but
+/// they are held back once the thread falls too far behind. Other stores are
+/// written directly. Dropping the writer waits for the queued writes.
+pub(crate) struct StoreWriter {
+    backend: WriterBackend,
+}
+
+enum WriterBackend {
+    Inline(Mutex<SectionWriter>),
+    Thread {
+        sender: Option<mpsc::Sender<WriterMessage>>,
+        thread: Option<thread::JoinHandle<()>>,
+    },
+}
+
+/// Resolves once a save has been applied to the store.
+#[derive(Default)]
+pub(crate) struct SaveReceipt(Option<oneshot::Receiver<()>>);
+
+impl SaveReceipt {
+    /// Waits until the save has reached the store, or failed to.
+    pub(crate) async fn written(self) {
+        if let Some(done) = self.0 {
+            let _ = done.await;
+        }
+    }
+}
+
+type SectionDigest = [u8; 32];
+
+fn section_digest(snapshot: &str) -> SectionDigest {
+    Sha256::digest(snapshot.as_bytes()).into()
+}
+
+impl StoreWriter {
+    /// Starts the writer for `store`, whose current contents are `loaded`.
+    pub(crate) fn spawn(
+        store: Arc<dyn SessionStore>,
+        loaded: &[(String, StoredSections)],
+    ) -> Result<Self, SessionStoreError> {
+        let mut writer = SectionWriter::new(store, loaded);
+        if !writer.store.blocks() {
+            return Ok(Self {
+                backend: WriterBackend::Inline(Mutex::new(writer)),
+            });
+        }
+
+        let (sender, mut receiver) = mpsc::channel(WRITE_QUEUE_CAPACITY);
+        let thread = thread::Builder::new()
+            .name("session-store-writer".to_string())
+            .spawn(move || {
+                while let Some(message) = receiver.blocking_recv() {
+                    match message {
+                        WriterMessage::Save {
+                            account,
+                            update,
+                            done,
+                        } => {
+                            writer.save(account, update);
+                            let _ = done.send(());
+                        }
+                        WriterMessage::Flush(done) => {
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            backend: WriterBackend::Thread {
+                sender: Some(sender),
+                thread: Some(thread),
+            },
+        })
+    }
+
+    /// Saves the latest sections of `account`, waiting for room in the queue
+    /// while the writer thread is behind.
+    pub(crate) async fn save(&self, account: &str, update: SectionUpdate) -> SaveReceipt {
+        match &self.backend {
+            WriterBackend::Inline(writer) => {
+                match writer.lock() {
+                    Ok(mut writer) => writer.save(account.to_string(), update),
+                    Err(_) => warn!("session store writer lock poisoned; dropping write"),
+                }
+                SaveReceipt(None)
+            }
+            WriterBackend::Thread { sender, .. } => {
+                let (done, written) = oneshot::channel();
+                let message = WriterMessage::Save {
+                    account: account.to_string(),
+                    update,
+                    done,
+                };
+                send(sender.as_ref(), message).await;
+                SaveReceipt(Some(written))
+            }
+        }
+    }
+
+    /// Waits until every save queued so far has been applied.
+    pub(crate) async fn flush(&self) {
+        if let WriterBackend::Thread { sender, .. } = &self.backend {
+            let (done, flushed) = oneshot::channel();
+            if send(sender.as_ref(), WriterMessage::Flush(done)).await {
+                let _ = flushed.await;
+            }
+        }
+    }
+}
+
+async fn send(sender: Option<&mpsc::Sender<WriterMessage>>, message: WriterMessage) -> bool {
+    let sent = match sender {
+        Some(sender) => sender.send(message).await.is_ok(),
+        None => false,
+    };
+    if !sent {
+        warn!("session store writer has stopped; dropping write");
+    }
+    sent
+}
+
+impl Drop for StoreWriter {
+    fn drop(&mut self) {
+        if let WriterBackend::Thread { sender, thread } = &mut self.backend {
+            drop(sender.take());
+            if let Some(thread) = thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+/// Tracks what has been written for every account and turns each update into
+/// the changes the store still has to apply.
+struct SectionWriter {
+    store: Arc<dyn SessionStore>,
+    written: HashMap<String, HashMap<String, SectionDigest>>,
+    /// Changes whose write failed, retried with the account's next save.
+    failed: HashMap<String, SectionChanges>,
+}
+
+impl SectionWriter {
+    fn new(store: Arc<dyn SessionStore>, loaded: &[(String, StoredSections)]) -> Self {
+        let written = loaded
+            .iter()
+            .map(|(account, sections)| {
+                let digests = sections
+                    .iter()
+                    .map(|(section, snapshot)| (section.clone(), section_digest(snapshot)))
+                    .collect();
+                (account.clone(), digests)
+            })
+            .collect();
+
+        Self {
+            store,
+            written,
+            failed: HashMap::new(),
+        }
+    }
+
+    fn save(&mut self, account: String, update: SectionUpdate) {
+        let previous = self.written.remove(&account).unwrap_or_default();
+        let (mut changes, current) = diff_sections(previous, update);
+        self.written.insert(account.clone(), current);
+        if let Some(mut failed) = self.failed.remove(&account) {
+            failed.merge(changes);
+            changes = failed;
+        }
+
+        if changes.is_empty() {
+            return;
+        }
+        if let Err(error) = self.store.save(&account, &changes) {
+            warn!(account = %account, %error, "failed to persist account sessions");
+            self.failed.insert(account, changes);
+        }
+    }
+}
+
+/// Compares `update` with the digests of the sections saved before, returning
+/// the changes to apply and the digests that are persisted once they are.
+fn diff_sections(
+    mut previous: HashMap<String, SectionDigest>,
+    update: SectionUpdate,
+) -> (SectionChanges, HashMap<String, SectionDigest>) {
+    let mut changes = SectionChanges::default();
+    let mut current = HashMap::with_capacity(update.sections.len() + update.unchanged.len());
+    for (section, snapshot) in update.sections {
+        let digest = section_digest(&snapshot);
+        if previous.remove(&section) != Some(digest) {
+            changes.written.insert(section.clone(), snapshot);
+        }
+        current.insert(section, digest);
+    }
+    for section in update.unchanged {
+        if let Some(digest) = previous.remove(&section) {
+            current.insert(section, digest);
+        }
+    }
+    changes.removed = previous.into_keys().collect();
+    (changes, current)
+}
+
+#[derive(Debug, Error)]
+pub enum SessionStoreConfigError {
+    #[error("environment variable {name} contains invalid UTF-8 characters")]
+    InvalidUnicode {
+        name: &'static str,
+        #[source]
+        source: env::VarError,
+    },
+    #[error("unsupported session store {value:?}; expected \"memory\" or \"sqlite\"")]
+    UnknownKind { value: String },
+}
+
+/// Selects the session persistence backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionStoreConfig {
+    Memory,
+    Sqlite { path: PathBuf },
+}
+
+impl SessionStoreConfig {
+    pub fn from_env() -> Result<Self, SessionStoreConfigError> {
+        let kind = read_env(STORE_KIND_ENV)?;
+
+        match kind.as_deref().map(str::trim) {
+            None | Some("") | Some("memory") => Ok(SessionStoreConfig::Memory),
+            Some("sqlite") => {
+                let path = read_env(STORE_PATH_ENV)?
+                    .filter(|value| !value.trim().is_empty())
+                    .unwrap_or_else(|| DEFAULT_STORE_PATH.to_string());
+                Ok(SessionStoreConfig::Sqlite {
+                    path: PathBuf::from(path),
+                })
+            }
+            Some(other) => Err(SessionStoreConfigError::UnknownKind {
+                value: other.to_string(),
+            }),
+        }
+    }
+
+    pub fn open(&self) -> Result<Arc<dyn SessionStore>, SessionStoreError> {
+        match self {
+            SessionStoreConfig::Memory => Ok(Arc::new(InMemorySessionStore::default())),
+            SessionStoreConfig::Sqlite { path } => Ok(Arc::new(SqliteSessionStore::open(path)?)),
+        }
+    }
+}
+
+fn read_env(name: &'static str) -> Result<Option<String>, SessionStoreConfigError> {
+    match env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(error) => Err(SessionStoreConfigError::InvalidUnicode {
+            name,
+            source: error,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hash_secret, AccountSequences, AccountSessions, AuthMethod, OutboxEventRequest,
+        SessionRecord,
+    };
+    use serde_json::json;
+
+    fn sections_of(sessions: &AccountSessions) -> SectionUpdate {
+        sessions.sections().expect("serialize sections")
+    }
+
+    fn restore(sections: &StoredSections) -> AccountSessions {
+        serde_json::from_value(crate::join_sections(sections).expect("join sections"))
+            .expect("deserialize sessions")
+    }
+
+    #[tokio::test]
+    async fn sqlite_store_round_trips_sessions_and_outbox() {
+        let path = env::temp_dir().join(format!("gateway-store-{}.db", uuid::Uuid::new_v4()));
+        let account = "acct-sqlite";
+        let hash = hash_secret(AuthMethod::AccountSessionKey, "secret", account);
+        let mut session = SessionRecord::new(AuthMethod::AccountSessionKey, hash);
+        let event = session.enqueue_outbox(
+            &mut AccountSequences::default(),
+            OutboxEventRequest {
+                event_type: "OrderCommand".to_string(),
+                payload: json!({"instrument": "EURUSD"}),
+                requires_ack: true,
+                expires_at: None,
+            },
+        );
+        let token = session.session_token;
+
+        let mut sessions = AccountSessions::default();
+        sessions.insert(session);
+
+        {
+            let store = Arc::new(SqliteSessionStore::open(&path).expect("open store"));
+            let writer = StoreWriter::spawn(store, &[]).expect("start writer");
+            writer.save(account, sections_of(&sessions)).await;
+        }
+
+        let store = SqliteSessionStore::open(&path).expect("reopen store");
+        let mut loaded = store.load().expect("load sections");
+        assert_eq!(loaded.len(), 1);
+        let (loaded_account, sections) = loaded.remove(0);
+        assert_eq!(loaded_account, account);
+        assert!(sections.contains_key(&format!("session:{token}")));
+
+        let mut loaded_sessions = restore(&sections);
+        assert_eq!(loaded_sessions.outbox_high_water_mark(), 1);
+        let restored = loaded_sessions
+            .get_mut_by_token(&token)
+            .expect("session restored");
+        assert_eq!(restored.outbox.len(), 1);
+        assert_eq!(restored.outbox[0].id, event.id);
+
+        drop(store);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// Records every change handed to the store, failing while `failing` is set.
+    #[derive(Default)]
+    struct RecordingStore {
+        saves: Mutex<Vec<SectionChanges>>,
+        failing: AtomicBool,
+    }
+
+    impl SessionStore for RecordingStore {
+        fn load(&self) -> Result<Vec<(String, StoredSections)>, SessionStoreError> {
+            Ok(Vec::new())
+        }
+
+        fn save(&self, _account: &str, changes: &SectionChanges) -> Result<(), SessionStoreError> {
+            if self.failing.load(Ordering::Relaxed) {
+                return Err(SessionStoreError::Poisoned);
+            }
+            self.saves.lock().unwrap().push(changes.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn writer_only_saves_sections_that_changed() {
+        let store = Arc::new(RecordingStore::default());
+        let writer = StoreWriter::spawn(store.clone(), &[]).expect("start writer");
+        let account = "acct-writer";
+        let hash = hash_secret(AuthMethod::AccountSessionKey, "secret", account);
+        let mut sessions = AccountSessions::default();
+        sessions.insert(SessionRecord::new(
+            AuthMethod::AccountSessionKey,
+            hash.clone(),
+        ));
+        let first = SessionRecord::new(AuthMethod::AccountSessionKey, hash);
+        let token = first.session_token;
+        sessions.insert(first);
+
+        writer.save(account, sections_of(&sessions)).await;
+        writer.save(account, sections_of(&sessions)).await;
+        let (session, sequences) = sessions
+            .session_with_sequences_by_token(&token)
+            .expect("session");
+        session.enqueue_outbox(
+            sequences,
+            OutboxEventRequest {
+                event_type: "OrderCommand".to_string(),
+                payload: json!({"instrument": "EURUSD"}),
+                requires_ack: true,
+                expires_at: None,
+            },
+        );
+        writer.save(account, sections_of(&sessions)).await;
+        sessions.remove_by_token(&token);
+        writer.save(account, sections_of(&sessions)).await;
+        writer.flush().await;
+
+        let saves = store.saves.lock().unwrap();
+        assert_eq!(saves.len(), 3, "an unchanged account is not written again");
+        assert!(saves[0].written.len() >= 4);
+
+        let session_section = format!("session:{token}");
+        let changed: Vec<&String> = saves[1].written.keys().collect();
+        assert!(changed.contains(&&session_section));
+        assert!(!changed.contains(&&"portfolio".to_string()));
+        assert!(!changed.contains(&&"commands".to_string()));
+
+        assert_eq!(saves[2].removed, vec![session_section]);
+    }
+
+    #[tokio::test]
+    async fn writer_retries_failed_changes_with_the_next_save() {
+        let store = Arc::new(RecordingStore::default());
+        let writer = StoreWriter::spawn(store.clone(), &[]).expect("start writer");
+        let account = "acct-retry";
+        let hash = hash_secret(AuthMethod::AccountSessionKey, "secret", account);
+        let session = SessionRecord::new(AuthMethod::AccountSessionKey, hash);
+        let session_section = format!("session:{}", session.session_token);
+        let mut sessions = AccountSessions::default();
+
+        store.failing.store(true, Ordering::Relaxed);
+        sessions.insert(session);
+        writer
+            .save(account, sections_of(&sessions))
+            .await
+            .written()
+            .await;
+        assert!(store.saves.lock().unwrap().is_empty());
+
+        store.failing.store(false, Ordering::Relaxed);
+        writer
+            .save(account, sections_of(&sessions))
+            .await
+            .written()
+            .await;
+        let saves = store.saves.lock().unwrap();
+        assert_eq!(saves.len(), 1);
+        assert!(saves[0].written.contains_key(&session_section));
+    }
+}
@@ -0,0 +1,392 @@
+use std::{collections::VecDeque, time::Duration};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::HeaderMap,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Response,
+    },
+};
+use futures_util::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::futures::OwnedNotified,
+    time::{sleep, sleep_until, timeout, Instant},
+};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    account_from_headers, bearer_token, current_time, AckResponse, ApiError, AppState, ErrorBody,
+    InboxEvent, InboxResponse, OutboundEvent, OutboxDelivery, SessionStatus,
+};
+
+/// Interval between pings, which also re-checks that the session still exists.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Interval between SSE keep-alive comments.
+const SSE_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+const LAST_EVENT_ID: &str = "Last-Event-ID";
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct StreamQuery {
+    #[serde(default)]
+    cursor: Option<u64>,
+}
+
+/// Frames accepted from the EA.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum ClientFrame {
+    Inbox {
+        events: Vec<InboxEvent>,
+    },
+    #[serde(rename_all = "camelCase")]
+    OutboxAck {
+        event_id: Uuid,
+    },
+}
+
+/// Frames pushed to the EA.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+enum ServerFrame<'a> {
+    Event(&'a OutboundEvent),
+    InboxAccepted(InboxResponse),
+    OutboxAcked(AckResponse),
+    Error(ErrorBody),
+}
+
+/// Upgrades an authenticated EA session to a WebSocket that pushes outbox
+/// events as they are enqueued and accepts inbox events and acks as frames.
+pub(crate) async fn session_stream(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<StreamQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let account = account_from_headers(&headers)?;
+    let token = bearer_token(&headers)?;
+    let session_id = authorize(&state, &account, token).await?;
+
+    info!(account = %account, session = %session_id, "session stream opened");
+
+    let cursor = query.cursor.unwrap_or_default();
+    Ok(upgrade.on_upgrade(move |socket| async move {
+        run_stream(socket, &state, &account, token, cursor).await;
+        info!(account = %account, session = %session_id, "session stream closed");
+    }))
+}
+
+async fn authorize(state: &AppState, account: &str, token: Uuid) -> Result<Uuid, ApiError> {
+    let Some(handle) = state.account(account).await else {
+        return Err(ApiError::unauthorized(
+            "session_missing",
+            "no active session for the supplied account",
+        ));
+    };
+    let mut account_sessions = handle.lock().await;
+
+    let Some(session) = account_sessions.get_mut_by_token(&token) else {
+        return Err(ApiError::unauthorized(
+            "invalid_session_token",
+            "the provided session token is not valid for this account",
+        ));
+    };
+
+    if session.status == SessionStatus::Terminated {
+        return Err(ApiError::forbidden(
+            "session_terminated",
+            "the session has been terminated and cannot open a stream",
+        ));
+    }
+
+    Ok(session.session_id)
+}
+
+async fn run_stream(
+    mut socket: WebSocket,
+    state: &AppState,
+    account: &str,
+    token: Uuid,
+    mut cursor: u64,
+) {
+    loop {
+        let Some(OutboxSnapshot {
+            events,
+            terminated,
+            notified,
+            redelivery_at,
+        }) = outbox_snapshot(state, account, token, cursor).await
+        else {
+            break;
+        };
+        tokio::pin!(notified);
+
+        for event in &events {
+            if send_frame(&mut socket, &ServerFrame::Event(event))
+                .await
+                .is_err()
+            {
+                return;
+            }
+            cursor = cursor.max(event.sequence);
+        }
+
+        if terminated {
+            break;
+        }
+
+        let keepalive = sleep(KEEPALIVE_INTERVAL);
+        tokio::pin!(keepalive);
+        let redelivery = sleep_until(redelivery_at.unwrap_or_else(Instant::now));
+        tokio::pin!(redelivery);
+
+        loop {
+            tokio::select! {
+                _ = &mut notified => break,
+                _ = &mut redelivery, if redelivery_at.is_some() => break,
+                _ = &mut keepalive => {
+                    if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                        return;
+                    }
+                    break;
+                }
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        let reply = handle_client_frame(state, account, token, &text).await;
+                        if send_frame(&mut socket, &reply).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | None => return,
+                    Some(Ok(_)) => {}
+                    Some(Err(error)) => {
+                        debug!(account = %account, %error, "session stream receive failed");
+                        return;
+                    }
+                },
+            }
+        }
+    }
+
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+/// Streams a session's outbox as Server-Sent Events, using each event's
+/// sequence as the SSE `id` so that clients can resume via `Last-Event-ID`.
+///
+/// The stream only observes the outbox: events stay queued for the EA and
+/// their delivery attempts are left untouched.
+pub(crate) async fn outbox_event_stream(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<StreamQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, ApiError> {
+    let account = account_from_headers(&headers)?;
+    let token = bearer_token(&headers)?;
+    let session_id = authorize(&state, &account, token).await?;
+    let cursor = match headers.get(LAST_EVENT_ID) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .ok_or_else(|| {
+                ApiError::bad_request(
+                    "invalid_last_event_id",
+                    "Last-Event-ID must be an outbox sequence number",
+                )
+            })?,
+        None => query.cursor.unwrap_or_default(),
+    };
+
+    info!(account = %account, session = %session_id, cursor, "outbox event stream opened");
+
+    let tail = OutboxTail {
+        state,
+        account,
+        token,
+        cursor,
+        buffered: VecDeque::new(),
+    };
+    let events = stream::unfold(tail, |mut tail| async move {
+        let event = tail.next_event().await?;
+        let sse = Event::default()
+            .id(event.sequence.to_string())
+            .json_data(&event);
+        Some((sse, tail))
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::new().interval(SSE_KEEPALIVE_INTERVAL)))
+}
+
+struct OutboxTail {
+    state: AppState,
+    account: String,
+    token: Uuid,
+    cursor: u64,
+    buffered: VecDeque<OutboundEvent>,
+}
+
+impl OutboxTail {
+    /// Waits for the next event queued after the cursor, returning `None` once
+    /// the session is gone or has been terminated and fully tailed.
+    async fn next_event(&mut self) -> Option<OutboundEvent> {
+        loop {
+            if let Some(event) = self.buffered.pop_front() {
+                self.cursor = self.cursor.max(event.sequence);
+                return Some(event);
+            }
+
+            let OutboxSnapshot {
+                events,
+                terminated,
+                notified,
+                ..
+            } = outbox_view(&self.state, &self.account, self.token, self.cursor).await?;
+
+            if !events.is_empty() {
+                self.buffered.extend(events);
+                continue;
+            }
+
+            if terminated {
+                return None;
+            }
+
+            // Wake up periodically to notice sessions that were removed outright.
+            let _ = timeout(KEEPALIVE_INTERVAL, notified).await;
+        }
+    }
+}
+
+/// Outbox events handed to a stream, plus a wake-up that was registered before
+/// the account lock was released so that later enqueues are never missed.
+struct OutboxSnapshot {
+    events: Vec<OutboundEvent>,
+    terminated: bool,
+    notified: OwnedNotified,
+    /// When the next in-flight event becomes visible again.
+    redelivery_at: Option<Instant>,
+}
+
+/// Outbox events queued after `cursor`, read without any delivery accounting.
+async fn outbox_view(
+    state: &AppState,
+    account: &str,
+    token: Uuid,
+    cursor: u64,
+) -> Option<OutboxSnapshot> {
+    let handle = state.account(account).await?;
+    let account_sessions = handle.lock().await;
+    let session = account_sessions.sessions_by_token.get(&token)?;
+
+    Some(OutboxSnapshot {
+        events: session.events_after(cursor, current_time()),
+        terminated: session.status == SessionStatus::Terminated,
+        notified: session.outbox_notify.clone().notified_owned(),
+        redelivery_at: None,
+    })
+}
+
+async fn outbox_snapshot(
+    state: &AppState,
+    account: &str,
+    token: Uuid,
+    cursor: u64,
+) -> Option<OutboxSnapshot> {
+    let handle = state.account(account).await?;
+    let mut account_sessions = handle.lock().await;
+
+    let now = current_time();
+    let expired = account_sessions.expire_outbox_events(now);
+    if !expired.is_empty() {
+        state.persist_sessions(account, &account_sessions).await;
+        state.report_expired_events(account, &expired);
+    }
+
+    // The EA's stream goes through the same delivery accounting as polls, so
+    // in-flight events stay hidden for the visibility timeout and are
+    // dead-lettered once they run out of attempts.
+    let (session, sequences) = account_sessions.session_with_sequences_by_token(&token)?;
+    let OutboxDelivery {
+        events,
+        dead_lettered,
+        ..
+    } = session.deliver_outbox(sequences, cursor, None, &state.delivery, now);
+    state.report_dead_letters(account, session.session_id, &dead_lettered);
+    let terminated = session.status == SessionStatus::Terminated;
+    let notified = session.outbox_notify.clone().notified_owned();
+    let redelivery_at = session
+        .next_redelivery_at(&state.delivery)
+        .map(|visible_at| {
+            let remaining = (visible_at - now).max(time::Duration::ZERO);
+            Instant::now() + Duration::try_from(remaining).unwrap_or_default()
+        });
+
+    account_sessions.commands.update(
+        |ledger| ledger.mark_delivered(&events, now),
+        |delivered| *delivered > 0,
+    );
+    if !events.is_empty() || !dead_lettered.is_empty() {
+        state.persist_sessions(account, &account_sessions).await;
+    }
+
+    Some(OutboxSnapshot {
+        events,
+        terminated,
+        notified,
+        redelivery_at,
+    })
+}
+
+async fn handle_client_frame(
+    state: &AppState,
+    account: &str,
+    token: Uuid,
+    text: &str,
+) -> ServerFrame<'static> {
+    let frame = match serde_json::from_str::<ClientFrame>(text) {
+        Ok(frame) => frame,
+        Err(error) => {
+            return ServerFrame::Error(ErrorBody {
+                code: "invalid_frame",
+                message: error.to_string(),
+            })
+        }
+    };
+
+    let result = match frame {
+        ClientFrame::Inbox { events } => state
+            .capture_inbox(account, token, events)
+            .await
+            .map(ServerFrame::InboxAccepted),
+        ClientFrame::OutboxAck { event_id } => state
+            .acknowledge_outbox(account, token, event_id)
+            .await
+            .map(ServerFrame::OutboxAcked),
+    };
+
+    result.unwrap_or_else(|error| {
+        ServerFrame::Error(ErrorBody {
+            code: error.code(),
+            message: error.message().to_string(),
+        })
+    })
+}
+
+async fn send_frame(socket: &mut WebSocket, frame: &ServerFrame<'_>) -> Result<(), axum::Error> {
+    let text = match serde_json::to_string(frame) {
+        Ok(text) => text,
+        Err(error) => {
+            warn!(%error, "failed to serialize session stream frame");
+            return Ok(());
+        }
+    };
+    socket.send(Message::Text(text)).await
+}
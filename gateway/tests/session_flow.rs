@@ -1,18 +1,30 @@
-use std::fmt::Debug;
+use std::{
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use axum::{
     body::Body,
     http::{self, header, Request, StatusCode},
     response::Response,
+    Router,
 };
+use futures_util::{SinkExt, StreamExt};
 use gateway::{
-    router, AdminApprovalCommand, AdminCommand, AdminCommandOutcome, AppState, AuthMethod,
-    SessionStatus,
+    admin_router, router, AdminApprovalCommand, AdminCommand, AdminCommandOutcome, AppState,
+    AuthMethod, InMemorySessionStore, SessionStatus,
 };
 use http_body_util::BodyExt;
 use serde::{de::DeserializeOwned, Deserialize};
 use serde_json::json;
 use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tokio::net::TcpListener;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, Error as WsError, Message as WsMessage},
+};
 use tower::ServiceExt;
 use uuid::Uuid;
 
@@ -338,6 +350,83 @@ async fn pending_session_ingests_events_and_remains_pending() {
     assert!(outbox.events.is_empty());
 }
 
+#[tokio::test]
+async fn malformed_catalogue_events_are_rejected_individually() {
+    let app = router(AppState::default());
+    let account = "acct-inbox-schema";
+    let created = open_session(&app, account, "inbox-schema-secret").await;
+
+    let body = json!({
+        "events": [
+            { "eventType": "SyncSnapshot", "payload": { "balance": "lots" } },
+            { "eventType": "statusheartbeat", "payload": { "equity": 1025.5 } },
+            { "eventType": "OrderIntent", "payload": { "symbol": "USDJPY", "volume": -1.0 } },
+            { "eventType": "ea.autotrade_status", "payload": { "status": "online" } },
+        ]
+    });
+    let (status, inbox) = json_response::<InboxResponsePayload>(
+        app.clone()
+            .oneshot(inbox_request(account, created.session_token, body))
+            .await
+            .expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(inbox.accepted, 2);
+    let rejected: Vec<(usize, &str, &str)> = inbox
+        .rejected
+        .iter()
+        .map(|rejection| {
+            (
+                rejection.index,
+                rejection.event_type.as_str(),
+                rejection.code.as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        rejected,
+        vec![
+            (0, "SyncSnapshot", "malformed_payload"),
+            (2, "OrderIntent", "invalid_payload"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn resent_inbox_events_are_counted_as_duplicates() {
+    let app = router(AppState::default());
+    let account = "acct-inbox-dedup";
+    let created = open_session(&app, account, "inbox-dedup-secret").await;
+    let body = json!({
+        "events": [
+            { "eventId": "evt_1001", "eventType": "ExecutionReport", "payload": { "deal": 42 } },
+            { "eventId": "evt_1002", "eventType": "StatusHeartbeat", "payload": {} },
+            { "eventId": "evt_1001", "eventType": "ExecutionReport", "payload": { "deal": 42 } },
+        ]
+    });
+
+    let (status, first) = json_response::<InboxResponsePayload>(
+        app.clone()
+            .oneshot(inbox_request(account, created.session_token, body.clone()))
+            .await
+            .expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!((first.accepted, first.duplicates), (2, 1));
+
+    let (status, resent) = json_response::<InboxResponsePayload>(
+        app.clone()
+            .oneshot(inbox_request(account, created.session_token, body))
+            .await
+            .expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!((resent.accepted, resent.duplicates), (0, 3));
+}
+
 #[tokio::test]
 async fn session_creation_is_idempotent_and_preempts_previous() {
     let app = router(AppState::default());
@@ -455,6 +544,154 @@ async fn session_creation_is_idempotent_and_preempts_previous() {
     );
 }
 
+#[tokio::test]
+async fn outbox_sequences_continue_across_reconnects() {
+    let state = AppState::default();
+    let app = router(state.clone());
+    let account = "acct-sequence";
+    let auth_key = "sequence-secret";
+
+    let first = open_session(&app, account, auth_key).await;
+    assert_eq!(first.outbox_high_water_mark, 0);
+    approve_session_via_service_bus(
+        &state,
+        account,
+        first.session_id,
+        AuthMethod::AccountSessionKey,
+        auth_key,
+    )
+    .await;
+
+    let second = open_session(&app, account, auth_key).await;
+    assert_eq!(second.previous_session_terminated, Some(first.session_id));
+    // InitAck and the ShutdownNotice of the preempted session.
+    assert_eq!(second.outbox_high_water_mark, 2);
+    approve_session_via_service_bus(
+        &state,
+        account,
+        second.session_id,
+        AuthMethod::AccountSessionKey,
+        auth_key,
+    )
+    .await;
+
+    let (status, outbox) = json_response::<OutboxResponsePayload>(
+        app.clone()
+            .oneshot(outbox_request(
+                account,
+                second.session_token,
+                &format!("cursor={}", second.outbox_high_water_mark),
+            ))
+            .await
+            .expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(outbox.events.len(), 1);
+    assert_eq!(outbox.events[0].event_type, "InitAck");
+    assert_eq!(outbox.events[0].sequence, 3);
+}
+
+#[tokio::test]
+async fn reused_idempotency_key_with_different_body_is_rejected() {
+    let app = router(AppState::default());
+    let account = "acct-reused-key";
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let create_request = |auth_key: &str| {
+        Request::builder()
+            .method(http::Method::POST)
+            .uri("/trade-agent/v1/sessions")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("X-TradeAgent-Account", account)
+            .header("Idempotency-Key", idempotency_key.as_str())
+            .body(Body::from(
+                json!({
+                    "authenticationKey": auth_key,
+                })
+                .to_string(),
+            ))
+            .expect("failed to build create session request")
+    };
+
+    let (status, _) = json_response::<SessionCreateResponsePayload>(
+        app.clone()
+            .oneshot(create_request("first-secret"))
+            .await
+            .expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, error) = json_response::<ErrorResponsePayload>(
+        app.clone()
+            .oneshot(create_request("second-secret"))
+            .await
+            .expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(error.code, "idempotency_key_reused");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_duplicate_requests_create_a_single_session() {
+    let app = router(AppState::default());
+    let account = "acct-concurrent";
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let create_request = |key: &str| {
+        Request::builder()
+            .method(http::Method::POST)
+            .uri("/trade-agent/v1/sessions")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("X-TradeAgent-Account", account)
+            .header("Idempotency-Key", key)
+            .body(Body::from(
+                json!({
+                    "authenticationKey": "concurrent-secret",
+                })
+                .to_string(),
+            ))
+            .expect("failed to build create session request")
+    };
+
+    let mut handles = Vec::new();
+    for _ in 0..8 {
+        let app = app.clone();
+        let request = create_request(&idempotency_key);
+        handles.push(tokio::spawn(async move {
+            json_response::<SessionCreateResponsePayload>(
+                app.oneshot(request).await.expect("router error"),
+            )
+            .await
+        }));
+    }
+
+    let mut session_ids = Vec::new();
+    for handle in handles {
+        let (status, created) = handle.await.expect("request task panicked");
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(created.previous_session_terminated.is_none());
+        session_ids.push(created.session_id);
+    }
+    session_ids.dedup();
+    assert_eq!(session_ids.len(), 1);
+
+    let (status, replacement) = json_response::<SessionCreateResponsePayload>(
+        app.clone()
+            .oneshot(create_request(&Uuid::new_v4().to_string()))
+            .await
+            .expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(
+        replacement.previous_session_terminated,
+        Some(session_ids[0])
+    );
+}
+
 #[tokio::test]
 async fn terminated_session_rejects_inbox_submission() {
     let state = AppState::default();
@@ -627,6 +864,587 @@ async fn pre_shared_key_sessions_authenticate_successfully() {
     assert_eq!(outbox.events[0].event_type, "InitAck");
 }
 
+#[tokio::test]
+async fn sessions_survive_state_rebuild_from_store() {
+    let store = Arc::new(InMemorySessionStore::default());
+    let state = AppState::with_store(store.clone()).expect("state should load from store");
+    let app = router(state.clone());
+    let account = "acct-durable";
+    let auth_key = "durable-secret";
+
+    let create_idempotency = Uuid::new_v4().to_string();
+    let create_request = Request::builder()
+        .method(http::Method::POST)
+        .uri("/trade-agent/v1/sessions")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("X-TradeAgent-Account", account)
+        .header("Idempotency-Key", create_idempotency.as_str())
+        .body(Body::from(
+            json!({
+                "authenticationKey": auth_key,
+            })
+            .to_string(),
+        ))
+        .expect("failed to build create session request");
+
+    let (status, created) = json_response::<SessionCreateResponsePayload>(
+        app.clone()
+            .oneshot(create_request)
+            .await
+            .expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    approve_session_via_service_bus(
+        &state,
+        account,
+        created.session_id,
+        created.auth_method,
+        auth_key,
+    )
+    .await;
+
+    drop(app);
+    drop(state);
+
+    let restarted = AppState::with_store(store).expect("state should reload from store");
+    let app = router(restarted);
+
+    let outbox_request = Request::builder()
+        .method(http::Method::GET)
+        .uri("/trade-agent/v1/sessions/current/outbox")
+        .header("X-TradeAgent-Account", account)
+        .header(
+            header::AUTHORIZATION,
+            format!("Bearer {}", created.session_token),
+        )
+        .body(Body::empty())
+        .expect("failed to build outbox request");
+
+    let (status, outbox) = json_response::<OutboxResponsePayload>(
+        app.clone()
+            .oneshot(outbox_request)
+            .await
+            .expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(outbox.session_id, created.session_id);
+    assert!(!outbox.pending);
+    assert_eq!(outbox.events.len(), 1);
+    assert_eq!(outbox.events[0].sequence, 1);
+    assert_eq!(outbox.events[0].event_type, "InitAck");
+}
+
+#[tokio::test]
+async fn lapsed_approval_terminates_session_on_next_poll() {
+    let state = AppState::default();
+    let app = router(state.clone());
+    let account = "acct-bounded";
+    let auth_key = "bounded-secret";
+
+    let create_idempotency = Uuid::new_v4().to_string();
+    let create_request = Request::builder()
+        .method(http::Method::POST)
+        .uri("/trade-agent/v1/sessions")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("X-TradeAgent-Account", account)
+        .header("Idempotency-Key", create_idempotency.as_str())
+        .body(Body::from(
+            json!({
+                "authenticationKey": auth_key,
+            })
+            .to_string(),
+        ))
+        .expect("failed to build create session request");
+
+    let (status, created) = json_response::<SessionCreateResponsePayload>(
+        app.clone()
+            .oneshot(create_request)
+            .await
+            .expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let expires_at = OffsetDateTime::now_utc() + time::Duration::milliseconds(200);
+    let outcome = state
+        .apply_admin_command(AdminCommand::Approve(AdminApprovalCommand {
+            account_id: account.to_string(),
+            session_id: created.session_id,
+            auth_key_fingerprint: fingerprint_for(account, created.auth_method, auth_key),
+            approved_by: Some("integration-test".to_string()),
+            expires_at: Some(expires_at),
+        }))
+        .await
+        .expect("service bus approval should succeed");
+    assert!(matches!(
+        outcome,
+        AdminCommandOutcome::SessionAuthenticated(_)
+    ));
+
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+
+    let outbox_request = Request::builder()
+        .method(http::Method::GET)
+        .uri("/trade-agent/v1/sessions/current/outbox")
+        .header("X-TradeAgent-Account", account)
+        .header(
+            header::AUTHORIZATION,
+            format!("Bearer {}", created.session_token),
+        )
+        .body(Body::empty())
+        .expect("failed to build outbox request");
+
+    let (status, outbox) = json_response::<OutboxResponsePayload>(
+        app.clone()
+            .oneshot(outbox_request)
+            .await
+            .expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let shutdown = outbox.events.last().expect("missing shutdown notice");
+    assert_eq!(shutdown.event_type, "ShutdownNotice");
+    assert_eq!(
+        shutdown
+            .payload
+            .get("reason")
+            .and_then(|value| value.as_str()),
+        Some("approval_expired")
+    );
+}
+
+#[tokio::test]
+async fn long_poll_returns_as_soon_as_an_event_is_enqueued() {
+    let state = AppState::default();
+    let app = router(state.clone());
+    let account = "acct-long-poll";
+    let auth_key = "long-poll-secret";
+    let created = open_session(&app, account, auth_key).await;
+
+    let poll = tokio::spawn({
+        let app = app.clone();
+        let request = outbox_request(account, created.session_token, "waitMs=10000");
+        async move {
+            let started = Instant::now();
+            let response = app.oneshot(request).await.expect("router error");
+            (
+                started.elapsed(),
+                json_response::<OutboxResponsePayload>(response).await,
+            )
+        }
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    approve_session_via_service_bus(
+        &state,
+        account,
+        created.session_id,
+        created.auth_method,
+        auth_key,
+    )
+    .await;
+
+    let (elapsed, (status, outbox)) = poll.await.expect("poll task panicked");
+    assert_eq!(status, StatusCode::OK);
+    assert!(elapsed < Duration::from_secs(5));
+    assert_eq!(outbox.events.len(), 1);
+    assert_eq!(outbox.events[0].event_type, "InitAck");
+    assert_eq!(outbox.retry_after_ms, 0);
+}
+
+#[tokio::test]
+async fn outbox_poll_reports_cursor_and_acknowledges_through_sequence() {
+    let state = AppState::default();
+    let app = router(state.clone());
+    let account = "acct-cursor";
+    let auth_key = "cursor-secret";
+    let created = open_session(&app, account, auth_key).await;
+    approve_session_via_service_bus(
+        &state,
+        account,
+        created.session_id,
+        created.auth_method,
+        auth_key,
+    )
+    .await;
+
+    let response = app
+        .clone()
+        .oneshot(outbox_request(account, created.session_token, "limit=1"))
+        .await
+        .expect("router error");
+    let retry_after = response
+        .headers()
+        .get(header::RETRY_AFTER)
+        .expect("polls should carry Retry-After")
+        .to_str()
+        .expect("Retry-After should be ASCII")
+        .parse::<u64>()
+        .expect("Retry-After should be in seconds");
+    let (status, outbox) = json_response::<OutboxResponsePayload>(response).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(retry_after, outbox.retry_after_ms.div_ceil(1_000));
+    assert_eq!(outbox.events.len(), 1);
+    assert_eq!(outbox.next_cursor, outbox.events[0].sequence);
+    assert!(!outbox.has_more);
+    assert_eq!(outbox.highest_acknowledged_sequence, 0);
+
+    let query = format!(
+        "cursor={cursor}&ackThrough={cursor}",
+        cursor = outbox.next_cursor
+    );
+    let (status, acked) = json_response::<OutboxResponsePayload>(
+        app.clone()
+            .oneshot(outbox_request(account, created.session_token, &query))
+            .await
+            .expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(acked.events.is_empty());
+    assert_eq!(acked.next_cursor, outbox.next_cursor);
+    assert_eq!(acked.highest_acknowledged_sequence, outbox.next_cursor);
+}
+
+#[tokio::test]
+async fn long_poll_returns_empty_batch_after_wait_elapses() {
+    let app = router(AppState::default());
+    let account = "acct-long-poll-timeout";
+    let created = open_session(&app, account, "long-poll-timeout-secret").await;
+
+    let started = Instant::now();
+    let (status, outbox) = json_response::<OutboxResponsePayload>(
+        app.clone()
+            .oneshot(outbox_request(account, created.session_token, "waitMs=200"))
+            .await
+            .expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    assert!(outbox.pending);
+    assert!(outbox.events.is_empty());
+}
+
+#[tokio::test]
+async fn websocket_stream_pushes_events_and_accepts_frames() {
+    let state = AppState::default();
+    let app = router(state.clone());
+    let account = "acct-stream";
+    let auth_key = "stream-secret";
+    let created = open_session(&app, account, auth_key).await;
+    approve_session_via_service_bus(
+        &state,
+        account,
+        created.session_id,
+        created.auth_method,
+        auth_key,
+    )
+    .await;
+
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("failed to bind listener");
+    let address = listener.local_addr().expect("missing local address");
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.expect("server error");
+    });
+
+    let mut request = format!("ws://{address}/trade-agent/v1/sessions/current/stream")
+        .into_client_request()
+        .expect("failed to build stream request");
+    request.headers_mut().insert(
+        "X-TradeAgent-Account",
+        account.parse().expect("invalid header"),
+    );
+    request.headers_mut().insert(
+        header::AUTHORIZATION,
+        format!("Bearer {}", created.session_token)
+            .parse()
+            .expect("invalid header"),
+    );
+    let (mut socket, _) = connect_async(request).await.expect("failed to open stream");
+
+    let init_ack = next_frame(&mut socket).await;
+    assert_eq!(init_ack["type"], "Event");
+    assert_eq!(init_ack["eventType"], "InitAck");
+
+    socket
+        .send(WsMessage::Text(
+            json!({ "type": "OutboxAck", "eventId": init_ack["id"] }).to_string(),
+        ))
+        .await
+        .expect("failed to send ack");
+    let acked = next_frame(&mut socket).await;
+    assert_eq!(acked["type"], "OutboxAcked");
+    assert_eq!(acked["acknowledgedEventId"], init_ack["id"]);
+    assert_eq!(acked["remainingOutboxDepth"], 0);
+
+    socket
+        .send(WsMessage::Text(
+            json!({
+                "type": "Inbox",
+                "events": [{ "eventType": "StatusHeartbeat", "payload": {} }],
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("failed to send inbox frame");
+    let accepted = next_frame(&mut socket).await;
+    assert_eq!(accepted["type"], "InboxAccepted");
+    assert_eq!(accepted["accepted"], 1);
+
+    socket
+        .send(WsMessage::Text("not json".to_string()))
+        .await
+        .expect("failed to send invalid frame");
+    let error = next_frame(&mut socket).await;
+    assert_eq!(error["type"], "Error");
+    assert_eq!(error["code"], "invalid_frame");
+
+    state
+        .reject_session(
+            account,
+            created.session_id,
+            &fingerprint_for(account, created.auth_method, auth_key),
+            Some("revoked".to_string()),
+            None,
+        )
+        .await
+        .expect("rejection should succeed");
+    let shutdown = next_frame(&mut socket).await;
+    assert_eq!(shutdown["eventType"], "ShutdownNotice");
+    assert!(matches!(
+        socket.next().await,
+        Some(Ok(WsMessage::Close(_))) | None
+    ));
+}
+
+async fn next_frame<S>(socket: &mut S) -> serde_json::Value
+where
+    S: StreamExt<Item = Result<WsMessage, WsError>> + Unpin,
+{
+    loop {
+        let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("timed out waiting for stream frame")
+            .expect("stream closed")
+            .expect("stream error");
+        if let WsMessage::Text(text) = message {
+            return serde_json::from_str(&text).expect("invalid stream frame");
+        }
+    }
+}
+
+#[tokio::test]
+async fn sse_outbox_stream_resumes_from_last_event_id() {
+    let state = AppState::default();
+    let app = router(state.clone());
+    let account = "acct-sse";
+    let auth_key = "sse-secret";
+    let created = open_session(&app, account, auth_key).await;
+    approve_session_via_service_bus(
+        &state,
+        account,
+        created.session_id,
+        created.auth_method,
+        auth_key,
+    )
+    .await;
+
+    let sse_request = |last_event_id: Option<&str>| {
+        let mut builder = Request::builder()
+            .method(http::Method::GET)
+            .uri("/trade-agent/v1/sessions/current/outbox/stream")
+            .header("X-TradeAgent-Account", account)
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", created.session_token),
+            );
+        if let Some(last_event_id) = last_event_id {
+            builder = builder.header("Last-Event-ID", last_event_id);
+        }
+        builder
+            .body(Body::empty())
+            .expect("failed to build sse request")
+    };
+
+    let response = app
+        .clone()
+        .oneshot(sse_request(None))
+        .await
+        .expect("router error");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+    let mut from_start = response.into_body();
+
+    let (id, event) = next_sse_event(&mut from_start)
+        .await
+        .expect("missing event");
+    assert_eq!(id, "1");
+    assert_eq!(event["eventType"], "InitAck");
+    drop(from_start);
+
+    // Tailing leaves the event for the EA's own poll.
+    let (status, outbox) = json_response::<OutboxResponsePayload>(
+        app.clone()
+            .oneshot(outbox_request(account, created.session_token, "cursor=0"))
+            .await
+            .expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(outbox.events.len(), 1);
+    assert_eq!(outbox.events[0].event_type, "InitAck");
+
+    // An event in flight to the EA is still listed when the tail resumes
+    // from before it.
+    let rewound = app
+        .clone()
+        .oneshot(sse_request(Some("0")))
+        .await
+        .expect("router error");
+    let mut rewound = rewound.into_body();
+    let (id, event) = next_sse_event(&mut rewound).await.expect("missing event");
+    assert_eq!(id, "1");
+    assert_eq!(event["eventType"], "InitAck");
+    drop(rewound);
+
+    // The resumed stream only sees what is queued after the InitAck.
+    let resumed = app
+        .clone()
+        .oneshot(sse_request(Some("1")))
+        .await
+        .expect("router error");
+    assert_eq!(resumed.status(), StatusCode::OK);
+    let mut resumed = resumed.into_body();
+
+    state
+        .reject_session(
+            account,
+            created.session_id,
+            &fingerprint_for(account, created.auth_method, auth_key),
+            None,
+            None,
+        )
+        .await
+        .expect("rejection should succeed");
+
+    let (id, event) = next_sse_event(&mut resumed).await.expect("missing event");
+    assert_eq!(id, "2");
+    assert_eq!(event["eventType"], "ShutdownNotice");
+    assert!(next_sse_event(&mut resumed).await.is_none());
+}
+
+#[tokio::test]
+async fn admin_endpoints_are_only_served_by_the_admin_router() {
+    let state = AppState::default();
+    let app = router(state.clone());
+    let admin = admin_router(state);
+    let account = "acct-admin";
+    let created = open_session(&app, account, "admin-secret").await;
+
+    let uri = format!(
+        "/trade-agent/v1/admin/accounts/{account}/sessions/{}/dead-letters",
+        created.session_id
+    );
+    let request = || {
+        Request::builder()
+            .uri(uri.as_str())
+            .body(Body::empty())
+            .expect("failed to build dead-letter request")
+    };
+
+    let response = app.oneshot(request()).await.expect("router error");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = admin.oneshot(request()).await.expect("router error");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+/// Reads the next SSE event from `body`, skipping keep-alive comments.
+async fn next_sse_event(body: &mut Body) -> Option<(String, serde_json::Value)> {
+    let mut buffer = String::new();
+    loop {
+        if let Some(end) = buffer.find("\n\n") {
+            let block: String = buffer.drain(..end + 2).collect();
+            let mut id = None;
+            let mut data = None;
+            for line in block.lines() {
+                if let Some(value) = line.strip_prefix("id:") {
+                    id = Some(value.trim().to_string());
+                } else if let Some(value) = line.strip_prefix("data:") {
+                    data = Some(serde_json::from_str(value.trim()).expect("invalid sse data"));
+                }
+            }
+            if let (Some(id), Some(data)) = (id, data) {
+                return Some((id, data));
+            }
+            continue;
+        }
+
+        let frame = tokio::time::timeout(Duration::from_secs(5), body.frame())
+            .await
+            .expect("timed out waiting for sse event")?
+            .expect("sse body error");
+        if let Ok(chunk) = frame.into_data() {
+            buffer.push_str(std::str::from_utf8(&chunk).expect("sse body is not utf-8"));
+        }
+    }
+}
+
+async fn open_session(app: &Router, account: &str, auth_key: &str) -> SessionCreateResponsePayload {
+    let request = Request::builder()
+        .method(http::Method::POST)
+        .uri("/trade-agent/v1/sessions")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("X-TradeAgent-Account", account)
+        .header("Idempotency-Key", Uuid::new_v4().to_string())
+        .body(Body::from(
+            json!({
+                "authMethod": "account_session_key",
+                "authenticationKey": auth_key,
+            })
+            .to_string(),
+        ))
+        .expect("failed to build create session request");
+
+    let (status, created) = json_response::<SessionCreateResponsePayload>(
+        app.clone().oneshot(request).await.expect("router error"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    created
+}
+
+fn outbox_request(account: &str, session_token: Uuid, query: &str) -> Request<Body> {
+    Request::builder()
+        .method(http::Method::GET)
+        .uri(format!("/trade-agent/v1/sessions/current/outbox?{query}"))
+        .header("X-TradeAgent-Account", account)
+        .header(header::AUTHORIZATION, format!("Bearer {session_token}"))
+        .body(Body::empty())
+        .expect("failed to build outbox request")
+}
+
+fn inbox_request(account: &str, session_token: Uuid, body: serde_json::Value) -> Request<Body> {
+    Request::builder()
+        .method(http::Method::POST)
+        .uri("/trade-agent/v1/sessions/current/inbox")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("X-TradeAgent-Account", account)
+        .header("Idempotency-Key", Uuid::new_v4().to_string())
+        .header(header::AUTHORIZATION, format!("Bearer {session_token}"))
+        .body(Body::from(body.to_string()))
+        .expect("failed to build inbox request")
+}
+
 async fn json_response<T>(response: Response) -> (StatusCode, T)
 where
     T: DeserializeOwned + Debug,
@@ -651,21 +1469,38 @@ struct SessionCreateResponsePayload {
     auth_method: AuthMethod,
     pending: bool,
     previous_session_terminated: Option<Uuid>,
+    outbox_high_water_mark: u64,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct InboxResponsePayload {
     accepted: usize,
+    #[serde(default)]
+    duplicates: usize,
+    #[serde(default)]
+    rejected: Vec<InboxRejectionPayload>,
     pending_session: bool,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InboxRejectionPayload {
+    index: usize,
+    event_type: String,
+    code: String,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct OutboxResponsePayload {
     session_id: Uuid,
     pending: bool,
     events: Vec<OutboxEventPayload>,
+    next_cursor: u64,
+    has_more: bool,
+    highest_acknowledged_sequence: u64,
+    retry_after_ms: u64,
 }
 
 #[derive(Debug, Deserialize)]